@@ -0,0 +1,48 @@
+//! Arbitrary ROMs run for thousands of ticks, checking the machine stays sane
+//!
+//! The first byte picks the quirks and stack depth and seeds the keypad, the
+//! rest is the ROM.
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate chip8_tismith;
+
+use chip8_tismith::cpu::{Cpu, MAX_ROM_SIZE};
+use chip8_tismith::fault::FaultPolicy;
+use chip8_tismith::quirks::Quirks;
+
+const TICKS: usize = 5_000;
+
+fuzz_target!(|data: &[u8]| {
+    let (&control, rom) = match data.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    let quirks = Quirks {
+        shift: control & 0x01 != 0,
+        load_store: control & 0x02 != 0,
+        clip: control & 0x04 != 0,
+        jump: control & 0x08 != 0,
+        vf_order: control & 0x10 != 0,
+        stack_depth: if control & 0x20 != 0 { 12 } else { 16 },
+    };
+    let mut cpu = Cpu::with_quirks(quirks);
+    cpu.set_fault_policy(FaultPolicy::Ignore);
+    if cpu.load_rom(rom).is_err() {
+        assert!(rom.len() > MAX_ROM_SIZE);
+        return;
+    }
+    let mut keys = u16::from(control) * 0x0101;
+    for tick in 0..TICKS {
+        if tick % 100 == 0 {
+            keys = keys.rotate_left(3) ^ tick as u16;
+            cpu.set_keys(keys);
+            cpu.tick_timers();
+        }
+        cpu.tick();
+        assert!(cpu.pc() < 0x1000);
+        assert!(cpu.i() < 0x1000);
+        assert!(cpu.stack().len() <= usize::from(cpu.quirks().stack_depth));
+        assert!(cpu.stack().iter().all(|&address| address < 0x1000));
+    }
+});
@@ -0,0 +1,20 @@
+//! Arbitrary bytes through the decoder, disassembler and control flow graph
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate chip8_tismith;
+
+use chip8_tismith::cfg::ControlFlowGraph;
+use chip8_tismith::disasm::{self, Syntax};
+use chip8_tismith::instruction::Instruction;
+
+fuzz_target!(|rom: &[u8]| {
+    for pair in rom.chunks(2).filter(|pair| pair.len() == 2) {
+        let instruction = Instruction::decode(u16::from(pair[0]) << 8 | u16::from(pair[1]));
+        assert_eq!(Instruction::decode(instruction.encode()), instruction);
+        let _ = instruction.to_string();
+    }
+    disasm::disassemble(rom, Syntax::Plain);
+    disasm::disassemble(rom, Syntax::Octo);
+    ControlFlowGraph::new(rom).to_dot();
+});
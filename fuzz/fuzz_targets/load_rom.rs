@@ -0,0 +1,29 @@
+//! Arbitrary bytes as a ROM file, a save state and a movie
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate chip8_tismith;
+
+use chip8_tismith::cpu::{Cpu, MAX_ROM_SIZE};
+use chip8_tismith::movie::{self, Movie};
+use chip8_tismith::rom;
+use std::str;
+
+fuzz_target!(|data: &[u8]| {
+    let mut cpu = Cpu::new();
+    assert_eq!(cpu.load_rom(data).is_ok(), data.len() <= MAX_ROM_SIZE);
+    for name in &["game", "game.ch8", "game.hex", "game.gz", "game.zip"] {
+        if let Ok(rom) = rom::decode(data, name) {
+            assert!(!rom.is_empty() && rom.len() <= MAX_ROM_SIZE);
+        }
+    }
+
+    if let Ok((cpu, _)) = movie::load_state(data) {
+        assert!(cpu.pc() < 0x1000 && cpu.i() < 0x1000);
+    }
+    if let Ok(text) = str::from_utf8(data) {
+        if let Ok(movie) = Movie::parse(text) {
+            let _ = movie.start(data);
+        }
+    }
+});
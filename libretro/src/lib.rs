@@ -0,0 +1,631 @@
+//! A libretro core, so RetroArch and other libretro frontends can run
+//! CHIP-8 ROMs with their own shaders, netplay and controller handling
+//!
+//! Each `retro_run` is one 60Hz frame: the joypad is read into the keypad,
+//! the ROM runs for its tickrate, the timers tick and the 64x32 screen goes
+//! to the video callback as XRGB8888. While the sound timer runs a square
+//! wave goes to the audio callback, and silence otherwise. Quirks, speed and
+//! colours come from the ROM database and the user's `config.toml`, as they
+//! do for `chip8 run`. There's no high resolution mode to report, so the
+//! geometry is always 64x32.
+//!
+//! Save states are the ones `chip8 run --save-state` writes, after their
+//! length and padded to `SERIALIZE_SIZE`, since frontends expect the size
+//! not to change for rewind and netplay.
+
+extern crate chip8_tismith;
+#[macro_use]
+extern crate log;
+
+use chip8_tismith::failure::{self, bail, format_err};
+use chip8_tismith::{config, cpu, movie, octo, rom, romdb};
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::os::raw::{c_char, c_uint, c_void};
+use std::path::Path;
+use std::ptr;
+use std::slice;
+use std::sync::{Mutex, MutexGuard};
+
+pub const RETRO_API_VERSION: c_uint = 1;
+pub const RETRO_DEVICE_JOYPAD: c_uint = 1;
+pub const RETRO_REGION_NTSC: c_uint = 0;
+pub const RETRO_MEMORY_SYSTEM_RAM: c_uint = 2;
+pub const RETRO_ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+pub const RETRO_ENVIRONMENT_GET_LOG_INTERFACE: c_uint = 27;
+pub const RETRO_PIXEL_FORMAT_XRGB8888: c_uint = 1;
+pub const RETRO_LOG_ERROR: c_uint = 3;
+
+pub const FPS: f64 = 60.0;
+pub const SAMPLE_RATE: u32 = 44_100;
+///stereo frames of audio to each video frame
+pub const SAMPLES_PER_FRAME: usize = 735;
+///the beep's pitch, in Hz
+const TONE: u32 = 440;
+const VOLUME: i16 = 0x1000;
+///fixed, so that netplay peers draw the same random numbers
+const SEED: u64 = 0xC8;
+///what `retro_serialize` writes: the save state's length, the save state
+///and zeros
+pub const SERIALIZE_SIZE: usize = 8192;
+
+///The keypad key for each joypad button, by `RETRO_DEVICE_ID_JOYPAD_*`: B,
+///Y, Select, Start, Up, Down, Left, Right, A, X, L, R, L2, R2, L3 and R3.
+///The d-pad is 2, 8, 4 and 6 and A is 5, as most ROMs expect.
+pub const JOYPAD_KEYS: [u8; 16] = [
+    0x0, 0x3, 0xE, 0xF, 0x2, 0x8, 0x4, 0x6, 0x5, 0x1, 0x7, 0x9, 0xA, 0xB, 0xC, 0xD,
+];
+
+#[repr(C)]
+pub struct SystemInfo {
+    pub library_name: *const c_char,
+    pub library_version: *const c_char,
+    ///`|` separated, without dots
+    pub valid_extensions: *const c_char,
+    pub need_fullpath: bool,
+    pub block_extract: bool,
+}
+
+#[repr(C)]
+pub struct GameGeometry {
+    pub base_width: c_uint,
+    pub base_height: c_uint,
+    pub max_width: c_uint,
+    pub max_height: c_uint,
+    pub aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct SystemTiming {
+    pub fps: f64,
+    pub sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct SystemAvInfo {
+    pub geometry: GameGeometry,
+    pub timing: SystemTiming,
+}
+
+#[repr(C)]
+pub struct GameInfo {
+    pub path: *const c_char,
+    pub data: *const c_void,
+    pub size: usize,
+    pub meta: *const c_char,
+}
+
+pub type EnvironmentFn = extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+pub type VideoRefreshFn =
+    extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+pub type AudioSampleFn = extern "C" fn(left: i16, right: i16);
+pub type AudioSampleBatchFn = extern "C" fn(data: *const i16, frames: usize) -> usize;
+pub type InputPollFn = extern "C" fn();
+pub type InputStateFn =
+    extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+pub type LogPrintfFn = unsafe extern "C" fn(level: c_uint, fmt: *const c_char, ...);
+
+///What `RETRO_ENVIRONMENT_GET_LOG_INTERFACE` fills in
+#[repr(C)]
+pub struct LogCallback {
+    pub log: Option<LogPrintfFn>,
+}
+
+///The callbacks the frontend has given us
+#[derive(Clone, Copy)]
+struct Frontend {
+    environment: Option<EnvironmentFn>,
+    video_refresh: Option<VideoRefreshFn>,
+    audio_sample: Option<AudioSampleFn>,
+    audio_sample_batch: Option<AudioSampleBatchFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>,
+    log: Option<LogPrintfFn>,
+}
+
+static FRONTEND: Mutex<Frontend> = Mutex::new(Frontend {
+    environment: None,
+    video_refresh: None,
+    audio_sample: None,
+    audio_sample_batch: None,
+    input_poll: None,
+    input_state: None,
+    log: None,
+});
+static CORE: Mutex<Loaded> = Mutex::new(Loaded(None));
+
+///The core, once a game is loaded
+struct Loaded(Option<Core>);
+
+//a `Cpu`'s `0NNN` routines needn't be `Send`, but the built in ones hold
+//nothing, and the mutex is only there for frontends that call in from more
+//than one thread
+unsafe impl Send for Loaded {}
+
+///tell the user what went wrong, in the frontend's log if it has one and
+///through the `log` crate if not
+fn report(message: &str) {
+    let log = lock(&FRONTEND).log;
+    match (log, CString::new(message)) {
+        (Some(log), Ok(message)) => unsafe {
+            log(
+                RETRO_LOG_ERROR,
+                b"chip8: %s\n\0".as_ptr() as *const c_char,
+                message.as_ptr(),
+            )
+        },
+        _ => error!("{}", message),
+    }
+}
+
+///a lock that a panic while it was held doesn't spoil, panics can't cross
+///into the frontend anyway
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+///A loaded ROM and the machine running it
+pub struct Core {
+    rom: Vec<u8>,
+    settings: config::Effective,
+    cpu: cpu::Cpu,
+    frame: u32,
+    ///samples into the current second, for the square wave
+    phase: u32,
+}
+
+impl Core {
+    ///a machine running `rom`, with the settings for the ROM at `path`
+    pub fn new(rom: Vec<u8>, path: Option<&Path>) -> Result<Self, failure::Error> {
+        let user = config::Config::load_user()?;
+        let database = romdb::Database::with_user_entries()?;
+        //the same layers as chip8 run, short of options files and flags
+        let mut settings = config::Effective::default();
+        settings.apply(&user.defaults, "config [defaults]");
+        if let Some(entry) = database.lookup(&rom) {
+            let profile = config::Profile {
+                quirks: entry.quirks,
+                tickrate: entry.tickrate,
+                ..Default::default()
+            };
+            settings.apply(&profile, "the ROM database");
+        }
+        if let Some((section, profile)) = path.and_then(|path| user.rom(path, &rom)) {
+            settings.apply(&profile, &format!("config [{}]", section));
+        }
+        let mut core = Core {
+            cpu: cpu::Cpu::with_quirks(settings.quirks),
+            rom,
+            settings,
+            frame: 0,
+            phase: 0,
+        };
+        core.reset()?;
+        Ok(core)
+    }
+
+    ///start the ROM again
+    pub fn reset(&mut self) -> Result<(), failure::Error> {
+        self.cpu = cpu::Cpu::with_quirks(self.settings.quirks);
+        self.cpu.seed(SEED);
+        self.cpu.load_rom(&self.rom)?;
+        self.frame = 0;
+        Ok(())
+    }
+
+    ///run a frame with `keys` down, true if the sound timer was running
+    pub fn run(&mut self, keys: u16) -> bool {
+        self.cpu.set_keys(keys);
+        for _ in 0..self.settings.tickrate {
+            if self.cpu.fault().is_some() || self.cpu.exit_code().is_some() {
+                break;
+            }
+            self.cpu.tick();
+        }
+        let sounding = self.cpu.sound() > 0;
+        self.cpu.tick_timers();
+        self.frame += 1;
+        sounding
+    }
+
+    ///the screen as XRGB8888 pixels, a row at a time
+    pub fn video(&self) -> Vec<u32> {
+        let xrgb = |rgb: chip8_tismith::options::Rgb| {
+            u32::from(rgb.0) << 16 | u32::from(rgb.1) << 8 | u32::from(rgb.2)
+        };
+        let fill = xrgb(self.settings.palette.fill);
+        let background = xrgb(self.settings.palette.background);
+        self.cpu
+            .screen()
+            .iter()
+            .map(|&lit| if lit { fill } else { background })
+            .collect()
+    }
+
+    ///a frame's worth of interleaved stereo samples, a square wave if
+    ///`sounding` and silence if not
+    pub fn audio(&mut self, sounding: bool) -> Vec<i16> {
+        let mut samples = Vec::with_capacity(2 * SAMPLES_PER_FRAME);
+        for _ in 0..SAMPLES_PER_FRAME {
+            let sample = if !sounding {
+                0
+            } else if (self.phase * 2 * TONE / SAMPLE_RATE).is_multiple_of(2) {
+                VOLUME
+            } else {
+                -VOLUME
+            };
+            samples.push(sample);
+            samples.push(sample);
+            self.phase = (self.phase + 1) % SAMPLE_RATE;
+        }
+        samples
+    }
+
+    ///write a save state to `out`, false if it doesn't fit
+    pub fn serialize(&self, out: &mut [u8]) -> bool {
+        let state = movie::save_state(&self.cpu, self.frame);
+        if 4 + state.len() > out.len() {
+            return false;
+        }
+        out[..4].copy_from_slice(&(state.len() as u32).to_be_bytes());
+        out[4..4 + state.len()].copy_from_slice(&state);
+        for byte in &mut out[4 + state.len()..] {
+            *byte = 0;
+        }
+        true
+    }
+
+    ///carry on from what `serialize` wrote
+    pub fn unserialize(&mut self, data: &[u8]) -> Result<(), failure::Error> {
+        if data.len() < 4 {
+            bail!("save state is truncated");
+        }
+        let length = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+        let state = match data[4..].get(..length) {
+            Some(state) => state,
+            None => bail!("save state is truncated"),
+        };
+        let (cpu, frame) = movie::load_state(state)?;
+        self.cpu = cpu;
+        self.frame = frame;
+        Ok(())
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    pub fn memory_mut(&mut self) -> &mut [u8; 4096] {
+        self.cpu.memory_mut()
+    }
+}
+
+///the ROM in a game the frontend hands us, compiling `.8o` sources
+fn load(game: &GameInfo) -> Result<(Vec<u8>, Option<String>), failure::Error> {
+    let path = if game.path.is_null() {
+        None
+    } else {
+        Some(
+            unsafe { CStr::from_ptr(game.path) }
+                .to_string_lossy()
+                .into_owned(),
+        )
+    };
+    let name = path.clone().unwrap_or_else(|| "game.ch8".to_string());
+    let bytes = if !game.data.is_null() {
+        unsafe { slice::from_raw_parts(game.data as *const u8, game.size) }.to_vec()
+    } else if let Some(ref path) = path {
+        fs::read(path).map_err(|e| format_err!("{}: {}", path, e))?
+    } else {
+        bail!("the frontend gave neither a ROM nor a path");
+    };
+    let rom = if name.ends_with(".8o") {
+        let source = String::from_utf8_lossy(&bytes);
+        octo::compile(&source, &name)?.rom
+    } else {
+        rom::decode(&bytes, &name)?
+    };
+    Ok((rom, path))
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(callback: Option<EnvironmentFn>) {
+    let mut logging = LogCallback { log: None };
+    if let Some(environment) = callback {
+        if !environment(
+            RETRO_ENVIRONMENT_GET_LOG_INTERFACE,
+            &mut logging as *mut LogCallback as *mut c_void,
+        ) {
+            logging.log = None;
+        }
+    }
+    let mut frontend = lock(&FRONTEND);
+    frontend.environment = callback;
+    frontend.log = logging.log;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(callback: Option<VideoRefreshFn>) {
+    lock(&FRONTEND).video_refresh = callback;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(callback: Option<AudioSampleFn>) {
+    lock(&FRONTEND).audio_sample = callback;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(callback: Option<AudioSampleBatchFn>) {
+    lock(&FRONTEND).audio_sample_batch = callback;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(callback: Option<InputPollFn>) {
+    lock(&FRONTEND).input_poll = callback;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(callback: Option<InputStateFn>) {
+    lock(&FRONTEND).input_state = callback;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    lock(&CORE).0 = None;
+}
+
+///# Safety
+///
+///`info` must point to a `SystemInfo` to fill in
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut SystemInfo) {
+    if info.is_null() {
+        return;
+    }
+    *info = SystemInfo {
+        library_name: b"chip8\0".as_ptr() as *const c_char,
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+        valid_extensions: b"ch8|c8|sc8|xo8|8o|hex|gz\0".as_ptr() as *const c_char,
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+///# Safety
+///
+///`info` must point to a `SystemAvInfo` to fill in
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut SystemAvInfo) {
+    if info.is_null() {
+        return;
+    }
+    let width = cpu::SCREEN_WIDTH as c_uint;
+    let height = cpu::SCREEN_HEIGHT as c_uint;
+    *info = SystemAvInfo {
+        geometry: GameGeometry {
+            base_width: width,
+            base_height: height,
+            max_width: width,
+            max_height: height,
+            aspect_ratio: width as f32 / height as f32,
+        },
+        timing: SystemTiming {
+            fps: FPS,
+            sample_rate: f64::from(SAMPLE_RATE),
+        },
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    if let Some(ref mut core) = lock(&CORE).0 {
+        if let Err(e) = core.reset() {
+            report(&e.to_string());
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    let frontend = *lock(&FRONTEND);
+    if let Some(poll) = frontend.input_poll {
+        poll();
+    }
+    let keys = match frontend.input_state {
+        Some(state) => JOYPAD_KEYS
+            .iter()
+            .enumerate()
+            .filter(|&(id, _)| state(0, RETRO_DEVICE_JOYPAD, 0, id as c_uint) != 0)
+            .fold(0, |keys, (_, &key)| keys | 1 << key),
+        None => 0,
+    };
+    let (video, audio) = match lock(&CORE).0 {
+        Some(ref mut core) => {
+            let sounding = core.run(keys);
+            (core.video(), core.audio(sounding))
+        }
+        None => return,
+    };
+    if let Some(refresh) = frontend.video_refresh {
+        refresh(
+            video.as_ptr() as *const c_void,
+            cpu::SCREEN_WIDTH as c_uint,
+            cpu::SCREEN_HEIGHT as c_uint,
+            cpu::SCREEN_WIDTH * 4,
+        );
+    }
+    if let Some(batch) = frontend.audio_sample_batch {
+        let mut sent = 0;
+        while sent < SAMPLES_PER_FRAME {
+            let taken = batch(audio[2 * sent..].as_ptr(), SAMPLES_PER_FRAME - sent);
+            if taken == 0 {
+                break;
+            }
+            sent += taken;
+        }
+    } else if let Some(sample) = frontend.audio_sample {
+        for pair in audio.chunks(2) {
+            sample(pair[0], pair[1]);
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    SERIALIZE_SIZE
+}
+
+///# Safety
+///
+///`data` must point to `size` writable bytes
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    match lock(&CORE).0 {
+        Some(ref core) if !data.is_null() => {
+            core.serialize(slice::from_raw_parts_mut(data as *mut u8, size))
+        }
+        _ => false,
+    }
+}
+
+///# Safety
+///
+///`data` must point to `size` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    match lock(&CORE).0 {
+        Some(ref mut core) if !data.is_null() => core
+            .unserialize(slice::from_raw_parts(data as *const u8, size))
+            .is_ok(),
+        _ => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+///# Safety
+///
+///`game` must point to a `GameInfo` whose `path`, if not null, is a C
+///string and whose `data`, if not null, holds `size` bytes
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const GameInfo) -> bool {
+    if game.is_null() {
+        return false;
+    }
+    let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+    let environment = lock(&FRONTEND).environment;
+    match environment {
+        Some(environment)
+            if environment(
+                RETRO_ENVIRONMENT_SET_PIXEL_FORMAT,
+                &mut format as *mut c_uint as *mut c_void,
+            ) => {}
+        _ => {
+            report("the frontend can't take XRGB8888 video");
+            return false;
+        }
+    }
+    let core = load(&*game).and_then(|(rom, path)| Core::new(rom, path.as_ref().map(Path::new)));
+    match core {
+        Ok(core) => {
+            lock(&CORE).0 = Some(core);
+            true
+        }
+        Err(e) => {
+            report(&e.to_string());
+            false
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(
+    _game_type: c_uint,
+    _info: *const GameInfo,
+    _num_info: usize,
+) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    lock(&CORE).0 = None;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    RETRO_REGION_NTSC
+}
+
+///the machine's 4K of memory, for cheats and achievements
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    match lock(&CORE).0 {
+        Some(ref mut core) if id == RETRO_MEMORY_SYSTEM_RAM => {
+            core.memory_mut().as_mut_ptr() as *mut c_void
+        }
+        _ => ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    match lock(&CORE).0 {
+        Some(ref mut core) if id == RETRO_MEMORY_SYSTEM_RAM => core.memory_mut().len(),
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_joypad_keys() {
+        let mut keys = JOYPAD_KEYS.to_vec();
+        keys.sort();
+        assert_eq!(keys, (0..16).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn test_serialize() {
+        let mut core = Core::new(vec![0xC0, 0xFF, 0x12, 0x00], None).unwrap();
+        core.run(0);
+        let mut state = vec![0xAA; SERIALIZE_SIZE];
+        assert!(core.serialize(&mut state));
+        assert_eq!(state[SERIALIZE_SIZE - 1], 0);
+        core.run(0);
+        core.unserialize(&state).unwrap();
+        assert_eq!(core.frame(), 1);
+        assert!(!core.serialize(&mut [0; 16]));
+        assert!(core.unserialize(&state[..100]).is_err());
+    }
+
+    #[test]
+    fn test_audio() {
+        let mut core = Core::new(vec![0x12, 0x00], None).unwrap();
+        let silence = core.audio(false);
+        assert_eq!(silence.len(), 2 * SAMPLES_PER_FRAME);
+        assert!(silence.iter().all(|&sample| sample == 0));
+        let beep = core.audio(true);
+        assert_eq!(beep[0], beep[1]);
+        //a 440Hz square wave changes sign every 50 samples or so
+        let changes = beep.windows(4).step_by(2).filter(|w| w[0] != w[2]).count();
+        assert!(changes > 10 && changes < 20, "{}", changes);
+    }
+}
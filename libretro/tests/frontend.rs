@@ -0,0 +1,176 @@
+//! A stand-in libretro frontend driving the core through its C API
+//!
+//! The core keeps its state in statics, as libretro cores do, so everything
+//! runs in the one test, in the order a frontend would call it.
+
+extern crate chip8_libretro;
+extern crate log;
+
+use chip8_libretro::*;
+use std::env;
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::os::raw::{c_uint, c_void};
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+const WHITE: u32 = 0x00FF_FFFF;
+
+static VIDEO: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+static AUDIO: Mutex<Vec<i16>> = Mutex::new(Vec::new());
+static POLLS: AtomicUsize = AtomicUsize::new(0);
+///the joypad buttons held, a bit for each id
+static PRESSED: AtomicUsize = AtomicUsize::new(0);
+static LOGGED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+///where the core's errors end up, as this frontend has no log interface
+struct Logger;
+
+impl log::Log for Logger {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        LOGGED.lock().unwrap().push(record.args().to_string());
+    }
+
+    fn flush(&self) {}
+}
+
+extern "C" fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    //no log interface, so the core falls back to the log crate
+    cmd == RETRO_ENVIRONMENT_SET_PIXEL_FORMAT
+        && unsafe { *(data as *const c_uint) } == RETRO_PIXEL_FORMAT_XRGB8888
+}
+
+extern "C" fn video_refresh(data: *const c_void, width: c_uint, height: c_uint, pitch: usize) {
+    assert_eq!((width, height, pitch), (64, 32, 64 * 4));
+    let pixels = unsafe { slice::from_raw_parts(data as *const u32, 64 * 32) };
+    *VIDEO.lock().unwrap() = pixels.to_vec();
+}
+
+extern "C" fn audio_sample(_left: i16, _right: i16) {
+    panic!("the core should send audio in batches");
+}
+
+extern "C" fn audio_sample_batch(data: *const i16, frames: usize) -> usize {
+    let samples = unsafe { slice::from_raw_parts(data, 2 * frames) };
+    AUDIO.lock().unwrap().extend_from_slice(samples);
+    frames
+}
+
+extern "C" fn input_poll() {
+    POLLS.fetch_add(1, Ordering::SeqCst);
+}
+
+extern "C" fn input_state(port: c_uint, device: c_uint, _index: c_uint, id: c_uint) -> i16 {
+    let held = port == 0 && device == RETRO_DEVICE_JOYPAD;
+    (held && PRESSED.load(Ordering::SeqCst) & 1 << id != 0) as i16
+}
+
+///run a frame, returning the picture and the audio it made
+fn frame() -> (Vec<u32>, Vec<i16>) {
+    AUDIO.lock().unwrap().clear();
+    retro_run();
+    let video = VIDEO.lock().unwrap().clone();
+    let audio = AUDIO.lock().unwrap().clone();
+    assert_eq!(audio.len(), 2 * SAMPLES_PER_FRAME);
+    (video, audio)
+}
+
+fn load(path: &str, data: Option<&[u8]>) -> bool {
+    let path = CString::new(path).unwrap();
+    let game = GameInfo {
+        path: path.as_ptr(),
+        data: data.map_or(ptr::null(), |data| data.as_ptr() as *const c_void),
+        size: data.map_or(0, |data| data.len()),
+        meta: ptr::null(),
+    };
+    unsafe { retro_load_game(&game) }
+}
+
+#[test]
+fn test_frontend() {
+    //no user config to change what the ROMs do
+    let config = env::temp_dir().join("chip8-libretro-config");
+    fs::create_dir_all(&config).unwrap();
+    env::set_var("XDG_CONFIG_HOME", &config);
+    log::set_logger(&Logger).unwrap();
+    log::set_max_level(log::LevelFilter::Error);
+
+    assert_eq!(retro_api_version(), RETRO_API_VERSION);
+    retro_set_environment(Some(environment));
+    retro_set_video_refresh(Some(video_refresh));
+    retro_set_audio_sample(Some(audio_sample));
+    retro_set_audio_sample_batch(Some(audio_sample_batch));
+    retro_set_input_poll(Some(input_poll));
+    retro_set_input_state(Some(input_state));
+    retro_init();
+
+    let mut info: SystemInfo = unsafe { std::mem::zeroed() };
+    unsafe { retro_get_system_info(&mut info) };
+    let name = unsafe { CStr::from_ptr(info.library_name) };
+    let extensions = unsafe { CStr::from_ptr(info.valid_extensions) };
+    assert_eq!(name.to_str().unwrap(), "chip8");
+    assert!(extensions.to_str().unwrap().split('|').any(|e| e == "ch8"));
+
+    //nothing loaded, so nothing to run or save
+    retro_run();
+    assert!(VIDEO.lock().unwrap().is_empty());
+    let mut state = vec![0u8; retro_serialize_size()];
+    assert!(!unsafe { retro_serialize(state.as_mut_ptr() as *mut c_void, state.len()) });
+
+    //a broken config.toml stops the game loading, as it does chip8 run
+    let maze = concat!(env!("CARGO_MANIFEST_DIR"), "/../roms/MAZE");
+    fs::create_dir_all(config.join("chip8")).unwrap();
+    fs::write(config.join("chip8/config.toml"), "scale = 0").unwrap();
+    assert!(!load(maze, None));
+    let logged = LOGGED.lock().unwrap().join("\n");
+    assert!(logged.contains("config.toml"), "{}", logged);
+    fs::remove_file(config.join("chip8/config.toml")).unwrap();
+
+    assert!(load(maze, None));
+    let mut av: SystemAvInfo = unsafe { std::mem::zeroed() };
+    unsafe { retro_get_system_av_info(&mut av) };
+    assert_eq!((av.geometry.base_width, av.geometry.base_height), (64, 32));
+    assert_eq!(av.timing.fps, FPS);
+    assert_eq!(av.timing.sample_rate, f64::from(SAMPLE_RATE));
+
+    let polls = POLLS.load(Ordering::SeqCst);
+    for _ in 0..9 {
+        frame();
+    }
+    let (video, audio) = frame();
+    assert_eq!(POLLS.load(Ordering::SeqCst), polls + 10);
+    assert!(video.contains(&WHITE));
+    assert!(audio.iter().all(|&sample| sample == 0));
+
+    //a save state puts it back exactly where it was
+    assert!(unsafe { retro_serialize(state.as_mut_ptr() as *mut c_void, state.len()) });
+    let after: Vec<_> = (0..30).map(|_| frame().0).collect();
+    assert!(unsafe { retro_unserialize(state.as_ptr() as *const c_void, state.len()) });
+    let again: Vec<_> = (0..30).map(|_| frame().0).collect();
+    assert_eq!(after, again);
+    let garbage = [0xFFu8; 64];
+    assert!(!unsafe { retro_unserialize(garbage.as_ptr() as *const c_void, garbage.len()) });
+    retro_unload_game();
+
+    //wait for a key, then beep for as long as its number says
+    let rom = [0xF0, 0x0A, 0xF0, 0x18, 0x12, 0x04];
+    assert!(load("beep.ch8", Some(&rom)));
+    let (_, audio) = frame();
+    assert!(audio.iter().all(|&sample| sample == 0));
+    //A is the keypad's 5
+    PRESSED.store(1 << 8, Ordering::SeqCst);
+    frame();
+    PRESSED.store(0, Ordering::SeqCst);
+    let (_, audio) = frame();
+    assert!(audio.iter().any(|&sample| sample != 0));
+    assert_eq!(JOYPAD_KEYS[8], 5);
+
+    retro_unload_game();
+    retro_deinit();
+}
@@ -0,0 +1,950 @@
+//! Assembler for the mnemonic syntax used in the `cpu.rs` doc comments
+//!
+//! ```text
+//! ; comments run to the end of the line
+//! SPEED   equ 2
+//! start:  mvi ball
+//!         mov v0,SPEED * 4
+//!         sprite v0,v1,ball_end - ball
+//!         jmp start
+//! ball:   db 0b11000000, 0b11000000
+//! ball_end:
+//!         include "more.s"
+//! ```
+//!
+//! Constants are declared with `equ` (or `=`), data with `db`/`dw`, and
+//! `org` moves the output address forwards. Expressions support the usual C
+//! operators. Everything is assembled to load at 0x200.
+
+use disasm::ROM_START;
+use failure::Fail;
+use instruction::Instruction;
+use std;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use symbols::{SourceLocation, SymbolMap};
+
+///Maximum nesting of `include` directives
+const MAX_INCLUDE_DEPTH: usize = 16;
+///Maximum nesting of constants defined in terms of other constants
+const MAX_CONSTANT_DEPTH: usize = 64;
+
+///An assembly error, located in the source
+#[derive(Debug, PartialEq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file, self.line, self.column, self.message
+        )
+    }
+}
+
+impl Fail for AsmError {}
+
+///The output of a successful assembly
+#[derive(Debug)]
+pub struct Assembly {
+    ///bytes to hand to `Cpu::load_rom`
+    pub rom: Vec<u8>,
+    pub symbols: SymbolMap,
+}
+
+///assemble the file at `path`, resolving includes relative to it
+pub fn assemble_file(path: &Path) -> Result<Assembly, AsmError> {
+    let name = path.to_string_lossy().into_owned();
+    let source = fs::read_to_string(path).map_err(|e| AsmError {
+        file: name.clone(),
+        line: 0,
+        column: 0,
+        message: e.to_string(),
+    })?;
+    assemble(&source, &name)
+}
+
+///assemble `source`, naming it `name` in errors and the symbol map.
+///Includes are resolved relative to the directory of `name`
+pub fn assemble(source: &str, name: &str) -> Result<Assembly, AsmError> {
+    let mut assembler = Assembler {
+        address: ROM_START,
+        ..Default::default()
+    };
+    assembler.files.push(name.into());
+    assembler.pass1(source, 0, 0)?;
+    assembler.pass2()
+}
+
+///A position in the source, `file` indexes `Assembler::files`
+#[derive(Debug, Clone, Copy)]
+struct Pos {
+    file: usize,
+    line: usize,
+    column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Ident(String),
+    Num(i64),
+    Str(Vec<u8>),
+    Op(&'static str),
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    tok: Tok,
+    column: usize,
+}
+
+///A line that produces output, kept for the second pass once every
+///label is known
+struct Item {
+    address: u16,
+    mnemonic: String,
+    operands: Vec<Vec<Token>>,
+    pos: Pos,
+}
+
+#[derive(Default)]
+struct Assembler {
+    files: Vec<String>,
+    address: u16,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, (Vec<Token>, Pos)>,
+    items: Vec<Item>,
+}
+
+const OPERATORS: [&str; 16] = [
+    "<<", ">>", "+", "-", "*", "/", "%", "&", "|", "^", "~", "(", ")", ",", ":", "=",
+];
+
+impl Assembler {
+    fn error<S: Into<String>>(&self, pos: Pos, message: S) -> AsmError {
+        AsmError {
+            file: self.files[pos.file].clone(),
+            line: pos.line,
+            column: pos.column,
+            message: message.into(),
+        }
+    }
+
+    fn at(&self, pos: Pos, token: &Token) -> Pos {
+        Pos {
+            column: token.column,
+            ..pos
+        }
+    }
+
+    ///split a line into tokens, dropping any comment
+    fn tokenize(&self, text: &str, pos: Pos) -> Result<Vec<Token>, AsmError> {
+        let chars: Vec<char> = text.chars().collect();
+        let mut tokens = Vec::new();
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            let column = i + 1;
+            let here = Pos { column, ..pos };
+            if c.is_whitespace() {
+                i += 1;
+            } else if c == ';' {
+                break;
+            } else if c.is_ascii_alphabetic() || c == '_' || c == '.' {
+                let start = i;
+                while i < chars.len()
+                    && (chars[i].is_ascii_alphanumeric() || chars[i] == '_' || chars[i] == '.')
+                {
+                    i += 1;
+                }
+                let ident = chars[start..i].iter().collect();
+                tokens.push(Token {
+                    tok: Tok::Ident(ident),
+                    column,
+                });
+            } else if c.is_ascii_digit() || (c == '$' && i + 1 < chars.len()) {
+                let start = i;
+                i += 1;
+                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                let text: String = chars[start..i].iter().filter(|&&c| c != '_').collect();
+                let lower = text.to_lowercase();
+                let parsed = if let Some(hex) = lower.strip_prefix("0x") {
+                    i64::from_str_radix(hex, 16)
+                } else if let Some(binary) = lower.strip_prefix("0b") {
+                    i64::from_str_radix(binary, 2)
+                } else if let Some(hex) = lower.strip_prefix('$') {
+                    i64::from_str_radix(hex, 16)
+                } else {
+                    lower.parse()
+                };
+                let value =
+                    parsed.map_err(|_| self.error(here, format!("invalid number '{}'", text)))?;
+                tokens.push(Token {
+                    tok: Tok::Num(value),
+                    column,
+                });
+            } else if c == '\'' {
+                if i + 2 < chars.len() && chars[i + 2] == '\'' && chars[i + 1].is_ascii() {
+                    tokens.push(Token {
+                        tok: Tok::Num(chars[i + 1] as i64),
+                        column,
+                    });
+                    i += 3;
+                } else {
+                    return Err(self.error(here, "invalid character literal"));
+                }
+            } else if c == '"' {
+                let mut bytes = Vec::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None => return Err(self.error(here, "unterminated string")),
+                        Some('"') => break,
+                        Some('\\') => {
+                            let escaped = match chars.get(i + 1) {
+                                Some('n') => '\n',
+                                Some('t') => '\t',
+                                Some('0') => '\0',
+                                Some(&c) => c,
+                                None => return Err(self.error(here, "unterminated string")),
+                            };
+                            bytes.push(escaped as u8);
+                            i += 2;
+                        }
+                        Some(&c) => {
+                            let mut buffer = [0; 4];
+                            bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+                            i += 1;
+                        }
+                    }
+                }
+                i += 1;
+                tokens.push(Token {
+                    tok: Tok::Str(bytes),
+                    column,
+                });
+            } else {
+                let rest: String = chars[i..].iter().take(2).collect();
+                match OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+                    Some(op) => {
+                        tokens.push(Token {
+                            tok: Tok::Op(op),
+                            column,
+                        });
+                        i += op.len();
+                    }
+                    None => return Err(self.error(here, format!("unexpected character '{}'", c))),
+                }
+            }
+        }
+        Ok(tokens)
+    }
+
+    ///first pass: tokenize, define labels and constants and lay out items
+    fn pass1(&mut self, source: &str, file: usize, depth: usize) -> Result<(), AsmError> {
+        for (number, text) in source.lines().enumerate() {
+            let pos = Pos {
+                file,
+                line: number + 1,
+                column: 1,
+            };
+            let mut tokens = self.tokenize(text, pos)?;
+
+            //label:
+            if tokens.len() >= 2 && tokens[1].tok == Tok::Op(":") {
+                if let Tok::Ident(ref name) = tokens[0].tok {
+                    let label_pos = self.at(pos, &tokens[0]);
+                    self.define(name, label_pos)?;
+                    self.labels.insert(name.clone(), self.address);
+                } else {
+                    return Err(self.error(self.at(pos, &tokens[0]), "expected a label name"));
+                }
+                tokens.drain(..2);
+            }
+            if tokens.is_empty() {
+                continue;
+            }
+
+            let mnemonic = match tokens[0].tok {
+                Tok::Ident(ref name) => name.clone(),
+                _ => return Err(self.error(self.at(pos, &tokens[0]), "expected a mnemonic")),
+            };
+            let pos = self.at(pos, &tokens[0]);
+
+            //name equ expr
+            if tokens.len() >= 2 {
+                let is_constant = match tokens[1].tok {
+                    Tok::Ident(ref word) => word.eq_ignore_ascii_case("equ"),
+                    Tok::Op("=") => true,
+                    _ => false,
+                };
+                if is_constant {
+                    if tokens.len() == 2 {
+                        return Err(self.error(pos, "missing constant value"));
+                    }
+                    self.define(&mnemonic, pos)?;
+                    self.constants.insert(mnemonic, (tokens.split_off(2), pos));
+                    continue;
+                }
+            }
+
+            let operands = self.split_operands(&tokens[1..]);
+            match mnemonic.to_lowercase().as_str() {
+                "include" => {
+                    let path = match operands.as_slice() {
+                        [operand] if operand.len() == 1 => match operand[0].tok {
+                            Tok::Str(ref bytes) => String::from_utf8_lossy(bytes).into_owned(),
+                            _ => return Err(self.error(pos, "include expects a quoted path")),
+                        },
+                        _ => return Err(self.error(pos, "include expects a quoted path")),
+                    };
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        return Err(self.error(pos, "includes nested too deeply"));
+                    }
+                    let path = self.resolve(file, &path);
+                    let source = fs::read_to_string(&path).map_err(|e| {
+                        self.error(pos, format!("cannot include {}: {}", path.display(), e))
+                    })?;
+                    self.files.push(path.to_string_lossy().into_owned());
+                    let included = self.files.len() - 1;
+                    self.pass1(&source, included, depth + 1)?;
+                }
+                "org" => {
+                    let operand = match operands.as_slice() {
+                        [operand] => operand.clone(),
+                        _ => return Err(self.error(pos, "org expects one address")),
+                    };
+                    let target = self.eval(&operand, pos, true)?;
+                    if target < i64::from(self.address) || target > 0xFFF {
+                        return Err(self.error(
+                            pos,
+                            format!("org 0x{:X} is before the current address", target),
+                        ));
+                    }
+                    self.address = target as u16;
+                }
+                lower => {
+                    let size = match lower {
+                        "db" => operands
+                            .iter()
+                            .map(|o| match o.as_slice() {
+                                [Token {
+                                    tok: Tok::Str(ref bytes),
+                                    ..
+                                }] => bytes.len(),
+                                _ => 1,
+                            })
+                            .sum(),
+                        "dw" => 2 * operands.len(),
+                        _ => 2,
+                    };
+                    self.items.push(Item {
+                        address: self.address,
+                        mnemonic: lower.into(),
+                        operands,
+                        pos,
+                    });
+                    let end = usize::from(self.address) + size;
+                    if end > 0x1000 {
+                        return Err(self.error(pos, "program does not fit in memory"));
+                    }
+                    self.address = end as u16;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    ///refuse to define a symbol twice
+    fn define(&self, name: &str, pos: Pos) -> Result<(), AsmError> {
+        if self.labels.contains_key(name) || self.constants.contains_key(name) {
+            return Err(self.error(pos, format!("'{}' is already defined", name)));
+        }
+        if register(name).is_some() {
+            return Err(self.error(pos, format!("'{}' is a register name", name)));
+        }
+        Ok(())
+    }
+
+    fn resolve(&self, file: usize, path: &str) -> PathBuf {
+        let including = Path::new(&self.files[file]);
+        match including.parent() {
+            Some(dir) => dir.join(path),
+            None => PathBuf::from(path),
+        }
+    }
+
+    fn split_operands(&self, tokens: &[Token]) -> Vec<Vec<Token>> {
+        let mut operands = Vec::new();
+        let mut current = Vec::new();
+        let mut depth = 0;
+        for token in tokens {
+            match token.tok {
+                Tok::Op("(") => depth += 1,
+                Tok::Op(")") => depth -= 1,
+                Tok::Op(",") if depth == 0 => {
+                    operands.push(std::mem::take(&mut current));
+                    continue;
+                }
+                _ => {}
+            }
+            current.push(token.clone());
+        }
+        if !current.is_empty() || !operands.is_empty() {
+            operands.push(current);
+        }
+        operands
+    }
+
+    ///second pass: encode every item now that all labels are known
+    fn pass2(&self) -> Result<Assembly, AsmError> {
+        let mut rom = Vec::new();
+        let mut symbols = SymbolMap::new();
+        for (name, address) in &self.labels {
+            symbols.labels.insert(name.clone(), *address);
+        }
+
+        for item in &self.items {
+            let bytes = self.encode(item)?;
+            let offset = usize::from(item.address - ROM_START);
+            if rom.len() < offset + bytes.len() {
+                rom.resize(offset + bytes.len(), 0);
+            }
+            rom[offset..offset + bytes.len()].copy_from_slice(&bytes);
+            if !bytes.is_empty() {
+                symbols.lines.insert(
+                    item.address,
+                    SourceLocation {
+                        file: self.files[item.pos.file].clone(),
+                        line: item.pos.line,
+                    },
+                );
+            }
+        }
+        Ok(Assembly { rom, symbols })
+    }
+
+    fn encode(&self, item: &Item) -> Result<Vec<u8>, AsmError> {
+        let pos = item.pos;
+        let ops = &item.operands;
+        let mnemonic = item.mnemonic.as_str();
+
+        match mnemonic {
+            "db" => {
+                let mut bytes = Vec::new();
+                for operand in ops {
+                    if let [Token {
+                        tok: Tok::Str(ref s),
+                        ..
+                    }] = operand.as_slice()
+                    {
+                        bytes.extend_from_slice(s);
+                    } else {
+                        bytes.push(self.byte(operand, pos)?);
+                    }
+                }
+                return Ok(bytes);
+            }
+            "dw" => {
+                let mut bytes = Vec::new();
+                for operand in ops {
+                    let value = self.eval(operand, pos, false)?;
+                    if !(-0x8000..=0xFFFF).contains(&value) {
+                        return Err(self.error(self.first(operand, pos), "word out of range"));
+                    }
+                    bytes.push((value >> 8) as u8);
+                    bytes.push(value as u8);
+                }
+                return Ok(bytes);
+            }
+            _ => {}
+        }
+
+        let expect = |count: usize| -> Result<(), AsmError> {
+            if ops.len() == count {
+                Ok(())
+            } else {
+                Err(self.error(
+                    pos,
+                    format!(
+                        "{} expects {} operand(s), found {}",
+                        mnemonic,
+                        count,
+                        ops.len()
+                    ),
+                ))
+            }
+        };
+
+        let instruction = match mnemonic {
+            "cls" | "rts" => {
+                expect(0)?;
+                if mnemonic == "cls" {
+                    Instruction::Cls
+                } else {
+                    Instruction::Rts
+                }
+            }
+            "sys" | "jmp" | "jsr" | "mvi" | "jmi" => {
+                expect(1)?;
+                let address = self.address(&ops[0], pos)?;
+                match mnemonic {
+                    "sys" => Instruction::Sys(address),
+                    "jmp" => Instruction::Jmp(address),
+                    "jsr" => Instruction::Jsr(address),
+                    "mvi" => Instruction::Mvi(address),
+                    _ => Instruction::Jmi(address),
+                }
+            }
+            "skeq" | "skne" | "mov" | "add" => {
+                expect(2)?;
+                let x = self.reg(&ops[0], pos)?;
+                match as_register(&ops[1]) {
+                    Some(y) => match mnemonic {
+                        "skeq" => Instruction::SkeqReg(x, y),
+                        "skne" => Instruction::SkneReg(x, y),
+                        "mov" => Instruction::MovReg(x, y),
+                        _ => Instruction::AddReg(x, y),
+                    },
+                    None => {
+                        let value = self.byte(&ops[1], pos)?;
+                        match mnemonic {
+                            "skeq" => Instruction::SkeqConst(x, value),
+                            "skne" => Instruction::SkneConst(x, value),
+                            "mov" => Instruction::MovConst(x, value),
+                            _ => Instruction::AddConst(x, value),
+                        }
+                    }
+                }
+            }
+            "or" | "and" | "xor" | "sub" | "rsb" => {
+                expect(2)?;
+                let x = self.reg(&ops[0], pos)?;
+                let y = self.reg(&ops[1], pos)?;
+                match mnemonic {
+                    "or" => Instruction::OrReg(x, y),
+                    "and" => Instruction::AndReg(x, y),
+                    "xor" => Instruction::XorReg(x, y),
+                    "sub" => Instruction::SubReg(x, y),
+                    _ => Instruction::Rsb(x, y),
+                }
+            }
+            "shr" | "shl" => {
+                if ops.len() != 1 {
+                    expect(2)?;
+                }
+                let x = self.reg(&ops[0], pos)?;
+                let y = match ops.get(1) {
+                    Some(operand) => self.reg(operand, pos)?,
+                    None => 0,
+                };
+                if mnemonic == "shr" {
+                    Instruction::Shr(x, y)
+                } else {
+                    Instruction::Shl(x, y)
+                }
+            }
+            "rand" => {
+                expect(2)?;
+                Instruction::Rand(self.reg(&ops[0], pos)?, self.byte(&ops[1], pos)?)
+            }
+            "sprite" => {
+                expect(3)?;
+                let x = self.reg(&ops[0], pos)?;
+                let y = self.reg(&ops[1], pos)?;
+                let n = self.eval(&ops[2], pos, false)?;
+                if !(0..=15).contains(&n) {
+                    return Err(self.error(
+                        self.first(&ops[2], pos),
+                        format!("sprite height {} out of range 0-15", n),
+                    ));
+                }
+                Instruction::Sprite(x, y, n as u8)
+            }
+            "skpr" | "skup" | "gdelay" | "key" | "sdelay" | "ssound" | "adi" | "font" | "bcd" => {
+                expect(1)?;
+                let x = self.reg(&ops[0], pos)?;
+                match mnemonic {
+                    "skpr" => Instruction::Skpr(x),
+                    "skup" => Instruction::Skup(x),
+                    "gdelay" => Instruction::Gdelay(x),
+                    "key" => Instruction::Key(x),
+                    "sdelay" => Instruction::Sdelay(x),
+                    "ssound" => Instruction::Ssound(x),
+                    "adi" => Instruction::Adi(x),
+                    "font" => Instruction::Font(x),
+                    _ => Instruction::Bcd(x),
+                }
+            }
+            "str" | "ldr" => {
+                expect(1)?;
+                let x = self.register_range(&ops[0], pos)?;
+                if mnemonic == "str" {
+                    Instruction::Str(x)
+                } else {
+                    Instruction::Ldr(x)
+                }
+            }
+            _ => return Err(self.error(pos, format!("unknown mnemonic '{}'", mnemonic))),
+        };
+        let opcode = instruction.encode();
+        Ok(vec![(opcode >> 8) as u8, opcode as u8])
+    }
+
+    fn first(&self, tokens: &[Token], pos: Pos) -> Pos {
+        match tokens.first() {
+            Some(token) => self.at(pos, token),
+            None => pos,
+        }
+    }
+
+    fn reg(&self, tokens: &[Token], pos: Pos) -> Result<u8, AsmError> {
+        as_register(tokens)
+            .ok_or_else(|| self.error(self.first(tokens, pos), "expected a register"))
+    }
+
+    ///`v0-vX` or just `vX`
+    fn register_range(&self, tokens: &[Token], pos: Pos) -> Result<u8, AsmError> {
+        if let Some(x) = as_register(tokens) {
+            return Ok(x);
+        }
+        if tokens.len() == 3 && tokens[1].tok == Tok::Op("-") {
+            if as_register(&tokens[..1]) != Some(0) {
+                return Err(self.error(self.first(tokens, pos), "register range must start at v0"));
+            }
+            if let Some(x) = as_register(&tokens[2..]) {
+                return Ok(x);
+            }
+        }
+        Err(self.error(
+            self.first(tokens, pos),
+            "expected a register range like v0-v5",
+        ))
+    }
+
+    fn address(&self, tokens: &[Token], pos: Pos) -> Result<u16, AsmError> {
+        let value = self.eval(tokens, pos, false)?;
+        if !(0..=0xFFF).contains(&value) {
+            return Err(self.error(
+                self.first(tokens, pos),
+                format!("address 0x{:X} out of range", value),
+            ));
+        }
+        Ok(value as u16)
+    }
+
+    fn byte(&self, tokens: &[Token], pos: Pos) -> Result<u8, AsmError> {
+        let value = self.eval(tokens, pos, false)?;
+        if !(-128..=255).contains(&value) {
+            return Err(self.error(
+                self.first(tokens, pos),
+                format!("value {} does not fit in a byte", value),
+            ));
+        }
+        Ok(value as u8)
+    }
+
+    ///evaluate an expression, `early` is set during the first pass when
+    ///labels further down the source aren't known yet
+    fn eval(&self, tokens: &[Token], pos: Pos, early: bool) -> Result<i64, AsmError> {
+        let mut parser = Parser {
+            assembler: self,
+            tokens,
+            next: 0,
+            pos,
+            early,
+            depth: 0,
+        };
+        parser.expression()
+    }
+}
+
+///a register name like `v3` or `VA`
+fn register(name: &str) -> Option<u8> {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('v'), Some(digit), None) | (Some('V'), Some(digit), None) => {
+            digit.to_digit(16).map(|d| d as u8)
+        }
+        _ => None,
+    }
+}
+
+fn as_register(tokens: &[Token]) -> Option<u8> {
+    match tokens {
+        [Token {
+            tok: Tok::Ident(ref name),
+            ..
+        }] => register(name),
+        _ => None,
+    }
+}
+
+///Precedence climbing expression evaluator
+struct Parser<'a> {
+    assembler: &'a Assembler,
+    tokens: &'a [Token],
+    next: usize,
+    pos: Pos,
+    early: bool,
+    depth: usize,
+}
+
+const BINARY_OPERATORS: [&[&str]; 6] = [
+    &["|"],
+    &["^"],
+    &["&"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+impl<'a> Parser<'a> {
+    fn here(&self) -> Pos {
+        match self.tokens.get(self.next).or_else(|| self.tokens.last()) {
+            Some(token) => self.assembler.at(self.pos, token),
+            None => self.pos,
+        }
+    }
+
+    fn error<S: Into<String>>(&self, message: S) -> AsmError {
+        self.assembler.error(self.here(), message)
+    }
+
+    fn expression(&mut self) -> Result<i64, AsmError> {
+        if self.tokens.is_empty() {
+            return Err(self.error("missing operand"));
+        }
+        let value = self.binary(0)?;
+        if self.next < self.tokens.len() {
+            return Err(self.error("unexpected token in expression"));
+        }
+        Ok(value)
+    }
+
+    fn peek_operator(&self, level: usize) -> Option<&'static str> {
+        match self.tokens.get(self.next) {
+            Some(Token {
+                tok: Tok::Op(op), ..
+            }) if BINARY_OPERATORS[level].contains(op) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<i64, AsmError> {
+        if level == BINARY_OPERATORS.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        while let Some(op) = self.peek_operator(level) {
+            let at = self.here();
+            self.next += 1;
+            let right = self.binary(level + 1)?;
+            left = match op {
+                "|" => left | right,
+                "^" => left ^ right,
+                "&" => left & right,
+                "<<" => left.checked_shl(right as u32).unwrap_or(0),
+                ">>" => left.checked_shr(right as u32).unwrap_or(0),
+                "+" => left.wrapping_add(right),
+                "-" => left.wrapping_sub(right),
+                "*" => left.wrapping_mul(right),
+                _ => {
+                    if right == 0 {
+                        return Err(self.assembler.error(at, "division by zero"));
+                    }
+                    if op == "/" {
+                        left.wrapping_div(right)
+                    } else {
+                        left.wrapping_rem(right)
+                    }
+                }
+            };
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<i64, AsmError> {
+        let token = match self.tokens.get(self.next) {
+            Some(token) => token.clone(),
+            None => return Err(self.error("expression ends unexpectedly")),
+        };
+        self.next += 1;
+        match token.tok {
+            Tok::Op("-") => Ok(self.unary()?.wrapping_neg()),
+            Tok::Op("+") => self.unary(),
+            Tok::Op("~") => Ok(!self.unary()?),
+            Tok::Op("(") => {
+                let value = self.binary(0)?;
+                match self.tokens.get(self.next) {
+                    Some(Token {
+                        tok: Tok::Op(")"), ..
+                    }) => {
+                        self.next += 1;
+                        Ok(value)
+                    }
+                    _ => Err(self.error("expected ')'")),
+                }
+            }
+            Tok::Num(value) => Ok(value),
+            Tok::Ident(ref name) => self.symbol(name, self.assembler.at(self.pos, &token)),
+            _ => {
+                self.next -= 1;
+                Err(self.error("expected a value"))
+            }
+        }
+    }
+
+    fn symbol(&mut self, name: &str, at: Pos) -> Result<i64, AsmError> {
+        if let Some(address) = self.assembler.labels.get(name) {
+            return Ok(i64::from(*address));
+        }
+        if let Some(&(ref tokens, pos)) = self.assembler.constants.get(name) {
+            if self.depth >= MAX_CONSTANT_DEPTH {
+                return Err(self
+                    .assembler
+                    .error(at, format!("'{}' is defined recursively", name)));
+            }
+            let mut parser = Parser {
+                assembler: self.assembler,
+                tokens,
+                next: 0,
+                pos,
+                early: self.early,
+                depth: self.depth + 1,
+            };
+            return parser.expression();
+        }
+        if register(name).is_some() {
+            return Err(self
+                .assembler
+                .error(at, "registers can't be used in expressions"));
+        }
+        if self.early {
+            Err(self
+                .assembler
+                .error(at, format!("'{}' must be defined before use here", name)))
+        } else {
+            Err(self
+                .assembler
+                .error(at, format!("undefined symbol '{}'", name)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use disasm;
+    use std::env;
+
+    fn assemble_ok(source: &str) -> Vec<u8> {
+        match assemble(source, "test.s") {
+            Ok(assembly) => assembly.rom,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn test_instructions() {
+        let rom = assemble_ok(
+            "cls\nrts\njmp 0x200\njsr 0x300\nskeq v1,3\nskeq v1,v2\nmov va,0xFF\n\
+             shr v3\nshl v3,v4\nsprite v0,v1,5\nstr v0-v5\nldr v2\nsys 0x123\n",
+        );
+        assert_eq!(
+            rom,
+            vec![
+                0x00, 0xE0, 0x00, 0xEE, 0x12, 0x00, 0x23, 0x00, 0x31, 0x03, 0x51, 0x20, 0x6A, 0xFF,
+                0x83, 0x06, 0x83, 0x4E, 0xD0, 0x15, 0xF5, 0x55, 0xF2, 0x65, 0x01, 0x23,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_labels_and_constants() {
+        let rom = assemble_ok(
+            "HEIGHT equ end - ball\nstart: mvi ball\n  sprite v0,v0,HEIGHT\n  jmp start\n\
+             ball: db 0x80, 0x40\nend:\n",
+        );
+        assert_eq!(rom, vec![0xA2, 0x06, 0xD0, 0x02, 0x12, 0x00, 0x80, 0x40]);
+    }
+
+    #[test]
+    fn test_data_and_expressions() {
+        let rom = assemble_ok("db \"AB\", 'c', -1, (1 << 4) | 2 * 3\ndw 0x1234, $ABC\n");
+        assert_eq!(
+            rom,
+            vec![0x41, 0x42, 0x63, 0xFF, 0x16, 0x12, 0x34, 0x0A, 0xBC]
+        );
+    }
+
+    #[test]
+    fn test_org() {
+        let rom = assemble_ok("cls\norg 0x206\ndb 1\n");
+        assert_eq!(rom, vec![0x00, 0xE0, 0x00, 0x00, 0x00, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn test_symbol_map() {
+        let assembly = assemble("main: cls\n\n  jmp main\n", "game.s").unwrap();
+        assert_eq!(assembly.symbols.labels["main"], 0x200);
+        assert_eq!(assembly.symbols.lines[&0x202].line, 3);
+        assert_eq!(assembly.symbols.lines[&0x202].file, "game.s");
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = env::temp_dir().join(format!("chip8-asm-include-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("sub.s"), "helper: rts\n").unwrap();
+        fs::write(dir.join("main.s"), "jsr helper\ninclude \"sub.s\"\n").unwrap();
+        let assembly = assemble_file(&dir.join("main.s")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(assembly.rom, vec![0x22, 0x02, 0x00, 0xEE]);
+        assert!(assembly.symbols.lines[&0x202].file.ends_with("sub.s"));
+    }
+
+    #[test]
+    fn test_error_locations() {
+        let e = assemble("cls\n  mov v0,300\n", "bad.s").unwrap_err();
+        assert_eq!((e.line, e.column), (2, 10));
+        assert_eq!(
+            e.to_string(),
+            "bad.s:2:10: value 300 does not fit in a byte"
+        );
+
+        let e = assemble("  jmp nowhere\n", "bad.s").unwrap_err();
+        assert_eq!((e.line, e.column), (1, 7));
+
+        let e = assemble("frob v0\n", "bad.s").unwrap_err();
+        assert_eq!(e.message, "unknown mnemonic 'frob'");
+
+        let e = assemble("a: cls\na: cls\n", "bad.s").unwrap_err();
+        assert_eq!(e.line, 2);
+
+        let e = assemble("A equ B\nB equ A\nmov v0,A\n", "bad.s").unwrap_err();
+        assert!(e.message.contains("recursively"));
+    }
+
+    #[test]
+    fn test_reassemble_disassembly() {
+        //the plain disassembly of MAZE assembles back to the same bytes
+        let maze = [
+            0xa2, 0x1e, 0xc2, 0x01, 0x32, 0x01, 0xa2, 0x1a, 0xd0, 0x14, 0x70, 0x04, 0x30, 0x40,
+            0x12, 0x00, 0x60, 0x00, 0x71, 0x04, 0x31, 0x20, 0x12, 0x00, 0x12, 0x18, 0x80, 0x40,
+            0x20, 0x10, 0x20, 0x40, 0x80, 0x10,
+        ];
+        let listing = disasm::disassemble(&maze, disasm::Syntax::Plain);
+        assert_eq!(assemble_ok(&listing), maze.to_vec());
+    }
+}
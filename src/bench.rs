@@ -0,0 +1,128 @@
+//! Timing how fast the interpreter runs a ROM
+//!
+//! The ROM runs headless at `headless::TICKS_PER_FRAME` instructions a
+//! frame, with no keys pressed and no waiting between frames, so the time
+//! taken is all interpreter. Speed is given in instructions a second and as
+//! a multiple of real time, where a frame lasts a sixtieth of a second.
+
+use cpu::Cpu;
+use failure;
+use fault::Fault;
+use headless::run_frames;
+use quirks::Quirks;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+///Frames run unless told otherwise, a minute of play
+pub const DEFAULT_FRAMES: u32 = 3600;
+
+///How long a run took
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bench {
+    ///frames run, fewer than asked for if the ROM halted
+    pub frames: u32,
+    pub instructions: u64,
+    pub elapsed: Duration,
+    ///the fault that halted it, if one did
+    pub fault: Option<Fault>,
+}
+
+impl Bench {
+    fn seconds(&self) -> f64 {
+        let seconds = self.elapsed.as_secs() as f64 + f64::from(self.elapsed.subsec_nanos()) * 1e-9;
+        seconds.max(1e-9)
+    }
+
+    pub fn instructions_per_second(&self) -> f64 {
+        self.instructions as f64 / self.seconds()
+    }
+
+    ///how many times faster than a 60Hz display would show the frames
+    pub fn real_time(&self) -> f64 {
+        f64::from(self.frames) / 60.0 / self.seconds()
+    }
+}
+
+///`N frames, N instructions in Ns: N instructions/s, Nx real time`
+impl fmt::Display for Bench {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} frames, {} instructions in {:.3}s: {:.0} instructions/s, {:.1}x real time",
+            self.frames,
+            self.instructions,
+            self.seconds(),
+            self.instructions_per_second(),
+            self.real_time()
+        )?;
+        if let Some(fault) = self.fault {
+            write!(f, ", halted by {}", fault)?;
+        }
+        Ok(())
+    }
+}
+
+///run `rom` for up to `frames` frames under `quirks`, timing it
+pub fn bench(rom: &[u8], quirks: Quirks, frames: u32, seed: u64) -> Result<Bench, failure::Error> {
+    let mut cpu = Cpu::with_quirks(quirks);
+    cpu.seed(seed);
+    cpu.load_rom(rom)?;
+    let mut result = Bench {
+        frames: 0,
+        instructions: 0,
+        elapsed: Duration::default(),
+        fault: None,
+    };
+    let start = Instant::now();
+    for frame in 0..frames {
+        let instructions = &mut result.instructions;
+        run_frames(
+            &mut cpu,
+            frame..frame + 1,
+            |_| 0,
+            |cpu| {
+                if cpu.fault().is_none() && cpu.exit_code().is_none() {
+                    *instructions += 1;
+                }
+                cpu.tick();
+            },
+        );
+        result.frames += 1;
+        if cpu.fault().is_some() || cpu.exit_code().is_some() {
+            result.fault = cpu.fault();
+            break;
+        }
+    }
+    result.elapsed = start.elapsed();
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use headless::TICKS_PER_FRAME;
+
+    #[test]
+    fn test_bench() {
+        //keep drawing the font's 0 in a new place
+        let rom = [0xA0, 0x50, 0xD0, 0x15, 0x70, 0x01, 0x12, 0x02];
+        let bench = bench(&rom, Quirks::default(), 10, 1).unwrap();
+        assert_eq!(bench.frames, 10);
+        assert_eq!(bench.instructions, u64::from(10 * TICKS_PER_FRAME));
+        assert_eq!(bench.fault, None);
+        assert!(bench.instructions_per_second() > 0.0);
+        assert!(bench
+            .to_string()
+            .starts_with("10 frames, 1000 instructions in "));
+    }
+
+    #[test]
+    fn test_halted() {
+        //return with nothing on the stack
+        let bench = bench(&[0x00, 0xEE], Quirks::default(), 10, 1).unwrap();
+        assert_eq!(bench.frames, 1);
+        assert_eq!(bench.instructions, 1);
+        assert!(bench.fault.is_some());
+        assert!(bench.to_string().contains("halted by"));
+    }
+}
@@ -1,44 +1,464 @@
 //standard includes
 extern crate chip8_tismith;
+#[cfg(feature = "sdl2")]
 extern crate sdl2;
 #[macro_use]
 extern crate log;
+extern crate rand;
 use chip8_tismith::*;
 
+#[cfg(feature = "sdl2")]
 use sdl2::event::Event;
+#[cfg(feature = "sdl2")]
 use sdl2::keyboard::Keycode;
+#[cfg(feature = "sdl2")]
 use sdl2::pixels::Color;
+#[cfg(feature = "sdl2")]
 use sdl2::rect::Rect;
+#[cfg(feature = "sdl2")]
 use sdl2::render::{Canvas, RenderTarget};
-use std::fs::read;
-use std::time::Duration;
+#[cfg(feature = "sdl2")]
+use std::collections::HashMap;
+use std::fs::{read, read_to_string, write};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+///seed for the runs that detect a ROM's quirks or time it, fixed so that
+///they're repeatable
+const REPEATABLE_SEED: u64 = 0xC8;
 
-const PIXEL_DIMENSION: u32 = 10;
-const TICKS_PER_TIMER: u32 = 100;
 const DISPLAY_FREQUENCY: u32 = 60;
 const DISPLAY_PERIOD: u32 = 1_000_000_000u32 / DISPLAY_FREQUENCY;
-const TICK_PERIOD: u32 = 1_000_000_000u32 / (TICKS_PER_TIMER * cpu::TIMER_FREQUENCY as u32);
-const TICKS_PER_DISPLAY: u32 = DISPLAY_PERIOD / TICK_PERIOD;
 
 fn main() -> Result<(), exitfailure::ExitFailure> {
     let mut config = utils::cmdline::parse_cmdline();
     config.module_path = Some(module_path!().into());
     utils::logging::configure_logger(&config)?;
-    let mut cpu = cpu::Cpu::new();
 
-    if let Some(path) = config.rom_path {
-        let rom = read(path)?;
-        cpu.load_rom(&rom);
+    match config.command {
+        utils::types::Command::Disasm(syntax) => {
+            if let Some(path) = config.rom_path {
+                let rom = rom::load(Path::new(&path))?;
+                print!("{}", disasm::disassemble(&rom, syntax));
+            }
+            return Ok(());
+        }
+        utils::types::Command::Info => {
+            if let Some(path) = config.rom_path {
+                let rom = rom::load(Path::new(&path))?;
+                let database = romdb::Database::with_user_entries()?;
+                println!("{}", path);
+                print!("{}", inspect::Report::new(&rom, &database));
+            }
+            return Ok(());
+        }
+        utils::types::Command::Detect { frames, save } => {
+            if let Some(path) = config.rom_path {
+                let rom = rom::load(Path::new(&path))?;
+                let runs = detect::detect(&rom, frames, REPEATABLE_SEED);
+                for run in &runs {
+                    println!("{}", run);
+                }
+                println!("recommended: {} ({})", runs[0].profile, runs[0].quirks);
+                if save {
+                    let saved = romdb::save_user_quirks(&rom, &title(&path), runs[0].quirks)?;
+                    info!("saved to {}", saved.display());
+                }
+            }
+            return Ok(());
+        }
+        utils::types::Command::Asm {
+            ref source,
+            ref output,
+            ref symbols,
+        } => {
+            let assembly = asm::assemble_file(Path::new(source))?;
+            write(output, &assembly.rom)?;
+            if let Some(symbols) = symbols {
+                write(symbols, assembly.symbols.to_string())?;
+            }
+            info!("wrote {} bytes to {}", assembly.rom.len(), output);
+            return Ok(());
+        }
+        utils::types::Command::Test(ref paths) => {
+            let mut failed = 0;
+            for path in paths {
+                let scenario = scenario::Scenario::from_file(Path::new(path))?;
+                match scenario.check() {
+                    Ok(()) => println!("ok {}", path),
+                    Err(failure) => {
+                        println!("FAILED {}", failure);
+                        failed += 1;
+                    }
+                }
+            }
+            if failed > 0 {
+                return Err(failure::err_msg(format!(
+                    "{} of {} scenarios failed",
+                    failed,
+                    paths.len()
+                )).into());
+            }
+            return Ok(());
+        }
+        utils::types::Command::Run
+        | utils::types::Command::Debug
+        | utils::types::Command::Bench { .. } => {}
     }
 
+    let mut rom = Vec::new();
+    let mut symbols = symbols::SymbolMap::new();
+    let mut rom_options = options::Options::default();
+    let mut octo_source = false;
+    if let Some(ref path) = config.rom_path {
+        rom = if path.ends_with(".8o") {
+            let assembly = octo::compile_file(Path::new(path))?;
+            symbols = assembly.symbols;
+            octo_source = true;
+            assembly.rom
+        } else if path.to_lowercase().ends_with(".gif") {
+            let cartridge = cartridge::Cartridge::load(Path::new(path))?;
+            let assembly = octo::compile(&cartridge.program, path)?;
+            symbols = assembly.symbols;
+            rom_options = cartridge.options;
+            octo_source = true;
+            assembly.rom
+        } else {
+            rom::load(Path::new(path))?
+        };
+    }
+    //an options file beats the options saved in a cartridge
+    let options_path = match config.options_path {
+        Some(ref path) => Some(PathBuf::from(path)),
+        None => config
+            .rom_path
+            .as_ref()
+            .and_then(|path| options::Options::sibling(Path::new(path))),
+    };
+    if let Some(ref path) = options_path {
+        info!("reading options from {}", path.display());
+        rom_options = options::Options::load(path)?.or(rom_options);
+    }
+    if config.detect_quirks {
+        let runs = detect::detect(&rom, detect::DEFAULT_FRAMES, REPEATABLE_SEED);
+        for run in &runs {
+            info!("{}", run);
+        }
+        info!("detected quirks: {} ({})", runs[0].profile, runs[0].quirks);
+        if let Some(ref path) = config.rom_path {
+            let saved = romdb::save_user_quirks(&rom, &title(path), runs[0].quirks)?;
+            info!("saved to {}", saved.display());
+        }
+        config.quirks = Some(runs[0].quirks);
+    }
+    let user_config = config::Config::load_user()?;
+    let database = romdb::Database::with_user_entries()?;
+    let entry = database.lookup(&rom);
+    if let Some(entry) = entry {
+        info!("recognised {}", entry);
+        if let Some(ref keys) = entry.keys {
+            info!("keys: {}", keys);
+        }
+        if entry.platform != romdb::Platform::Chip8 {
+            warn!(
+                "{} is for {}, which isn't supported",
+                entry.title, entry.platform
+            );
+        }
+    }
+    //each layer beats the ones before: the config's defaults, the database,
+    //the ROM's own options, the config's section for the ROM, then flags
+    let mut settings = config::Effective::default();
+    if octo_source {
+        //compiled comparisons read the flag from vf, so Octo's quirks it is
+        let octo = config::Profile {
+            quirks: Some(quirks::Quirks::octo()),
+            ..Default::default()
+        };
+        settings.apply(&octo, "Octo source");
+    }
+    settings.apply(&user_config.defaults, "config [defaults]");
+    if let Some(entry) = entry {
+        if entry.quirks.is_some() || entry.tickrate.is_some() {
+            let profile = config::Profile {
+                quirks: entry.quirks,
+                tickrate: entry.tickrate,
+                ..Default::default()
+            };
+            settings.apply(&profile, "the ROM database");
+            info!(
+                "using the database's settings for {}: tickrate {}, quirks {}",
+                entry.title, settings.tickrate, settings.quirks
+            );
+        }
+    }
+    if rom_options != options::Options::default() {
+        settings.apply_options(&rom_options, "the ROM's options");
+        info!(
+            "using the ROM's options: tickrate {}, quirks {}, colours {} on {}",
+            settings.tickrate, settings.quirks, settings.palette.fill, settings.palette.background
+        );
+    }
+    if let Some(ref path) = config.rom_path {
+        if let Some((section, profile)) = user_config.rom(Path::new(path), &rom) {
+            info!("using the config's [{}]", section);
+            settings.apply(&profile, &format!("config [{}]", section));
+        }
+    }
+    let flags = config::Profile {
+        quirks: config.quirks,
+        tickrate: config.tickrate,
+        scale: config.scale,
+        audio: if config.mute { Some(false) } else { None },
+        frontend: config.frontend,
+        ..Default::default()
+    };
+    settings.apply(&flags, "the command line");
+    if config.print_config {
+        if let Some(ref path) = config.rom_path {
+            println!("# the settings for {}, and where each comes from", path);
+        }
+        print!("{}", settings);
+        return Ok(());
+    }
+    if let utils::types::Command::Bench { frames } = config.command {
+        let bench = bench::bench(&rom, settings.quirks, frames, REPEATABLE_SEED)?;
+        println!("{}", bench);
+        return Ok(());
+    }
+
+    let seed = config.seed.unwrap_or_else(rand::random);
+    let mut cpu = cpu::Cpu::with_quirks(settings.quirks);
+    cpu.seed(seed);
+    cpu.load_rom(&rom)?;
+    let mut session = Session {
+        tickrate: settings.tickrate,
+        ..Default::default()
+    };
+    if let Some(path) = config.play_path {
+        let movie = movie::Movie::parse(&read_to_string(path)?)?;
+        cpu = movie.start(&rom)?;
+        session.tickrate = movie.tickrate;
+        session.play = Some(movie);
+    }
+    if let Some(ref path) = config.load_state_path {
+        let (state, frame) = movie::load_state(&read(path)?)?;
+        cpu = state;
+        session.frame = frame;
+    }
+    if let Some(ref path) = config.record_path {
+        session.record = Some(if config.load_state_path.is_some() {
+            //carry on the movie the state was taken from
+            let text = read_to_string(path)
+                .map_err(|e| failure::err_msg(format!("can't append to movie {}: {}", path, e)))?;
+            let mut movie = movie::Movie::parse(&text)?;
+            movie.check_rom(&rom)?;
+            movie.truncate(session.frame);
+            movie
+        } else {
+            let mut movie = movie::Movie::new(&rom, seed, cpu.quirks());
+            movie.tickrate = settings.tickrate;
+            movie
+        });
+    }
+    if config.semihosting {
+        cpu.enable_semihosting();
+    }
+    cpu.set_fault_policy(config.fault_policy);
+    if let Some(path) = config.symbols_path {
+        symbols = symbols::SymbolMap::parse(&read_to_string(path)?)?;
+    }
+    if config.command == utils::types::Command::Debug {
+        //stop before the first instruction, as if there were a breakpoint
+        symbols
+            .breakpoints
+            .entry(cpu.pc())
+            .or_insert_with(|| "start".to_string());
+    }
+    let mut debugger = debugger::Debugger::new(symbols);
+    let mut profiler = config
+        .profile_path
+        .as_ref()
+        .map(|_| profiler::Profiler::new());
+    let mut coverage = config
+        .coverage_path
+        .as_ref()
+        .map(|_| coverage::Coverage::new());
+
+    if config.headless {
+        let keys = match config.keys_path {
+            Some(path) => headless::parse_keys(&read_to_string(path)?)?,
+            None => Vec::new(),
+        };
+        let end = match session.play {
+            Some(ref movie) if config.frames == 0 => movie.frames.max(session.frame),
+            _ => session.frame.saturating_add(config.frames),
+        };
+        while session.frame < end && cpu.exit_code().is_none() && cpu.fault().is_none() {
+            let live = headless::keys_at(&keys, session.frame);
+            session.step(&mut cpu, live, &mut profiler, &mut coverage);
+        }
+        println!("{}", headless::state_json(&cpu, session.frame));
+        if let Some(path) = config.screen_path {
+            write(path, headless::screen_ascii(&cpu))?;
+        }
+        if let Some(ref path) = config.save_state_path {
+            write(path, movie::save_state(&cpu, session.frame))?;
+        }
+    } else if settings.frontend == tui::Frontend::Sdl {
+        run_window(
+            &mut cpu,
+            &mut session,
+            &settings,
+            &config.save_state_path,
+            &mut debugger,
+            &mut profiler,
+            &mut coverage,
+        )?;
+    } else {
+        run_terminal(
+            &mut cpu,
+            &mut session,
+            &settings,
+            &config.save_state_path,
+            &mut debugger,
+            &mut profiler,
+            &mut coverage,
+        )?;
+    }
+
+    if let (Some(movie), Some(path)) = (session.record, config.record_path) {
+        write(&path, movie.to_string())?;
+        info!("wrote {} frames of input to {}", movie.frames, path);
+    }
+
+    if let (Some(profiler), Some(path)) = (profiler, config.profile_path) {
+        write(&path, profiler.report(&cpu, debugger.symbols()))?;
+        let folded = Path::new(&path).with_extension("folded");
+        write(&folded, profiler.folded(debugger.symbols()))?;
+        info!("wrote profile to {} and {}", path, folded.display());
+    }
+    if let (Some(coverage), Some(path)) = (coverage, config.coverage_path) {
+        let symbols = debugger.symbols();
+        let listing = Path::new(&path).with_extension("lst");
+        let listing_name = listing.to_string_lossy().into_owned();
+        write(&path, coverage.lcov(&rom, symbols, &listing_name))?;
+        if symbols.lines.is_empty() {
+            write(&listing, coverage.listing(&rom))?;
+        }
+        info!("wrote coverage to {}", path);
+    }
+
+    if let Some(fault) = cpu.fault() {
+        return Err(failure::Error::from(fault).into());
+    }
+    match cpu.exit_code() {
+        Some(code) if code != 0 => std::process::exit(i32::from(code)),
+        _ => Ok(()),
+    }
+}
+
+///a title for a ROM the database doesn't know, from its file name
+fn title(path: &str) -> String {
+    Path::new(path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.to_string())
+}
+
+fn tick(
+    cpu: &mut cpu::Cpu,
+    profiler: &mut Option<profiler::Profiler>,
+    coverage: &mut Option<coverage::Coverage>,
+) {
+    let pc = cpu.pc();
+    match profiler {
+        Some(profiler) => profiler.tick(cpu),
+        None => cpu.tick(),
+    }
+    if let Some(coverage) = coverage {
+        coverage.record(cpu, pc);
+    }
+}
+
+///Frame counting and the keypad, from a movie or live, for either runner
+#[derive(Default)]
+struct Session {
+    frame: u32,
+    ///instructions to a frame
+    tickrate: u32,
+    ///instructions run so far this frame
+    ticks: u32,
+    play: Option<movie::Movie>,
+    record: Option<movie::Movie>,
+}
+
+impl Session {
+    ///run an instruction, setting the keypad first if a frame is starting
+    ///and ticking the timers if one is ending, true if the sound timer beeps
+    fn step(
+        &mut self,
+        cpu: &mut cpu::Cpu,
+        live: u16,
+        profiler: &mut Option<profiler::Profiler>,
+        coverage: &mut Option<coverage::Coverage>,
+    ) -> bool {
+        if self.ticks == 0 {
+            let keys = match self.play {
+                Some(ref movie) if self.frame < movie.frames => movie.keys_at(self.frame),
+                _ => live,
+            };
+            if let Some(ref mut movie) = self.record {
+                movie.record(self.frame, keys);
+            }
+            cpu.set_keys(keys);
+        }
+        tick(cpu, profiler, coverage);
+        if let Some(host) = cpu.semihost_mut() {
+            if !host.output.is_empty() {
+                let mut stdout = std::io::stdout();
+                let _ = stdout.write_all(&host.take_output());
+                let _ = stdout.flush();
+            }
+        }
+        self.ticks += 1;
+        if self.ticks < self.tickrate {
+            return false;
+        }
+        self.ticks = 0;
+        self.frame += 1;
+        cpu.tick_timers()
+    }
+}
+
+#[cfg(feature = "sdl2")]
+fn run_window(
+    cpu: &mut cpu::Cpu,
+    session: &mut Session,
+    settings: &config::Effective,
+    save_state_path: &Option<String>,
+    debugger: &mut debugger::Debugger,
+    profiler: &mut Option<profiler::Profiler>,
+    coverage: &mut Option<coverage::Coverage>,
+) -> Result<(), failure::Error> {
+    let mut paused = false;
+    let mut pad = 0u16;
+    let mut save_requested = false;
+    //a frame's instructions spread evenly across it
+    let tick_period = DISPLAY_PERIOD / session.tickrate.max(1);
+    let palette = settings.palette;
+    let scale = settings.scale;
+    let keymap = keymap(&settings.keys)?;
+
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
 
     let window = video_subsystem
         .window(
             "CHIP-8",
-            PIXEL_DIMENSION * cpu::SCREEN_WIDTH as u32,
-            PIXEL_DIMENSION * cpu::SCREEN_HEIGHT as u32,
+            scale * cpu::SCREEN_WIDTH as u32,
+            scale * cpu::SCREEN_HEIGHT as u32,
         ).position_centered()
         .opengl()
         .build()
@@ -46,13 +466,17 @@ fn main() -> Result<(), exitfailure::ExitFailure> {
 
     let mut canvas = window.into_canvas().build().unwrap();
 
-    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.set_draw_color(color(palette.background));
     canvas.clear();
     canvas.present();
     let mut event_pump = sdl_context.event_pump().unwrap();
 
     let mut counter = 0u32;
     'running: loop {
+        if let Some(code) = cpu.exit_code() {
+            info!("exited with code {}", code);
+            break;
+        }
         for event in event_pump.poll_iter() {
             match event {
                 Event::Quit { .. }
@@ -60,77 +484,239 @@ fn main() -> Result<(), exitfailure::ExitFailure> {
                     keycode: Some(Keycode::Escape),
                     ..
                 } => break 'running,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    ..
+                } => {
+                    if let Some(fault) = cpu.fault() {
+                        cpu.clear_fault();
+                        cpu.set_pc(fault.pc().wrapping_add(2));
+                    }
+                    paused = false
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F6),
+                    ..
+                } if paused => {
+                    session.step(cpu, pad, profiler, coverage);
+                    print!("{}", debugger.report(cpu));
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F2),
+                    ..
+                } if save_state_path.is_some() => save_requested = true,
                 Event::KeyDown {
                     keycode: Some(key), ..
-                } => *cpu.key_mut(map_key(key)) = true,
+                } => pad |= keymap.get(&key).cloned().unwrap_or(0),
                 Event::KeyUp {
                     keycode: Some(key), ..
-                } => *cpu.key_mut(map_key(key)) = false,
+                } => pad &= !keymap.get(&key).cloned().unwrap_or(0),
                 _ => {}
             }
         }
+        //states are only taken between frames, where a movie can resume
+        if save_requested && session.ticks == 0 {
+            if let Some(ref path) = *save_state_path {
+                write(path, movie::save_state(cpu, session.frame))?;
+                info!("saved state at frame {} to {}", session.frame, path);
+            }
+            save_requested = false;
+        }
+        if !paused && cpu.fault().is_some() {
+            //stop to show where, F5 carries on past the instruction
+            print!("{}", debugger.report(cpu));
+            paused = true;
+        }
+        if !paused {
+            if let Some(name) = debugger.check(cpu) {
+                println!("breakpoint {}", name);
+                print!("{}", debugger.report(cpu));
+                paused = true;
+            }
+        }
+        if paused {
+            std::thread::sleep(Duration::new(0, DISPLAY_PERIOD));
+            draw_screen(&mut canvas, cpu, palette, scale)?;
+            continue;
+        }
         counter = counter.wrapping_add(1);
 
-        if counter % TICKS_PER_TIMER == 0 && cpu.tick_timers() {
+        if session.step(cpu, pad, profiler, coverage) && settings.audio {
             info!("BEEP!");
         }
 
-        cpu.tick();
+        if counter.is_multiple_of(session.tickrate) {
+            draw_screen(&mut canvas, cpu, palette, scale)?;
+        }
+
+        std::thread::sleep(Duration::new(0, tick_period));
+    }
+
+    Ok(())
+}
+
+#[cfg(not(feature = "sdl2"))]
+fn run_window(
+    _cpu: &mut cpu::Cpu,
+    _session: &mut Session,
+    _settings: &config::Effective,
+    _save_state_path: &Option<String>,
+    _debugger: &mut debugger::Debugger,
+    _profiler: &mut Option<profiler::Profiler>,
+    _coverage: &mut Option<coverage::Coverage>,
+) -> Result<(), failure::Error> {
+    Err(failure::err_msg(
+        "built without a window, use --headless or --frontend tui",
+    ))
+}
+
+#[cfg(unix)]
+fn run_terminal(
+    cpu: &mut cpu::Cpu,
+    session: &mut Session,
+    settings: &config::Effective,
+    save_state_path: &Option<String>,
+    debugger: &mut debugger::Debugger,
+    profiler: &mut Option<profiler::Profiler>,
+    coverage: &mut Option<coverage::Coverage>,
+) -> Result<(), failure::Error> {
+    let glyphs = match settings.frontend {
+        tui::Frontend::Tui(glyphs) => glyphs,
+        tui::Frontend::Sdl => tui::Glyphs::HalfBlocks,
+    };
+    let keymap = tui::Keymap::new(&settings.keys)?;
+    let mut keypad = tui::Keypad::default();
+    //why the ROM is stopped, if it is
+    let mut paused: Option<String> = None;
+    let mut save_requested = false;
+    let period = Duration::new(0, DISPLAY_PERIOD);
+
+    let mut terminal = tui::Terminal::new()?;
+    'running: loop {
+        let started = Instant::now();
+        if let Some(code) = cpu.exit_code() {
+            info!("exited with code {}", code);
+            break;
+        }
+        for input in tui::decode(&terminal.read()) {
+            match input {
+                tui::Input::Escape | tui::Input::Interrupt => break 'running,
+                tui::Input::F5 => {
+                    if let Some(fault) = cpu.fault() {
+                        cpu.clear_fault();
+                        cpu.set_pc(fault.pc().wrapping_add(2));
+                    }
+                    paused = None;
+                }
+                tui::Input::F6 if paused.is_some() => {
+                    session.step(cpu, keypad.keys(), profiler, coverage);
+                    paused = Some("stepped".to_string());
+                }
+                tui::Input::F2 if save_state_path.is_some() => save_requested = true,
+                input => keypad.press(keymap.keys(input)),
+            }
+        }
+        //states are only taken between frames, where a movie can resume
+        if save_requested && session.ticks == 0 {
+            if let Some(ref path) = *save_state_path {
+                write(path, movie::save_state(cpu, session.frame))?;
+                info!("saved state at frame {} to {}", session.frame, path);
+            }
+            save_requested = false;
+        }
+
+        let mut beeped = false;
+        let frame = session.frame;
+        while paused.is_none() && session.frame == frame {
+            if let Some(fault) = cpu.fault() {
+                paused = Some(fault.to_string());
+            } else if let Some(name) = debugger.check(cpu) {
+                paused = Some(format!("breakpoint {}", name));
+            } else {
+                beeped |= session.step(cpu, keypad.keys(), profiler, coverage);
+            }
+        }
+        keypad.frame();
 
-        if counter % TICKS_PER_DISPLAY == 0 {
-            draw_screen(&mut canvas, &cpu)?;
+        let mut status = tui::status(cpu, session.frame);
+        if let Some(ref why) = paused {
+            status.push_str(&format!(
+                "  paused: {}, F6 steps, F5 resumes\n{}",
+                why,
+                debugger.report(cpu)
+            ));
         }
+        let mut text = tui::draw(cpu.screen(), glyphs, settings.palette, &status);
+        if beeped && settings.audio {
+            text.push('\x07');
+        }
+        terminal.write(&text)?;
 
-        std::thread::sleep(Duration::new(0, TICK_PERIOD));
+        if let Some(left) = period.checked_sub(started.elapsed()) {
+            std::thread::sleep(left);
+        }
     }
 
     Ok(())
 }
 
-fn map_key(keycode: sdl2::keyboard::Keycode) -> u8 {
-    match keycode {
-        Keycode::Num0 => 0x00,
-        Keycode::Num1 => 0x01,
-        Keycode::Num2 => 0x02,
-        Keycode::Num3 => 0x03,
-        Keycode::Num4 => 0x04,
-        Keycode::Num5 => 0x05,
-        Keycode::Num6 => 0x06,
-        Keycode::Num7 => 0x07,
-        Keycode::Num8 => 0x08,
-        Keycode::Num9 => 0x09,
-        Keycode::A => 0x0A,
-        Keycode::B => 0x0B,
-        Keycode::C => 0x0C,
-        Keycode::D => 0x0D,
-        Keycode::E => 0x0E,
-        Keycode::F => 0x0F,
-        _ => 0xFF,
+#[cfg(not(unix))]
+fn run_terminal(
+    _cpu: &mut cpu::Cpu,
+    _session: &mut Session,
+    _settings: &config::Effective,
+    _save_state_path: &Option<String>,
+    _debugger: &mut debugger::Debugger,
+    _profiler: &mut Option<profiler::Profiler>,
+    _coverage: &mut Option<coverage::Coverage>,
+) -> Result<(), failure::Error> {
+    Err(failure::err_msg(
+        "the terminal frontend needs a Unix terminal",
+    ))
+}
+
+///the keypad bit for each keyboard key, from the key names by keypad key
+#[cfg(feature = "sdl2")]
+fn keymap(names: &[String; 16]) -> Result<HashMap<Keycode, u16>, failure::Error> {
+    let mut keymap = HashMap::new();
+    for (key, name) in names.iter().enumerate() {
+        let keycode = Keycode::from_name(name)
+            .ok_or_else(|| failure::err_msg(format!("no keyboard key called '{}'", name)))?;
+        *keymap.entry(keycode).or_insert(0) |= 1 << key;
     }
+    Ok(keymap)
 }
 
+#[cfg(feature = "sdl2")]
 fn draw_screen<T: RenderTarget>(
     canvas: &mut Canvas<T>,
     cpu: &cpu::Cpu,
+    palette: options::Palette,
+    scale: u32,
 ) -> Result<(), failure::Error> {
     for (i, filled) in cpu.screen().iter().enumerate() {
         if *filled {
-            canvas.set_draw_color(Color::RGB(255, 255, 255));
+            canvas.set_draw_color(color(palette.fill));
         } else {
-            canvas.set_draw_color(Color::RGB(0, 0, 0));
+            canvas.set_draw_color(color(palette.background));
         }
 
         let x = i % cpu::SCREEN_WIDTH;
         let y = i / cpu::SCREEN_WIDTH;
         canvas
             .fill_rect(Rect::new(
-                (PIXEL_DIMENSION * x as u32) as i32,
-                (PIXEL_DIMENSION * y as u32) as i32,
-                PIXEL_DIMENSION,
-                PIXEL_DIMENSION,
-            )).map_err(failure::err_msg)?;;
+                (scale * x as u32) as i32,
+                (scale * y as u32) as i32,
+                scale,
+                scale,
+            )).map_err(failure::err_msg)?;
     }
 
     canvas.present();
     Ok(())
 }
+
+#[cfg(feature = "sdl2")]
+fn color(rgb: options::Rgb) -> Color {
+    Color::RGB(rgb.0, rgb.1, rgb.2)
+}
@@ -0,0 +1,147 @@
+//! Octo cartridges, programs shared as GIF images
+//!
+//! Octo saves a program as a picture of a cartridge with its source and
+//! options hidden in the image data. The low two bits of each pixel's colour
+//! index, frame after frame, carry the payload four pixels to a byte, high
+//! bits first. The payload is the length of what follows as a 32-bit
+//! big-endian number, then a JSON object holding the Octo `program` source
+//! and its `options`, which `options::Options` reads.
+
+use failure;
+use gif;
+use options::Options;
+use serde_json::{self, Value};
+use std::fs;
+use std::path::Path;
+
+///The payload of a cartridge
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cartridge {
+    ///Octo source, for `octo::compile`
+    pub program: String,
+    pub options: Options,
+}
+
+impl Cartridge {
+    ///the cartridge in the GIF at `path`
+    pub fn load(path: &Path) -> Result<Self, failure::Error> {
+        let bytes = fs::read(path).map_err(|e| format_err!("{}: {}", path.display(), e))?;
+        Cartridge::decode(&bytes).map_err(|e| format_err!("{}: {}", path.display(), e))
+    }
+
+    ///the cartridge in the bytes of a GIF
+    pub fn decode(gif: &[u8]) -> Result<Self, failure::Error> {
+        let payload = payload(gif)?;
+        let json: Value = serde_json::from_slice(&payload)
+            .map_err(|e| format_err!("bad cartridge payload: {}", e))?;
+        let program = match json.get("program") {
+            Some(Value::String(program)) => program.clone(),
+            _ => bail!("the cartridge has no program"),
+        };
+        let options = match json.get("options") {
+            Some(Value::Object(options)) => Options::from_json(options)?,
+            None | Some(Value::Null) => Options::default(),
+            Some(_) => bail!("the cartridge's options should be a JSON object"),
+        };
+        Ok(Cartridge { program, options })
+    }
+}
+
+///the bytes hidden in the image, without the length in front
+fn payload(gif: &[u8]) -> Result<Vec<u8>, failure::Error> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = options
+        .read_info(gif)
+        .map_err(|e| format_err!("not a GIF: {}", e))?;
+    let mut bytes = Vec::new();
+    let mut byte = 0u8;
+    let mut pairs = 0;
+    while let Some(frame) = decoder
+        .read_next_frame()
+        .map_err(|e| format_err!("bad GIF: {}", e))?
+    {
+        for &index in frame.buffer.iter() {
+            byte = byte << 2 | index & 0x03;
+            pairs += 1;
+            if pairs == 4 {
+                bytes.push(byte);
+                byte = 0;
+                pairs = 0;
+            }
+        }
+    }
+    if bytes.len() < 4 {
+        bail!("the GIF is too small to be a cartridge");
+    }
+    let length = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+    if length > bytes.len() - 4 {
+        bail!(
+            "not a cartridge, or a damaged one: the payload is {} bytes but only {} fit",
+            length,
+            bytes.len() - 4
+        );
+    }
+    Ok(bytes[4..4 + length].to_vec())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    ///a blank cartridge with `data` hidden in it, as Octo would write it
+    fn encode(data: &[u8]) -> Vec<u8> {
+        const WIDTH: u16 = 160;
+        const HEIGHT: u16 = 128;
+        let mut pixels: Vec<u8> = data
+            .iter()
+            .flat_map(|&byte| (0..4).rev().map(move |pair| byte >> (2 * pair) & 0x03))
+            .collect();
+        let frame_size = usize::from(WIDTH) * usize::from(HEIGHT);
+        let frames = pixels.len().div_ceil(frame_size).max(1);
+        pixels.resize(frames * frame_size, 0);
+
+        //four of each colour, so the hidden bits don't show
+        let palette: Vec<u8> = (0..=255)
+            .flat_map(|index: u8| vec![index & !0x03; 3])
+            .collect();
+        let mut gif = Vec::new();
+        {
+            let mut encoder = gif::Encoder::new(&mut gif, WIDTH, HEIGHT, &palette).unwrap();
+            for frame in pixels.chunks(frame_size) {
+                let frame = gif::Frame::from_indexed_pixels(WIDTH, HEIGHT, frame, None);
+                encoder.write_frame(&frame).unwrap();
+            }
+        }
+        gif
+    }
+
+    fn cartridge(payload: &str) -> Vec<u8> {
+        let mut data = (payload.len() as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(payload.as_bytes());
+        encode(&data)
+    }
+
+    #[test]
+    fn test_decode() {
+        //long enough to take a few frames
+        let program = ": main\n  loop again\n".repeat(600);
+        let payload = format!(
+            r#"{{"program": {}, "options": {{"tickrate": 500, "jumpQuirks": true}}}}"#,
+            Value::String(program.clone())
+        );
+        let decoded = Cartridge::decode(&cartridge(&payload)).unwrap();
+        assert_eq!(decoded.program, program);
+        assert_eq!(decoded.options.tickrate, Some(500));
+        assert_eq!(decoded.options.jump_quirks, Some(true));
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |gif: &[u8]| Cartridge::decode(gif).unwrap_err().to_string();
+        assert!(error(b"GIF89a").starts_with("not a GIF"));
+        assert!(error(&cartridge(r#"{"options": {}}"#)).contains("no program"));
+        assert!(error(&cartridge("not json")).starts_with("bad cartridge payload"));
+        assert!(error(&encode(&[0xFF; 8])).contains("damaged"));
+    }
+}
@@ -0,0 +1,411 @@
+//! Control-flow graphs of CHIP-8 ROMs
+//!
+//! Instructions are discovered by recursive traversal from the entry point
+//! and split into basic blocks at every jump, call, return and skip, and at
+//! every address something branches to. Calls end a block so that the blocks
+//! of each subroutine can be found by following everything but call edges.
+//! `jmi` jumps to an address that depends on v0, so only its base is
+//! followed and the instruction is reported as an indirect jump.
+
+use disasm::ROM_START;
+use instruction::{Instruction, INSTRUCTION_WIDTH};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::ops::Range;
+
+///Why control can pass from one block to another
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    ///execution runs on into the next block
+    Fallthrough,
+    Jump,
+    ///a `jsr`, execution continues at the following `Fallthrough` edge once
+    ///the subroutine returns
+    Call,
+    ///the instruction after a skip is jumped over
+    Skip,
+    ///the base address of a `jmi`
+    Indirect,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+    pub target: u16,
+    pub kind: EdgeKind,
+}
+
+///A straight run of instructions with a single entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub start: u16,
+    ///address after the last instruction
+    pub end: u16,
+    pub instructions: Vec<(u16, Instruction)>,
+    pub successors: Vec<Edge>,
+}
+
+impl Block {
+    ///true if the block ends with `rts`
+    pub fn returns(&self) -> bool {
+        self.instructions
+            .last()
+            .is_some_and(|&(_, instruction)| instruction == Instruction::Rts)
+    }
+}
+
+///The blocks reachable from a `jsr` target without following further calls
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subroutine {
+    pub entry: u16,
+    ///start addresses of the blocks
+    pub blocks: BTreeSet<u16>,
+    ///entries of the subroutines it calls
+    pub calls: BTreeSet<u16>,
+    ///true if some path reaches an `rts`
+    pub returns: bool,
+}
+
+///A `jmi` whose destination is only known at run time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndirectJump {
+    pub address: u16,
+    ///destination when v0 is zero, the destination is at most 0xFF past it
+    pub base: u16,
+}
+
+///The result of analysing a ROM loaded at `ROM_START`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControlFlowGraph {
+    ///keyed by start address
+    pub blocks: BTreeMap<u16, Block>,
+    ///keyed by entry address
+    pub subroutines: BTreeMap<u16, Subroutine>,
+    pub indirect_jumps: Vec<IndirectJump>,
+    ///byte ranges no traced instruction covers, including sprite data
+    pub unreachable: Vec<Range<u16>>,
+}
+
+impl ControlFlowGraph {
+    ///analyse a ROM that will be loaded at `ROM_START`
+    pub fn new(rom: &[u8]) -> Self {
+        let mut instructions = BTreeMap::new();
+        let mut leaders = BTreeSet::new();
+        let mut calls = BTreeSet::new();
+        let mut indirect_jumps = Vec::new();
+        let mut covered = vec![false; rom.len()];
+
+        let in_rom =
+            |address: u16| address >= ROM_START && usize::from(address - ROM_START) + 1 < rom.len();
+
+        leaders.insert(ROM_START);
+        let mut pending = vec![ROM_START];
+        while let Some(mut address) = pending.pop() {
+            while in_rom(address) && !instructions.contains_key(&address) {
+                let offset = usize::from(address - ROM_START);
+                let instruction = match Instruction::fetch(rom, offset) {
+                    Some(Instruction::Unknown(_)) | None => break,
+                    Some(instruction) => instruction,
+                };
+                instructions.insert(address, instruction);
+                covered[offset] = true;
+                covered[offset + 1] = true;
+                let next = address.wrapping_add(INSTRUCTION_WIDTH);
+
+                let mut branch = |target: u16, pending: &mut Vec<u16>| {
+                    if in_rom(target) {
+                        leaders.insert(target);
+                        pending.push(target);
+                    }
+                };
+                match instruction {
+                    Instruction::Jmp(target) => {
+                        branch(target, &mut pending);
+                        break;
+                    }
+                    Instruction::Jmi(base) => {
+                        indirect_jumps.push(IndirectJump { address, base });
+                        branch(base, &mut pending);
+                        break;
+                    }
+                    Instruction::Rts => break,
+                    Instruction::Jsr(target) => {
+                        calls.insert(target);
+                        branch(target, &mut pending);
+                        branch(next, &mut pending);
+                    }
+                    i if i.is_skip() => {
+                        branch(next, &mut pending);
+                        branch(next.wrapping_add(INSTRUCTION_WIDTH), &mut pending);
+                    }
+                    _ => {}
+                }
+                address = next;
+            }
+        }
+
+        let mut blocks = BTreeMap::new();
+        for &start in &leaders {
+            if !instructions.contains_key(&start) {
+                continue;
+            }
+            let mut block = Block {
+                start,
+                end: start,
+                instructions: Vec::new(),
+                successors: Vec::new(),
+            };
+            let mut address = start;
+            while let Some(&instruction) = instructions.get(&address) {
+                block.instructions.push((address, instruction));
+                let next = address.wrapping_add(INSTRUCTION_WIDTH);
+                block.end = next;
+                let edge = |target, kind| Edge { target, kind };
+                match instruction {
+                    Instruction::Jmp(target) => block.successors.push(edge(target, EdgeKind::Jump)),
+                    Instruction::Jmi(base) => block.successors.push(edge(base, EdgeKind::Indirect)),
+                    Instruction::Rts => {}
+                    Instruction::Jsr(target) => {
+                        block.successors.push(edge(target, EdgeKind::Call));
+                        block.successors.push(edge(next, EdgeKind::Fallthrough));
+                    }
+                    i if i.is_skip() => {
+                        block.successors.push(edge(next, EdgeKind::Fallthrough));
+                        let skipped = next.wrapping_add(INSTRUCTION_WIDTH);
+                        block.successors.push(edge(skipped, EdgeKind::Skip));
+                    }
+                    _ => {
+                        if leaders.contains(&next) {
+                            block.successors.push(edge(next, EdgeKind::Fallthrough));
+                        } else {
+                            address = next;
+                            continue;
+                        }
+                    }
+                }
+                break;
+            }
+            //branches out of the ROM, or into bytes that don't decode
+            block
+                .successors
+                .retain(|edge| instructions.contains_key(&edge.target));
+            blocks.insert(start, block);
+        }
+
+        let subroutines = calls
+            .iter()
+            .filter(|entry| blocks.contains_key(entry))
+            .map(|&entry| (entry, subroutine(&blocks, entry)))
+            .collect();
+
+        let mut unreachable = Vec::new();
+        let mut offset = 0;
+        while offset < rom.len() {
+            if covered[offset] {
+                offset += 1;
+                continue;
+            }
+            let start = offset;
+            while offset < rom.len() && !covered[offset] {
+                offset += 1;
+            }
+            unreachable.push(ROM_START + start as u16..ROM_START + offset as u16);
+        }
+
+        ControlFlowGraph {
+            blocks,
+            subroutines,
+            indirect_jumps,
+            unreachable,
+        }
+    }
+
+    ///the block containing the instruction at `address`
+    pub fn block_at(&self, address: u16) -> Option<&Block> {
+        self.blocks
+            .range(..=address)
+            .next_back()
+            .map(|(_, block)| block)
+            .filter(|block| block.instructions.iter().any(|&(a, _)| a == address))
+    }
+
+    ///render as a Graphviz digraph, with each subroutine in its own cluster
+    pub fn to_dot(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "digraph cfg {{");
+        let _ = writeln!(out, "    node [shape=box fontname=monospace];");
+
+        //a block shared by several subroutines is drawn in the first one
+        let mut drawn = BTreeSet::new();
+        for subroutine in self.subroutines.values() {
+            let _ = writeln!(out, "    subgraph cluster_{:03X} {{", subroutine.entry);
+            let _ = writeln!(out, "        label=\"sub_{:03X}\";", subroutine.entry);
+            for start in &subroutine.blocks {
+                if drawn.insert(*start) {
+                    out.push_str("    ");
+                    self.dot_node(&mut out, &self.blocks[start]);
+                }
+            }
+            let _ = writeln!(out, "    }}");
+        }
+        for block in self.blocks.values() {
+            if !drawn.contains(&block.start) {
+                self.dot_node(&mut out, block);
+            }
+        }
+
+        for block in self.blocks.values() {
+            for edge in &block.successors {
+                let style = match edge.kind {
+                    EdgeKind::Fallthrough => "",
+                    EdgeKind::Jump => " [label=jmp]",
+                    EdgeKind::Call => " [label=jsr style=dashed]",
+                    EdgeKind::Skip => " [label=skip]",
+                    EdgeKind::Indirect => " [label=jmi style=dotted]",
+                };
+                let _ = writeln!(
+                    out,
+                    "    b{:03X} -> b{:03X}{};",
+                    block.start, edge.target, style
+                );
+            }
+        }
+        for range in &self.unreachable {
+            let _ = writeln!(
+                out,
+                "    u{:03X} [label=\"unreachable 0x{:03X}-0x{:03X}\" style=dashed];",
+                range.start,
+                range.start,
+                range.end - 1
+            );
+        }
+        let _ = writeln!(out, "}}");
+        out
+    }
+
+    fn dot_node(&self, out: &mut String, block: &Block) {
+        let mut label = String::new();
+        for &(address, instruction) in &block.instructions {
+            let _ = write!(label, "{:03X}: {}\\l", address, instruction);
+        }
+        let _ = writeln!(out, "    b{:03X} [label=\"{}\"];", block.start, label);
+    }
+}
+
+///collect the blocks reachable from `entry` without following calls
+fn subroutine(blocks: &BTreeMap<u16, Block>, entry: u16) -> Subroutine {
+    let mut subroutine = Subroutine {
+        entry,
+        blocks: BTreeSet::new(),
+        calls: BTreeSet::new(),
+        returns: false,
+    };
+    let mut pending = vec![entry];
+    while let Some(start) = pending.pop() {
+        let block = match blocks.get(&start) {
+            Some(block) => block,
+            None => continue,
+        };
+        if !subroutine.blocks.insert(start) {
+            continue;
+        }
+        subroutine.returns |= block.returns();
+        for edge in &block.successors {
+            if edge.kind == EdgeKind::Call {
+                subroutine.calls.insert(edge.target);
+            } else {
+                pending.push(edge.target);
+            }
+        }
+    }
+    subroutine
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    //the start of roms/MAZE
+    const MAZE: [u8; 34] = [
+        0xa2, 0x1e, 0xc2, 0x01, 0x32, 0x01, 0xa2, 0x1a, 0xd0, 0x14, 0x70, 0x04, 0x30, 0x40, 0x12,
+        0x00, 0x60, 0x00, 0x71, 0x04, 0x31, 0x20, 0x12, 0x00, 0x12, 0x18, 0x80, 0x40, 0x20, 0x10,
+        0x20, 0x40, 0x80, 0x10,
+    ];
+
+    #[test]
+    fn test_blocks_split_at_skips() {
+        let cfg = ControlFlowGraph::new(&MAZE);
+        let starts: Vec<u16> = cfg.blocks.keys().cloned().collect();
+        assert_eq!(
+            starts,
+            vec![0x200, 0x206, 0x208, 0x20E, 0x210, 0x216, 0x218]
+        );
+        let first = &cfg.blocks[&0x200];
+        assert_eq!(first.end, 0x206);
+        assert_eq!(
+            first.successors,
+            vec![
+                Edge {
+                    target: 0x206,
+                    kind: EdgeKind::Fallthrough
+                },
+                Edge {
+                    target: 0x208,
+                    kind: EdgeKind::Skip
+                },
+            ]
+        );
+        assert_eq!(cfg.block_at(0x204).map(|b| b.start), Some(0x200));
+        assert!(cfg.block_at(0x205).is_none());
+    }
+
+    #[test]
+    fn test_unreachable() {
+        let cfg = ControlFlowGraph::new(&MAZE);
+        assert_eq!(cfg.unreachable, vec![0x21A..0x222]);
+    }
+
+    #[test]
+    fn test_subroutines() {
+        //jsr 0x204; jmp 0x202; jsr 0x208; rts; rts
+        let rom = [0x22, 0x04, 0x12, 0x02, 0x22, 0x08, 0x00, 0xEE, 0x00, 0xEE];
+        let cfg = ControlFlowGraph::new(&rom);
+        assert_eq!(cfg.subroutines.len(), 2);
+        let outer = &cfg.subroutines[&0x204];
+        assert!(outer.returns);
+        assert_eq!(outer.calls.iter().cloned().collect::<Vec<_>>(), vec![0x208]);
+        assert_eq!(
+            outer.blocks.iter().cloned().collect::<Vec<_>>(),
+            vec![0x204, 0x206]
+        );
+        assert!(cfg.subroutines[&0x208].calls.is_empty());
+        //the caller continues after the call
+        assert!(cfg.blocks.contains_key(&0x202));
+    }
+
+    #[test]
+    fn test_indirect_jump() {
+        //jmi 0x204; dw 0xFFFF; jmp 0x204
+        let rom = [0xB2, 0x04, 0xFF, 0xFF, 0x12, 0x04];
+        let cfg = ControlFlowGraph::new(&rom);
+        assert_eq!(
+            cfg.indirect_jumps,
+            vec![IndirectJump {
+                address: 0x200,
+                base: 0x204
+            }]
+        );
+        assert_eq!(cfg.unreachable, vec![0x202..0x204]);
+    }
+
+    #[test]
+    fn test_dot() {
+        let rom = [0x22, 0x04, 0x12, 0x02, 0x00, 0xEE];
+        let dot = ControlFlowGraph::new(&rom).to_dot();
+        assert!(dot.starts_with("digraph cfg {\n"));
+        assert!(dot.contains("subgraph cluster_204 {"));
+        assert!(dot.contains("b204 [label=\"204: rts\\l\"];"));
+        assert!(dot.contains("b200 -> b204 [label=jsr style=dashed];"));
+        assert!(dot.contains("b202 -> b202 [label=jmp];"));
+        assert!(dot.ends_with("}\n"));
+    }
+}
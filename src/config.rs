@@ -0,0 +1,506 @@
+//! The user's config file, `config.toml` in the XDG config directory
+//!
+//! `[defaults]` holds settings for every ROM and `[roms.NAME]` sections
+//! hold settings for one, matched by its file name or the hex SHA-1 of the
+//! image. Each section can set
+//!
+//! ```toml
+//! quirks = "vip"        # a profile or flags, as --quirks takes
+//! tickrate = 20         # instructions per 60Hz frame
+//! background = "#000000"
+//! fill = "#FFFFFF"
+//! scale = 10            # window pixels to a CHIP-8 pixel
+//! audio = true          # beep when the sound timer runs out
+//! frontend = "tui"      # sdl, or tui or braille for a terminal
+//!
+//! [defaults.keys]       # keyboard key for each keypad key
+//! 5 = "W"
+//! ```
+//!
+//! `Effective` layers the sections over the built in settings along with
+//! the ROM database and the ROM's own options, and remembers where each
+//! setting came from.
+
+use failure;
+use headless::TICKS_PER_FRAME;
+use movie::rom_sha1;
+use options::{Options, Palette, Rgb};
+use quirks::Quirks;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::value::Table;
+use toml::Value;
+use tui::Frontend;
+
+///Window pixels to a CHIP-8 pixel unless told otherwise
+pub const DEFAULT_SCALE: u32 = 10;
+///the most window pixels to a CHIP-8 pixel
+pub const MAX_SCALE: u32 = 64;
+///the most instructions a frame
+pub const MAX_TICKRATE: u32 = 1_000_000;
+
+///`n` as a count from 1 to `max`, for the `name` setting
+pub fn count(name: &str, n: i64, max: u32) -> Result<u32, String> {
+    if n > 0 && n <= i64::from(max) {
+        Ok(n as u32)
+    } else {
+        Err(format!("{} should be from 1 to {}, not {}", name, max, n))
+    }
+}
+
+///the directory the emulator's own files live in, `chip8` under
+///`$XDG_CONFIG_HOME`, or under `~/.config` without it
+pub fn dir() -> Option<PathBuf> {
+    let config = match env::var_os("XDG_CONFIG_HOME") {
+        Some(ref dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config.join("chip8"))
+}
+
+///One section of the config file, each setting `None` when not given
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+    pub quirks: Option<Quirks>,
+    pub tickrate: Option<u32>,
+    pub background: Option<Rgb>,
+    pub fill: Option<Rgb>,
+    ///keyboard key names by keypad key
+    pub keys: BTreeMap<u8, String>,
+    pub scale: Option<u32>,
+    pub audio: Option<bool>,
+    pub frontend: Option<Frontend>,
+}
+
+impl Profile {
+    ///these settings, with those not given here taken from `fallback`
+    pub fn or(self, fallback: &Profile) -> Profile {
+        let mut keys = fallback.keys.clone();
+        keys.extend(self.keys);
+        Profile {
+            quirks: self.quirks.or(fallback.quirks),
+            tickrate: self.tickrate.or(fallback.tickrate),
+            background: self.background.or(fallback.background),
+            fill: self.fill.or(fallback.fill),
+            keys,
+            scale: self.scale.or(fallback.scale),
+            audio: self.audio.or(fallback.audio),
+            frontend: self.frontend.or(fallback.frontend),
+        }
+    }
+
+    fn from_toml(table: &Table) -> Result<Self, failure::Error> {
+        let mut profile = Profile::default();
+        for (name, value) in table {
+            let text = || match *value {
+                Value::String(ref text) => Ok(text.as_str()),
+                _ => Err(format_err!("{} should be a string, not {}", name, value)),
+            };
+            let number = |max: u32| match value.as_integer() {
+                Some(n) => count(name, n, max).map_err(failure::err_msg),
+                None => Err(format_err!(
+                    "{} should be from 1 to {}, not {}",
+                    name,
+                    max,
+                    value
+                )),
+            };
+            match name.as_str() {
+                "quirks" => profile.quirks = Some(text()?.parse().map_err(failure::err_msg)?),
+                "tickrate" => profile.tickrate = Some(number(MAX_TICKRATE)?),
+                "background" => {
+                    profile.background = Some(text()?.parse().map_err(failure::err_msg)?)
+                }
+                "fill" => profile.fill = Some(text()?.parse().map_err(failure::err_msg)?),
+                "scale" => profile.scale = Some(number(MAX_SCALE)?),
+                "audio" => match *value {
+                    Value::Boolean(audio) => profile.audio = Some(audio),
+                    _ => bail!("audio should be true or false, not {}", value),
+                },
+                "frontend" => profile.frontend = Some(text()?.parse().map_err(failure::err_msg)?),
+                "keys" => {
+                    let keys = match *value {
+                        Value::Table(ref keys) => keys,
+                        _ => bail!("keys should be a table of keypad keys"),
+                    };
+                    for (key, name) in keys {
+                        let key = match u8::from_str_radix(key, 16) {
+                            Ok(key) if key < 16 => key,
+                            _ => bail!("no keypad key '{}', they're 0 to F", key),
+                        };
+                        match *name {
+                            Value::String(ref name) => profile.keys.insert(key, name.clone()),
+                            _ => bail!("keys should be key names, not {}", name),
+                        };
+                    }
+                }
+                _ => bail!("unknown setting '{}'", name),
+            }
+        }
+        Ok(profile)
+    }
+}
+
+///The whole config file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    pub defaults: Profile,
+    ///by file name or SHA-1
+    pub roms: BTreeMap<String, Profile>,
+}
+
+impl Config {
+    ///where the user's config lives
+    pub fn user_path() -> Option<PathBuf> {
+        dir().map(|dir| dir.join("config.toml"))
+    }
+
+    ///the user's config, empty if they haven't got one
+    pub fn load_user() -> Result<Self, failure::Error> {
+        match Config::user_path() {
+            Some(ref path) if path.exists() => Config::load(path),
+            _ => Ok(Config::default()),
+        }
+    }
+
+    ///the config in the TOML file at `path`
+    pub fn load(path: &Path) -> Result<Self, failure::Error> {
+        let text =
+            fs::read_to_string(path).map_err(|e| format_err!("{}: {}", path.display(), e))?;
+        Config::parse(&text).map_err(|e| format_err!("{}: {}", path.display(), e))
+    }
+
+    ///a config from TOML text
+    pub fn parse(text: &str) -> Result<Self, failure::Error> {
+        let value: Value = text
+            .parse()
+            .map_err(|e| format_err!("bad config TOML: {}", e))?;
+        let mut config = Config::default();
+        let top = match value {
+            Value::Table(top) => top,
+            _ => bail!("bad config TOML"),
+        };
+        for (section, value) in &top {
+            match (section.as_str(), value) {
+                ("defaults", Value::Table(table)) => {
+                    config.defaults =
+                        Profile::from_toml(table).map_err(|e| format_err!("[defaults]: {}", e))?
+                }
+                ("roms", Value::Table(roms)) => {
+                    for (name, value) in roms {
+                        let profile = match *value {
+                            Value::Table(ref table) => Profile::from_toml(table),
+                            _ => Err(format_err!("should be a section")),
+                        }
+                        .map_err(|e| format_err!("[roms.{}]: {}", name, e))?;
+                        config.roms.insert(name.clone(), profile);
+                    }
+                }
+                _ => bail!("unknown section '{}'", section),
+            }
+        }
+        Ok(config)
+    }
+
+    ///the section for the ROM at `path`, with its name: the one for its
+    ///SHA-1, over the one for its file name
+    pub fn rom(&self, path: &Path, rom: &[u8]) -> Option<(String, Profile)> {
+        let by_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| self.roms.get(name).map(|profile| (name, profile)));
+        let sha1 = rom_sha1(rom);
+        let by_sha1 = self
+            .roms
+            .iter()
+            .find(|&(key, _)| key.to_lowercase() == sha1);
+        match (by_sha1, by_name) {
+            (Some((key, profile)), Some((_, fallback))) => {
+                Some((format!("roms.{}", key), profile.clone().or(fallback)))
+            }
+            (Some((key, profile)), None) => Some((format!("roms.{}", key), profile.clone())),
+            (None, Some((name, profile))) => Some((format!("roms.{}", name), profile.clone())),
+            (None, None) => None,
+        }
+    }
+}
+
+///The settings a run ends up with, and where each came from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Effective {
+    pub quirks: Quirks,
+    pub tickrate: u32,
+    pub palette: Palette,
+    ///keyboard key names by keypad key
+    pub keys: [String; 16],
+    pub scale: u32,
+    pub audio: bool,
+    pub frontend: Frontend,
+    ///where each setting came from, by its name in the config file
+    pub sources: BTreeMap<&'static str, String>,
+}
+
+///The emulator's own settings, keys 0 to F on the keyboard's 0 to F
+impl Default for Effective {
+    fn default() -> Self {
+        let names = [
+            "quirks",
+            "tickrate",
+            "background",
+            "fill",
+            "keys",
+            "scale",
+            "audio",
+            "frontend",
+        ];
+        let mut keys: [String; 16] = Default::default();
+        for (key, name) in keys.iter_mut().enumerate() {
+            *name = format!("{:X}", key);
+        }
+        Effective {
+            quirks: Quirks::default(),
+            tickrate: TICKS_PER_FRAME,
+            palette: Palette::default(),
+            keys,
+            scale: DEFAULT_SCALE,
+            audio: true,
+            frontend: Frontend::default(),
+            sources: names
+                .iter()
+                .map(|&name| (name, "built in".to_string()))
+                .collect(),
+        }
+    }
+}
+
+impl Effective {
+    fn set(&mut self, name: &'static str, source: &str) {
+        self.sources.insert(name, source.to_string());
+    }
+
+    ///take whatever `profile` sets, from `source`
+    pub fn apply(&mut self, profile: &Profile, source: &str) {
+        if let Some(quirks) = profile.quirks {
+            self.quirks = quirks;
+            self.set("quirks", source);
+        }
+        if let Some(tickrate) = profile.tickrate {
+            self.tickrate = tickrate;
+            self.set("tickrate", source);
+        }
+        if let Some(background) = profile.background {
+            self.palette.background = background;
+            self.set("background", source);
+        }
+        if let Some(fill) = profile.fill {
+            self.palette.fill = fill;
+            self.set("fill", source);
+        }
+        for (&key, name) in &profile.keys {
+            self.keys[usize::from(key)] = name.clone();
+            self.set("keys", source);
+        }
+        if let Some(scale) = profile.scale {
+            self.scale = scale;
+            self.set("scale", source);
+        }
+        if let Some(audio) = profile.audio {
+            self.audio = audio;
+            self.set("audio", source);
+        }
+        if let Some(frontend) = profile.frontend {
+            self.frontend = frontend;
+            self.set("frontend", source);
+        }
+    }
+
+    ///take whatever Octo `options` set, from `source`. Quirk flags they
+    ///leave out are Octo's, all off, as they were when the options were
+    ///saved, and without any quirk flags the quirks are left alone
+    pub fn apply_options(&mut self, options: &Options, source: &str) {
+        let octo = Quirks {
+            stack_depth: self.quirks.stack_depth,
+            ..Quirks::octo()
+        };
+        self.apply(
+            &Profile {
+                quirks: if options.has_quirks() {
+                    Some(options.quirks(octo))
+                } else {
+                    None
+                },
+                tickrate: options.tickrate,
+                background: options.background_color,
+                fill: options.fill_color,
+                ..Profile::default()
+            },
+            source,
+        );
+    }
+}
+
+///As a `[defaults]` section, with where each setting came from
+impl fmt::Display for Effective {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let source = |name: &str| self.sources.get(name).map_or("", |s| s.as_str());
+        writeln!(f, "[defaults]")?;
+        writeln!(f, "quirks = \"{}\" # {}", self.quirks, source("quirks"))?;
+        writeln!(f, "tickrate = {} # {}", self.tickrate, source("tickrate"))?;
+        writeln!(
+            f,
+            "background = \"{}\" # {}",
+            self.palette.background,
+            source("background")
+        )?;
+        writeln!(f, "fill = \"{}\" # {}", self.palette.fill, source("fill"))?;
+        writeln!(f, "scale = {} # {}", self.scale, source("scale"))?;
+        writeln!(f, "audio = {} # {}", self.audio, source("audio"))?;
+        writeln!(
+            f,
+            "frontend = \"{}\" # {}",
+            self.frontend,
+            source("frontend")
+        )?;
+        writeln!(f)?;
+        writeln!(f, "[defaults.keys] # {}", source("keys"))?;
+        for (key, name) in self.keys.iter().enumerate() {
+            writeln!(f, "{:X} = \"{}\"", key, name)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tui::Glyphs;
+
+    const CONFIG: &str = r##"
+        [defaults]
+        quirks = "vip"
+        scale = 4
+        [defaults.keys]
+        5 = "W"
+
+        [roms.BRIX]
+        tickrate = 15
+        frontend = "braille"
+        fill = "#00FF00"
+
+        [roms.b9272ae1acdaaa79ab649f6b48b72088ca2b1d74]
+        audio = false
+        [roms.b9272ae1acdaaa79ab649f6b48b72088ca2b1d74.keys]
+        a = "Space"
+    "##;
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse(CONFIG).unwrap();
+        assert_eq!(config.defaults.quirks, Some(Quirks::vip()));
+        assert_eq!(config.defaults.keys[&5], "W");
+        assert_eq!(config.roms["BRIX"].tickrate, Some(15));
+        assert_eq!(config.roms["BRIX"].fill, Some(Rgb(0, 0xFF, 0)));
+        assert_eq!(
+            config.roms["BRIX"].frontend,
+            Some(Frontend::Tui(Glyphs::Braille))
+        );
+
+        //MAZE's SHA-1, under another name
+        let maze = fs::read("roms/MAZE").unwrap();
+        let (section, profile) = config.rom(Path::new("maze.ch8"), &maze).unwrap();
+        assert_eq!(section, "roms.b9272ae1acdaaa79ab649f6b48b72088ca2b1d74");
+        assert_eq!(profile.audio, Some(false));
+        assert_eq!(profile.keys[&0xA], "Space");
+        assert!(config.rom(Path::new("roms/BRIX"), &maze).is_some());
+        assert_eq!(config.rom(Path::new("roms/PONG"), b"\x12\x00"), None);
+    }
+
+    #[test]
+    fn test_precedence() {
+        let config = Config::parse(CONFIG).unwrap();
+        let mut effective = Effective::default();
+        effective.apply(&config.defaults, "config [defaults]");
+        effective.apply_options(
+            &Options::parse(r#"{"tickrate": 30, "jumpQuirks": true}"#).unwrap(),
+            "the ROM's options",
+        );
+        let (section, rom) = config.rom(Path::new("roms/BRIX"), &[]).unwrap();
+        effective.apply(&rom, &section);
+        effective.apply(
+            &Profile {
+                scale: Some(2),
+                ..Profile::default()
+            },
+            "the command line",
+        );
+
+        //Octo's options are Octo's quirks, but Octo has no stack depth
+        assert_eq!(
+            effective.quirks,
+            Quirks {
+                jump: true,
+                stack_depth: 12,
+                ..Quirks::octo()
+            }
+        );
+        assert_eq!(effective.tickrate, 15);
+        assert_eq!(effective.scale, 2);
+        assert_eq!(effective.keys[5], "W");
+        assert_eq!(effective.keys[6], "6");
+        let text = effective.to_string();
+        assert!(text.contains("tickrate = 15 # roms.BRIX\n"));
+        assert!(text.contains("quirks = \"shift=0 load_store=0 clip=0 jump=1"));
+        assert!(text.contains("# the ROM's options\n"));
+        assert!(text.contains("scale = 2 # the command line\n"));
+        assert!(text.contains("audio = true # built in\n"));
+        assert!(text.contains("frontend = \"braille\" # roms.BRIX\n"));
+        assert!(text.contains("[defaults.keys] # config [defaults]\n"));
+        //what it prints is a config file in its own right
+        let printed = Config::parse(&text).unwrap();
+        assert_eq!(printed.defaults.tickrate, Some(15));
+        assert_eq!(printed.defaults.keys[&5], "W");
+        assert_eq!(
+            printed.defaults.frontend,
+            Some(Frontend::Tui(Glyphs::Braille))
+        );
+    }
+
+    #[test]
+    fn test_partial_options() {
+        let mut effective = Effective::default();
+        effective.apply_options(
+            &Options::parse(r#"{"jumpQuirks": true}"#).unwrap(),
+            "the ROM's options",
+        );
+        assert_eq!(
+            effective.quirks,
+            Quirks {
+                jump: true,
+                ..Quirks::octo()
+            }
+        );
+        //no quirk flags at all leaves the quirks as they were
+        let mut effective = Effective::default();
+        effective.apply_options(
+            &Options::parse(r#"{"tickrate": 7}"#).unwrap(),
+            "the ROM's options",
+        );
+        assert_eq!(effective.quirks, Quirks::default());
+        assert_eq!(effective.tickrate, 7);
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |text: &str| Config::parse(text).unwrap_err().to_string();
+        assert!(error("[defaults]\nspeed = 3").contains("[defaults]: unknown setting 'speed'"));
+        assert!(error("[roms.X]\nscale = 0").contains("[roms.X]: scale should be from 1 to 64"));
+        assert!(error("[defaults.keys]\n10 = \"X\"").contains("no keypad key '10'"));
+        assert!(error("[defaults]\nquirks = \"fast\"").contains("unknown quirks profile"));
+        assert!(error("[defaults]\nfrontend = \"x11\"").contains("unknown frontend 'x11'"));
+        assert!(error("[window]").contains("unknown section 'window'"));
+        assert!(error("[defaults").starts_with("bad config TOML"));
+    }
+}
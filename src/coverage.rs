@@ -0,0 +1,265 @@
+//! Code coverage of ROM execution
+//!
+//! Records how often each address is executed and, for the skip
+//! instructions, how often each way was taken. The result is written as an
+//! lcov tracefile against the assembler source when a symbol map has line
+//! records, or otherwise against an annotated listing of the ROM's code.
+
+use cfg::ControlFlowGraph;
+use cpu::Cpu;
+use disasm::ROM_START;
+use instruction::{Instruction, INSTRUCTION_WIDTH};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use symbols::SymbolMap;
+
+///How often each way out of a skip instruction was taken
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Branches {
+    ///the next instruction ran
+    pub fallthrough: u64,
+    ///the next instruction was skipped
+    pub skipped: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct Coverage {
+    hits: BTreeMap<u16, u64>,
+    branches: BTreeMap<u16, Branches>,
+}
+
+///One line of an lcov file
+struct Line {
+    hits: Option<u64>,
+    ///one entry per skip on the line, `None` if it never ran
+    branches: Vec<Option<Branches>>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    ///run a single instruction on `cpu` and record it
+    pub fn tick(&mut self, cpu: &mut Cpu) {
+        let pc = cpu.pc();
+        cpu.tick();
+        self.record(cpu, pc);
+    }
+
+    ///record the instruction at `pc`, call after `cpu` has executed it
+    pub fn record(&mut self, cpu: &Cpu, pc: u16) {
+        *self.hits.entry(pc).or_insert(0) += 1;
+        match Instruction::fetch(cpu.memory(), usize::from(pc)) {
+            Some(instruction) if instruction.is_skip() => {
+                let branches = self.branches.entry(pc).or_default();
+                if cpu.pc() == pc.wrapping_add(2 * INSTRUCTION_WIDTH) {
+                    branches.skipped += 1;
+                } else {
+                    branches.fallthrough += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    ///times the instruction at `address` ran
+    pub fn hits(&self, address: u16) -> u64 {
+        self.hits.get(&address).cloned().unwrap_or(0)
+    }
+
+    ///the outcomes of the skip at `address`, if it ran
+    pub fn branches(&self, address: u16) -> Option<Branches> {
+        self.branches.get(&address).cloned()
+    }
+
+    ///every instruction in `rom` that was traced statically or executed
+    fn instructions(&self, rom: &[u8]) -> BTreeMap<u16, Instruction> {
+        let graph = ControlFlowGraph::new(rom);
+        let mut instructions: BTreeMap<u16, Instruction> = graph
+            .blocks
+            .values()
+            .flat_map(|block| block.instructions.iter().cloned())
+            .collect();
+        for &address in self.hits.keys() {
+            if address < ROM_START {
+                continue;
+            }
+            if let Some(instruction) = Instruction::fetch(rom, usize::from(address - ROM_START)) {
+                instructions.insert(address, instruction);
+            }
+        }
+        instructions
+    }
+
+    ///the code in `rom`, one instruction per line, prefixed with its hit
+    ///count in the style of gcov, `#####` marking lines that never ran
+    pub fn listing(&self, rom: &[u8]) -> String {
+        let mut out = String::new();
+        for (&address, instruction) in &self.instructions(rom) {
+            let hits = match self.hits(address) {
+                0 => "#####".to_string(),
+                hits => hits.to_string(),
+            };
+            let _ = write!(out, "{:>9}: {:03X}: {}", hits, address, instruction);
+            if instruction.is_skip() {
+                let branches = self.branches(address).unwrap_or_default();
+                let _ = write!(
+                    out,
+                    "  ; skipped {} of {}",
+                    branches.skipped,
+                    branches.skipped + branches.fallthrough
+                );
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    ///an lcov tracefile, against the source lines in `symbols` if it has
+    ///any, otherwise against `listing` as written by `Coverage::listing`
+    pub fn lcov(&self, rom: &[u8], symbols: &SymbolMap, listing: &str) -> String {
+        let mut files: BTreeMap<&str, BTreeMap<usize, Line>> = BTreeMap::new();
+        for (line_index, (&address, instruction)) in self.instructions(rom).iter().enumerate() {
+            let (file, line) = if symbols.lines.is_empty() {
+                (listing, line_index + 1)
+            } else {
+                match symbols.lines.get(&address) {
+                    Some(location) => (location.file.as_str(), location.line),
+                    None => continue,
+                }
+            };
+            let entry = files.entry(file).or_default().entry(line).or_insert(Line {
+                hits: None,
+                branches: Vec::new(),
+            });
+            //several instructions on one line, e.g. from a macro
+            let hits = self.hits.get(&address).cloned();
+            entry.hits = entry.hits.max(hits).or(Some(0));
+            if instruction.is_skip() {
+                entry.branches.push(self.branches(address));
+            }
+        }
+
+        let mut out = String::new();
+        for (file, lines) in &files {
+            let _ = writeln!(out, "TN:\nSF:{}", file);
+            let (mut found, mut hit, mut branches_found, mut branches_hit) = (0, 0, 0, 0);
+            for (number, line) in lines {
+                let hits = line.hits.unwrap_or(0);
+                for (block, branches) in line.branches.iter().enumerate() {
+                    for (branch, taken) in
+                        [branches.map(|b| b.fallthrough), branches.map(|b| b.skipped)]
+                            .iter()
+                            .enumerate()
+                    {
+                        let taken = match *taken {
+                            Some(taken) => {
+                                if taken > 0 {
+                                    branches_hit += 1;
+                                }
+                                taken.to_string()
+                            }
+                            None => "-".to_string(),
+                        };
+                        branches_found += 1;
+                        let _ = writeln!(out, "BRDA:{},{},{},{}", number, block, branch, taken);
+                    }
+                }
+                let _ = writeln!(out, "DA:{},{}", number, hits);
+                found += 1;
+                if hits > 0 {
+                    hit += 1;
+                }
+            }
+            let _ = writeln!(out, "BRF:{}\nBRH:{}", branches_found, branches_hit);
+            let _ = writeln!(out, "LF:{}\nLH:{}\nend_of_record", found, hit);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use symbols::SourceLocation;
+
+    //0x200: mov v0,1
+    //0x202: skeq v0,1
+    //0x204: cls
+    //0x206: jmp 0x206
+    const ROM: [u8; 8] = [0x60, 0x01, 0x30, 0x01, 0x00, 0xE0, 0x12, 0x06];
+
+    fn run(ticks: usize) -> Coverage {
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&ROM).unwrap();
+        let mut coverage = Coverage::new();
+        for _ in 0..ticks {
+            coverage.tick(&mut cpu);
+        }
+        coverage
+    }
+
+    #[test]
+    fn test_hits_and_branches() {
+        let coverage = run(4);
+        assert_eq!(coverage.hits(0x200), 1);
+        assert_eq!(coverage.hits(0x204), 0);
+        assert_eq!(coverage.hits(0x206), 2);
+        assert_eq!(
+            coverage.branches(0x202),
+            Some(Branches {
+                fallthrough: 0,
+                skipped: 1
+            })
+        );
+        assert_eq!(coverage.branches(0x200), None);
+    }
+
+    #[test]
+    fn test_listing() {
+        let listing = run(4).listing(&ROM);
+        assert_eq!(
+            listing,
+            "        1: 200: mov v0,0x01\n\
+             \x20       1: 202: skeq v0,0x01  ; skipped 1 of 1\n\
+             \x20   #####: 204: cls\n\
+             \x20       2: 206: jmp 0x206\n"
+        );
+    }
+
+    #[test]
+    fn test_lcov_against_listing() {
+        let lcov = run(4).lcov(&ROM, &SymbolMap::new(), "rom.lst");
+        assert!(lcov.starts_with("TN:\nSF:rom.lst\n"));
+        assert!(lcov.contains("BRDA:2,0,0,0\nBRDA:2,0,1,1\nDA:2,1\n"));
+        assert!(lcov.contains("DA:3,0\n"));
+        assert!(lcov.contains("BRF:2\nBRH:1\nLF:4\nLH:3\nend_of_record\n"));
+    }
+
+    #[test]
+    fn test_lcov_against_source() {
+        let mut symbols = SymbolMap::new();
+        for (address, line) in &[(0x200, 3), (0x202, 4), (0x204, 4), (0x206, 9)] {
+            let location = SourceLocation {
+                file: "game.8o".into(),
+                line: *line,
+            };
+            symbols.lines.insert(*address, location);
+        }
+        let lcov = run(4).lcov(&ROM, &symbols, "unused.lst");
+        assert!(lcov.contains("SF:game.8o\n"));
+        //line 4 ran, even though the `cls` on it didn't
+        assert!(lcov.contains("DA:4,1\n"));
+        assert!(lcov.contains("DA:9,2\n"));
+        assert!(lcov.contains("LF:3\nLH:3\n"));
+        assert!(!lcov.contains("unused.lst"));
+    }
+
+    #[test]
+    fn test_unexecuted_skip() {
+        let lcov = Coverage::new().lcov(&ROM, &SymbolMap::new(), "rom.lst");
+        assert!(lcov.contains("BRDA:2,0,0,-\nBRDA:2,0,1,-\n"));
+        assert!(lcov.contains("BRH:0\n"));
+    }
+}
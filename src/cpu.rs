@@ -1,7 +1,14 @@
 //! The CHIP-8 CPU emulation and instruction set
 
+use failure;
+use fault::{Fault, FaultPolicy};
+use instruction::{Instruction, INSTRUCTION_WIDTH};
+use quirks::Quirks;
 use rand;
+use routines::Routines;
+use semihost::{HostCall, Semihost};
 use std;
+use std::io::Write;
 
 ///The core CPU registers and memory
 pub struct Cpu {
@@ -12,19 +19,42 @@ pub struct Cpu {
     i: u16,
     ///actually 12 bits, pointer into `memory`
     pc: u16,
-    sp: Vec<u16>,
+    ///return addresses pushed by `jsr`, the first `sp` of them in use
+    stack: [u16; MAX_STACK_DEPTH],
+    ///stack pointer, the number of return addresses on `stack`
+    sp: u8,
     key: [bool; 16],
     unknown_key: bool,
     screen: [bool; SCREEN_WIDTH * SCREEN_HEIGHT],
     memory: [u8; 4096],
+    ///xorshift state for `rand`, kept here rather than in a `rand` generator
+    ///so that a seed gives the same numbers whatever version of `rand` is
+    ///built against
+    rng: u64,
+    quirks: Quirks,
+    ///host calls through `0NNN`, when turned on
+    semihost: Option<Semihost>,
+    ///native stand-ins for `0NNN` machine code
+    routines: Routines,
+    fault_policy: FaultPolicy,
+    ///what halted the cpu, if anything has
+    fault: Option<Fault>,
 }
 
 pub const SCREEN_WIDTH: usize = 64;
 pub const SCREEN_HEIGHT: usize = 32;
 pub const TIMER_FREQUENCY: usize = 60;
+///the deepest call stack any platform has, see `Quirks::stack_depth`
+pub const MAX_STACK_DEPTH: usize = 16;
+///the most ROM that fits between 0x200 and the end of memory
+pub const MAX_ROM_SIZE: usize = MEMORY_SIZE - INITIAL_PC as usize;
 
 const INITIAL_PC: u16 = 0x200;
-const INSTRUCTION_WIDTH: u16 = 2;
+const MEMORY_SIZE: usize = 4096;
+///PC and I are 12 bits, addressing the 4K of memory
+const ADDRESS_MASK: u16 = 0x0FFF;
+///bumped whenever the layout written by `save_state` changes
+const STATE_VERSION: u8 = 2;
 const FONTSET_ADDRESS: u16 = 0x50;
 const FONTSET: [u8; 5 * 16] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -53,12 +83,20 @@ impl Default for Cpu {
             sound: 0,
             i: 0,
             pc: INITIAL_PC,
-            sp: Vec::new(),
+            stack: [0; MAX_STACK_DEPTH],
+            sp: 0,
             key: [false; 16],
             unknown_key: false,
             screen: [false; SCREEN_WIDTH * SCREEN_HEIGHT],
             memory: [0; 4096],
+            rng: 0,
+            quirks: Quirks::default(),
+            semihost: None,
+            routines: Routines::builtin(),
+            fault_policy: FaultPolicy::default(),
+            fault: None,
         };
+        cpu.seed(rand::random());
 
         let fontset_range =
             usize::from(FONTSET_ADDRESS)..(usize::from(FONTSET_ADDRESS) + FONTSET.len());
@@ -73,9 +111,86 @@ impl Cpu {
         Default::default()
     }
 
+    ///new cpu interpreting ambiguous instructions as `quirks` says
+    pub fn with_quirks(quirks: Quirks) -> Self {
+        let mut cpu = Cpu::new();
+        cpu.quirks = quirks;
+        cpu
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    ///reseed the generator behind `rand`, for reproducible runs
+    pub fn seed(&mut self, seed: u64) {
+        //xorshift gets stuck at zero
+        self.rng = if seed == 0 {
+            0x9E37_79B9_7F4A_7C15
+        } else {
+            seed
+        };
+    }
+
+    ///treat the `0NNN` calls described in `semihost` as host calls
+    pub fn enable_semihosting(&mut self) {
+        self.semihost.get_or_insert_with(Semihost::new);
+    }
+
+    pub fn semihost(&self) -> Option<&Semihost> {
+        self.semihost.as_ref()
+    }
+
+    pub fn semihost_mut(&mut self) -> Option<&mut Semihost> {
+        self.semihost.as_mut()
+    }
+
+    ///the code the ROM exited with through semihosting, if it has
+    pub fn exit_code(&self) -> Option<u8> {
+        self.semihost.as_ref().and_then(|host| host.exit_code)
+    }
+
+    pub fn routines(&self) -> &Routines {
+        &self.routines
+    }
+
+    ///where to register routines for `0NNN` calls
+    pub fn routines_mut(&mut self) -> &mut Routines {
+        &mut self.routines
+    }
+
+    pub fn fault_policy(&self) -> FaultPolicy {
+        self.fault_policy
+    }
+
+    pub fn set_fault_policy(&mut self, policy: FaultPolicy) {
+        self.fault_policy = policy;
+    }
+
+    ///the fault that halted the cpu, if one has
+    pub fn fault(&self) -> Option<Fault> {
+        self.fault
+    }
+
+    ///carry on after a fault halted the cpu, e.g. once a debugger has
+    ///fixed things up
+    pub fn clear_fault(&mut self) {
+        self.fault = None;
+    }
+
+    ///where the generator behind `rand` is up to, which `seed` restores
+    ///unless it is zero
+    pub fn rng_state(&self) -> u64 {
+        self.rng
+    }
+
     ///lookup a mutable key register
     pub fn key_mut(&mut self, keycode: u8) -> &mut bool {
-        if keycode < 0x0F {
+        if keycode <= 0x0F {
             &mut self.key[usize::from(keycode)]
         } else {
             warn!("unexpected keycode {}", keycode);
@@ -83,11 +198,18 @@ impl Cpu {
         }
     }
 
-    ///copies the rom into memory
-    pub fn load_rom(&mut self, rom: &[u8]) {
-        for (i, byte) in rom.iter().enumerate() {
-            *self.mem_mut(INITIAL_PC + i as u16) = *byte;
+    ///copies the rom into memory, failing if it's more than `MAX_ROM_SIZE`
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), failure::Error> {
+        if rom.len() > MAX_ROM_SIZE {
+            bail!(
+                "rom is {} bytes, more than the {} that fit in memory",
+                rom.len(),
+                MAX_ROM_SIZE
+            );
         }
+        let start = usize::from(INITIAL_PC);
+        self.memory[start..start + rom.len()].copy_from_slice(rom);
+        Ok(())
     }
 
     ///decrements timers, returns true if the buzzer needs to sound
@@ -107,68 +229,260 @@ impl Cpu {
 
     ///runs a single instruction, from PC
     pub fn tick(&mut self) {
-        let opcode = (u16::from(self.mem(self.pc)) << 8) + u16::from(self.mem(self.pc + 1));
-        let address = opcode & 0x0FFF;
-        let value = (opcode & 0x00FF) as u8;
-        let reg = ((opcode >> 8) & 0x000F) as u8;
-        let x = ((opcode >> 8) & 0x000F) as u8;
-        let y = ((opcode >> 4) & 0x000F) as u8;
-        let n = (opcode & 0x000F) as u8;
-        match opcode & 0xF000 {
-            0x0000 => match opcode {
-                0x00E0 => self.cls(),
-                0x00EE => self.rts(),
-                _ => error!("unmatched opcode! {}", opcode),
-            },
-            0x1000 => self.jmp(address),
-            0x2000 => self.jsr(address),
-            0x3000 => self.skeq_const(reg, value),
-            0x4000 => self.skne_const(reg, value),
-            0x5000 => self.skeq_reg(x, y),
-            0x6000 => self.mov_const(reg, value),
-            0x7000 => self.add_const(reg, value),
-            0x8000 => match opcode & 0x000F {
-                0x0000 => self.mov_reg(x, y),
-                0x0001 => self.or_reg(x, y),
-                0x0002 => self.and_reg(x, y),
-                0x0003 => self.xor_reg(x, y),
-                0x0004 => self.add_reg(x, y),
-                0x0005 => self.sub_reg(x, y),
-                0x0006 => self.shr(x, y),
-                0x0007 => self.rsb(x, y),
-                0x000E => self.shl(x, y),
-                _ => error!("unmatched opcode! {}", opcode),
-            },
-            0x9000 => self.skne_reg(x, y),
-            0xA000 => self.mvi(address),
-            0xB000 => self.jmi(address),
-            0xC000 => self.rand(reg, value),
-            0xD000 => self.sprite(x, y, n),
-            0xE000 => match opcode & 0x00FF {
-                0x009E => self.skpr(x),
-                0x00A1 => self.skup(x),
-                _ => error!("unmatched opcode! {}", opcode),
-            },
-            0xF000 => match opcode & 0x00FF {
-                0x0007 => self.gdelay(x),
-                0x000A => self.key(x),
-                0x0015 => self.sdelay(x),
-                0x0018 => self.ssound(x),
-                0x001E => self.adi(x),
-                0x0029 => self.font(x),
-                0x0033 => self.bcd(x),
-                0x0055 => self.str(x),
-                0x0065 => self.ldr(x),
-                _ => error!("unmatched opcode! {}", opcode),
+        if self.exit_code().is_some() || self.fault.is_some() {
+            return;
+        }
+        let opcode =
+            (u16::from(self.mem(self.pc)) << 8) + u16::from(self.mem(self.pc.wrapping_add(1)));
+        match Instruction::decode(opcode) {
+            Instruction::Cls => self.cls(),
+            Instruction::Rts => self.rts(),
+            Instruction::Jmp(address) => self.jmp(address),
+            Instruction::Jsr(address) => self.jsr(address),
+            Instruction::SkeqConst(reg, value) => self.skeq_const(reg, value),
+            Instruction::SkneConst(reg, value) => self.skne_const(reg, value),
+            Instruction::SkeqReg(x, y) => self.skeq_reg(x, y),
+            Instruction::MovConst(reg, value) => self.mov_const(reg, value),
+            Instruction::AddConst(reg, value) => self.add_const(reg, value),
+            Instruction::MovReg(x, y) => self.mov_reg(x, y),
+            Instruction::OrReg(x, y) => self.or_reg(x, y),
+            Instruction::AndReg(x, y) => self.and_reg(x, y),
+            Instruction::XorReg(x, y) => self.xor_reg(x, y),
+            Instruction::AddReg(x, y) => self.add_reg(x, y),
+            Instruction::SubReg(x, y) => self.sub_reg(x, y),
+            Instruction::Shr(x, y) => self.shr(x, y),
+            Instruction::Rsb(x, y) => self.rsb(x, y),
+            Instruction::Shl(x, y) => self.shl(x, y),
+            Instruction::SkneReg(x, y) => self.skne_reg(x, y),
+            Instruction::Mvi(address) => self.mvi(address),
+            Instruction::Jmi(address) => self.jmi(address),
+            Instruction::Rand(reg, value) => self.rand(reg, value),
+            Instruction::Sprite(x, y, n) => self.sprite(x, y, n),
+            Instruction::Skpr(x) => self.skpr(x),
+            Instruction::Skup(x) => self.skup(x),
+            Instruction::Gdelay(x) => self.gdelay(x),
+            Instruction::Key(x) => self.key(x),
+            Instruction::Sdelay(x) => self.sdelay(x),
+            Instruction::Ssound(x) => self.ssound(x),
+            Instruction::Adi(x) => self.adi(x),
+            Instruction::Font(x) => self.font(x),
+            Instruction::Bcd(x) => self.bcd(x),
+            Instruction::Str(x) => self.str(x),
+            Instruction::Ldr(x) => self.ldr(x),
+            Instruction::Sys(address) => match HostCall::decode(address) {
+                Some(call) if self.semihost.is_some() => self.host_call(call),
+                _ => self.sys(address),
             },
-            _ => error!("unmatched opcode! {}", opcode),
+            Instruction::Unknown(_) => self.raise(Fault::UnknownOpcode {
+                pc: self.pc,
+                opcode,
+            }),
         }
+        self.pc &= ADDRESS_MASK;
+        self.i &= ADDRESS_MASK;
     }
 
     pub fn screen(&self) -> &[bool; SCREEN_WIDTH * SCREEN_HEIGHT] {
         &self.screen
     }
 
+    pub fn screen_mut(&mut self) -> &mut [bool; SCREEN_WIDTH * SCREEN_HEIGHT] {
+        &mut self.screen
+    }
+
+    ///the program counter
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc & ADDRESS_MASK;
+    }
+
+    ///the index register
+    pub fn i(&self) -> u16 {
+        self.i
+    }
+
+    pub fn set_i(&mut self, i: u16) {
+        self.i = i & ADDRESS_MASK;
+    }
+
+    ///registers V0 to VF
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.register
+    }
+
+    pub fn registers_mut(&mut self) -> &mut [u8; 16] {
+        &mut self.register
+    }
+
+    ///the delay timer
+    pub fn delay(&self) -> u8 {
+        self.delay
+    }
+
+    pub fn set_delay(&mut self, delay: u8) {
+        self.delay = delay;
+    }
+
+    ///the sound timer
+    pub fn sound(&self) -> u8 {
+        self.sound
+    }
+
+    pub fn set_sound(&mut self, sound: u8) {
+        self.sound = sound;
+    }
+
+    ///return addresses pushed by `jsr`, innermost last
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..usize::from(self.sp)]
+    }
+
+    ///the stack pointer, how many return addresses are on the stack
+    pub fn sp(&self) -> u8 {
+        self.sp
+    }
+
+    ///replace the return addresses on the stack, innermost last
+    ///
+    ///Panics if there are more than `MAX_STACK_DEPTH`.
+    pub fn set_stack(&mut self, stack: &[u16]) {
+        assert!(stack.len() <= MAX_STACK_DEPTH, "stack too deep");
+        for (slot, &address) in self.stack.iter_mut().zip(stack) {
+            *slot = address & ADDRESS_MASK;
+        }
+        self.sp = stack.len() as u8;
+    }
+
+    ///the whole address space
+    pub fn memory(&self) -> &[u8; 4096] {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut [u8; 4096] {
+        &mut self.memory
+    }
+
+    ///the keypad as a bitmap, bit N set while key N is down
+    pub fn keys(&self) -> u16 {
+        self.key
+            .iter()
+            .enumerate()
+            .filter(|&(_, &down)| down)
+            .fold(0, |keys, (n, _)| keys | 1 << n)
+    }
+
+    ///set the whole keypad from a bitmap, as returned by `keys`
+    pub fn set_keys(&mut self, keys: u16) {
+        for (n, key) in self.key.iter_mut().enumerate() {
+            *key = keys & (1 << n) != 0;
+        }
+    }
+
+    ///append everything needed to resume execution later to `out`
+    pub fn save_state(&self, out: &mut Vec<u8>) {
+        out.push(STATE_VERSION);
+        out.extend_from_slice(&self.register);
+        out.push(self.delay);
+        out.push(self.sound);
+        out.extend_from_slice(&self.i.to_be_bytes());
+        out.extend_from_slice(&self.pc.to_be_bytes());
+        out.push(self.sp);
+        for address in self.stack() {
+            out.extend_from_slice(&address.to_be_bytes());
+        }
+        out.extend_from_slice(&self.keys().to_be_bytes());
+        out.extend(self.screen.iter().map(|&pixel| pixel as u8));
+        out.extend_from_slice(&self.memory);
+        out.extend_from_slice(&self.rng.to_be_bytes());
+        let q = self.quirks;
+        out.extend(
+            [q.shift, q.load_store, q.clip, q.jump, q.vf_order]
+                .iter()
+                .map(|&quirk| quirk as u8),
+        );
+        out.push(q.stack_depth);
+    }
+
+    ///restore a cpu from the front of `input`, as written by `save_state`,
+    ///leaving `input` pointing after it
+    pub fn load_state(input: &mut &[u8]) -> Result<Self, failure::Error> {
+        fn take<'a>(input: &mut &'a [u8], n: usize) -> Result<&'a [u8], failure::Error> {
+            if input.len() < n {
+                bail!("save state is truncated");
+            }
+            let (head, tail) = input.split_at(n);
+            *input = tail;
+            Ok(head)
+        }
+        let u16_at = |bytes: &[u8]| u16::from_be_bytes([bytes[0], bytes[1]]);
+
+        let version = take(input, 1)?[0];
+        //version 1 predates the stack depth
+        if version != 1 && version != STATE_VERSION {
+            bail!("unsupported save state version {}", version);
+        }
+        let mut cpu = Cpu::new();
+        cpu.register.copy_from_slice(take(input, 16)?);
+        cpu.delay = take(input, 1)?[0];
+        cpu.sound = take(input, 1)?[0];
+        cpu.i = u16_at(take(input, 2)?) & ADDRESS_MASK;
+        cpu.pc = u16_at(take(input, 2)?) & ADDRESS_MASK;
+        let depth = take(input, 1)?[0];
+        if usize::from(depth) > MAX_STACK_DEPTH {
+            bail!("save state stack is {} deep", depth);
+        }
+        let stack: Vec<u16> = take(input, 2 * usize::from(depth))?
+            .chunks(2)
+            .map(u16_at)
+            .collect();
+        cpu.set_stack(&stack);
+        cpu.set_keys(u16_at(take(input, 2)?));
+        for (pixel, &byte) in cpu
+            .screen
+            .iter_mut()
+            .zip(take(input, SCREEN_WIDTH * SCREEN_HEIGHT)?)
+        {
+            *pixel = byte != 0;
+        }
+        cpu.memory.copy_from_slice(take(input, 4096)?);
+        let mut rng = [0; 8];
+        rng.copy_from_slice(take(input, 8)?);
+        cpu.rng = u64::from_be_bytes(rng);
+        let quirks = take(input, 5)?;
+        cpu.quirks = Quirks {
+            shift: quirks[0] != 0,
+            load_store: quirks[1] != 0,
+            clip: quirks[2] != 0,
+            jump: quirks[3] != 0,
+            vf_order: quirks[4] != 0,
+            ..Quirks::default()
+        };
+        if version > 1 {
+            let stack_depth = take(input, 1)?[0];
+            if stack_depth == 0 || usize::from(stack_depth) > MAX_STACK_DEPTH {
+                bail!("bad save state stack depth {}", stack_depth);
+            }
+            cpu.quirks.stack_depth = stack_depth;
+        }
+        Ok(cpu)
+    }
+
+    ///store an 8XYN result and its carry, borrow or shifted out bit in VF,
+    ///in the order the vf_order quirk asks for
+    fn set_result(&mut self, register_x_id: u8, result: u8, flag: bool) {
+        let flag = if flag { 0x01 } else { 0x00 };
+        if self.quirks.vf_order {
+            self.register[0x0F] = flag;
+            *self.reg_mut(register_x_id) = result;
+        } else {
+            *self.reg_mut(register_x_id) = result;
+            self.register[0x0F] = flag;
+        }
+    }
+
     ///convert an id to a register reference
     fn reg(&self, register: u8) -> u8 {
         if register <= 0x0F {
@@ -185,20 +499,73 @@ impl Cpu {
         panic!("unexpected register id {}", register)
     }
 
-    ///lookup a memory address
+    ///lookup a memory address, wrapping around the end of memory
     fn mem(&self, address: u16) -> u8 {
-        if address < 4096 {
-            return self.memory[usize::from(address)];
-        }
-        panic!("unexpected memory access at {}", address)
+        self.memory[usize::from(address & ADDRESS_MASK)]
     }
 
-    ///lookup a mutable memory address
+    ///lookup a mutable memory address, wrapping around the end of memory
     fn mem_mut(&mut self, address: u16) -> &mut u8 {
-        if address < 4096 {
-            return &mut self.memory[usize::from(address)];
+        &mut self.memory[usize::from(address & ADDRESS_MASK)]
+    }
+
+    ///deal with `fault` as the fault policy says
+    fn raise(&mut self, fault: Fault) {
+        match self.fault_policy {
+            FaultPolicy::Halt => {
+                error!("{}", fault);
+                self.fault = Some(fault);
+            }
+            FaultPolicy::Skip => {
+                warn!("{}, skipping it", fault);
+                self.pc = fault.pc().wrapping_add(INSTRUCTION_WIDTH);
+            }
+            FaultPolicy::Ignore => self.pc = fault.pc().wrapping_add(INSTRUCTION_WIDTH),
+        }
+    }
+
+    ///0x0NNN
+    ///call the routine registered for NNN
+    fn sys(&mut self, address: u16) {
+        match self.routines.take(address) {
+            Some(mut routine) => {
+                self.pc = self.pc.wrapping_add(INSTRUCTION_WIDTH);
+                routine(self);
+                self.routines.restore(address, routine);
+            }
+            None => self.raise(Fault::UnknownRoutine {
+                pc: self.pc,
+                address,
+            }),
+        }
+    }
+
+    ///0x0NNN with semihosting on
+    fn host_call(&mut self, call: HostCall) {
+        let pc = self.pc;
+        let register = self.register;
+        let host = match self.semihost {
+            Some(ref mut host) => host,
+            None => return,
+        };
+        match call {
+            HostCall::PrintDecimal(x) => {
+                let _ = writeln!(host.output, "{}", register[usize::from(x)]);
+            }
+            HostCall::PrintChar(x) => host.output.push(register[usize::from(x)]),
+            HostCall::AssertEq(x, y) => {
+                let (vx, vy) = (register[usize::from(x)], register[usize::from(y)]);
+                if vx != vy {
+                    error!(
+                        "assertion failed at 0x{:03X}: v{:x} ({}) != v{:x} ({})",
+                        pc, x, vx, y, vy
+                    );
+                    host.exit_code = Some(1);
+                }
+            }
+            HostCall::Exit(code) => host.exit_code = Some(code),
         }
-        panic!("unexpected memory access at {}", address)
+        self.pc = self.pc.wrapping_add(INSTRUCTION_WIDTH);
     }
 
     ///0x00E0
@@ -213,10 +580,11 @@ impl Cpu {
     ///0x00EE
     ///return from subroutine
     fn rts(&mut self) {
-        if let Some(address) = self.sp.pop() {
-            self.pc = address;
+        if self.sp == 0 {
+            self.raise(Fault::StackUnderflow { pc: self.pc });
         } else {
-            error!("rts called with no return address");
+            self.sp -= 1;
+            self.pc = self.stack[usize::from(self.sp)];
         }
     }
 
@@ -228,9 +596,18 @@ impl Cpu {
 
     ///0x2NNN (NNN is the address)
     ///jump to subroutine
+    ///
+    ///faults once the stack holds the quirks' `stack_depth` addresses
     fn jsr(&mut self, address: u16) {
-        self.sp.push(self.pc.wrapping_add(INSTRUCTION_WIDTH));
-        self.pc = address;
+        let depth = self.quirks.stack_depth.min(MAX_STACK_DEPTH as u8);
+        if self.sp >= depth {
+            self.raise(Fault::StackOverflow { pc: self.pc });
+        } else {
+            self.stack[usize::from(self.sp)] =
+                self.pc.wrapping_add(INSTRUCTION_WIDTH) & ADDRESS_MASK;
+            self.sp += 1;
+            self.pc = address;
+        }
     }
 
     ///0x3XRR
@@ -336,12 +713,7 @@ impl Cpu {
         let y = self.reg(register_y_id);
         let x = self.reg(register_x_id);
         let (result, overflow) = x.overflowing_add(y);
-        if overflow {
-            self.register[0x0F] = 0x01;
-        } else {
-            self.register[0x0F] = 0x00;
-        }
-        *self.reg_mut(register_x_id) = result;
+        self.set_result(register_x_id, result, overflow);
         self.pc = self.pc.wrapping_add(INSTRUCTION_WIDTH);
     }
 
@@ -352,21 +724,20 @@ impl Cpu {
         let y = self.reg(register_y_id);
         let x = self.reg(register_x_id);
         let (result, borrow) = x.overflowing_sub(y);
-        if !borrow {
-            self.register[0x0F] = 0x01;
-        } else {
-            self.register[0x0F] = 0x00;
-        }
-        *self.reg_mut(register_x_id) = result;
+        self.set_result(register_x_id, result, !borrow);
         self.pc = self.pc.wrapping_add(INSTRUCTION_WIDTH);
     }
 
-    ///8XY6 shr vx  shift register VX right, bit 0 goes into register VF
-    fn shr(&mut self, register_x_id: u8, _register_y_id: u8) {
-        let x = self.reg(register_x_id);
-        self.register[0x0F] = x & 0x01;
-        //*self.reg_mut(register_y_id) = x >> 1;
-        *self.reg_mut(register_x_id) = x >> 1;
+    ///8XY6 shr vx,vy  shift register VY right into register VX, or VX in
+    ///place with the shift quirk, bit 0 goes into register VF
+    fn shr(&mut self, register_x_id: u8, register_y_id: u8) {
+        let source = if self.quirks.shift {
+            register_x_id
+        } else {
+            register_y_id
+        };
+        let x = self.reg(source);
+        self.set_result(register_x_id, x >> 1, x & 0x01 != 0);
         self.pc = self.pc.wrapping_add(INSTRUCTION_WIDTH);
     }
 
@@ -377,25 +748,20 @@ impl Cpu {
         let y = self.reg(register_y_id);
         let x = self.reg(register_x_id);
         let (result, borrow) = y.overflowing_sub(x);
-        if !borrow {
-            self.register[0x0F] = 0x01;
-        } else {
-            self.register[0x0F] = 0x00;
-        }
-        *self.reg_mut(register_x_id) = result;
+        self.set_result(register_x_id, result, !borrow);
         self.pc = self.pc.wrapping_add(INSTRUCTION_WIDTH);
     }
 
-    ///8XYE shl vx  shift register VX left, bit 7 stored into register VF
-    fn shl(&mut self, register_x_id: u8, _register_y_id: u8) {
-        let x = self.reg(register_x_id);
-        if x & 0x80 != 0 {
-            self.register[0x0F] = 0x01;
+    ///8XYE shl vx,vy  shift register VY left into register VX, or VX in
+    ///place with the shift quirk, bit 7 stored into register VF
+    fn shl(&mut self, register_x_id: u8, register_y_id: u8) {
+        let source = if self.quirks.shift {
+            register_x_id
         } else {
-            self.register[0x0F] = 0x00;
-        }
-        //*self.reg_mut(register_y_id) = x << 1;
-        *self.reg_mut(register_x_id) = x << 1;
+            register_y_id
+        };
+        let x = self.reg(source);
+        self.set_result(register_x_id, x << 1, x & 0x80 != 0);
         self.pc = self.pc.wrapping_add(INSTRUCTION_WIDTH);
     }
 
@@ -416,14 +782,25 @@ impl Cpu {
         self.pc = self.pc.wrapping_add(INSTRUCTION_WIDTH);
     }
 
-    ///BNNN jmi nnn Jump to address NNN + register V0
+    ///BNNN jmi nnn Jump to address NNN + register V0, or with the jump
+    ///quirk to NNN + register VN where N is the top nibble of NNN
     fn jmi(&mut self, value: u16) {
-        self.pc = u16::from(self.reg(0)).wrapping_add(value & 0xFFF);
+        let register = if self.quirks.jump {
+            (value >> 8) as u8 & 0x0F
+        } else {
+            0
+        };
+        self.pc = u16::from(self.reg(register)).wrapping_add(value & 0xFFF);
     }
 
     ///CXKK rand vx,kk register VX = random number AND KK
     fn rand(&mut self, register_x_id: u8, value: u8) {
-        *self.reg_mut(register_x_id) = rand::random::<u8>() & value;
+        //xorshift64*, taking the well mixed top byte
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        let random = (self.rng.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8;
+        *self.reg_mut(register_x_id) = random & value;
         self.pc = self.pc.wrapping_add(INSTRUCTION_WIDTH);
     }
 
@@ -431,30 +808,33 @@ impl Cpu {
     ///(register VX,register VY) height N
     ///Sprites stored in memory at location in index register (I),
     ///maximum 8bits wide. Wraps around
-    ///the screen. If when drawn, clears a pixel,
+    ///the screen, or with the clip quirk only the starting position wraps
+    ///and the rest is cut off at the edges. If when drawn, clears a pixel,
     ///register VF is set to 1 otherwise it is zero. All
     ///drawing is XOR drawing (e.g. it toggles the screen pixels)
     fn sprite(&mut self, register_x_id: u8, register_y_id: u8, num_lines: u8) {
-        let x = usize::from(self.reg(register_x_id));
-        let y = usize::from(self.reg(register_y_id));
+        let x = usize::from(self.reg(register_x_id)) % SCREEN_WIDTH;
+        let y = usize::from(self.reg(register_y_id)) % SCREEN_HEIGHT;
         let mut index = 0;
+        let mut collision = false;
         for line in 0..num_lines {
-            let sprite_row = self.mem(self.i + u16::from(line));
+            let sprite_row = self.mem(self.i.wrapping_add(u16::from(line)));
             for i in 0..8 {
                 let sprite_pixel = (sprite_row << i) & 0x80;
-                if sprite_pixel != 0 {
+                let clipped = self.quirks.clip
+                    && (x + (index % 8) >= SCREEN_WIDTH || y + (index / 8) >= SCREEN_HEIGHT);
+                if sprite_pixel != 0 && !clipped {
                     let sprite_x = (x + (index % 8)) % SCREEN_WIDTH;
                     let sprite_y = (y + (index / 8)) % SCREEN_HEIGHT;
                     let pixel_address = sprite_y * SCREEN_WIDTH + sprite_x;
                     let current_pixel = self.screen[pixel_address];
-                    if current_pixel {
-                        *self.reg_mut(0xf) = 0x01;
-                    }
+                    collision |= current_pixel;
                     self.screen[pixel_address] = !current_pixel;
                 }
                 index += 1;
             }
         }
+        self.register[0x0F] = collision as u8;
         self.pc = self.pc.wrapping_add(INSTRUCTION_WIDTH);
     }
 
@@ -462,10 +842,9 @@ impl Cpu {
     ///The key is a key number, see the chip-8
     ///documentation
     fn skpr(&mut self, key_id: u8) {
-        let key = self.reg(key_id);
-        if key > 0x0F {
-            error!("invalid key id {}", key);
-        } else if self.key[usize::from(key)] {
+        //only the low nibble picks a key, as on the VIP
+        let key = self.reg(key_id) & 0x0F;
+        if self.key[usize::from(key)] {
             self.pc = self.pc.wrapping_add(INSTRUCTION_WIDTH);
         }
         self.pc = self.pc.wrapping_add(INSTRUCTION_WIDTH);
@@ -473,10 +852,8 @@ impl Cpu {
 
     ///eka1 skup k  skip if key (register rk) not pressed
     fn skup(&mut self, key_id: u8) {
-        let key = self.reg(key_id);
-        if key > 0x0F {
-            error!("invalid key id {}", key);
-        } else if !self.key[usize::from(key)] {
+        let key = self.reg(key_id) & 0x0F;
+        if !self.key[usize::from(key)] {
             self.pc = self.pc.wrapping_add(INSTRUCTION_WIDTH);
         }
         self.pc = self.pc.wrapping_add(INSTRUCTION_WIDTH);
@@ -509,21 +886,23 @@ impl Cpu {
     }
 
     ///fr1e adi vr  add register vr to the index register
+    ///register VF set to 1 if I runs past the end of memory, and I wraps
     fn adi(&mut self, register_x_id: u8) {
-        let (result, overflow) = self.i.overflowing_add(u16::from(self.reg(register_x_id)));
-        if overflow {
+        let result = self.i + u16::from(self.reg(register_x_id));
+        if result > ADDRESS_MASK {
             self.register[0x0F] = 0x01;
         } else {
             self.register[0x0F] = 0x00;
         }
-        self.i = result;
+        self.i = result & ADDRESS_MASK;
         self.pc = self.pc.wrapping_add(INSTRUCTION_WIDTH);
     }
 
     ///fr29 font vr point I to the sprite for hexadecimal
     ///character in vr   Sprite is 5 bytes high
     fn font(&mut self, register_x_id: u8) {
-        self.i = FONTSET_ADDRESS + u16::from(self.reg(register_x_id)) * 5;
+        //only the low nibble is a hex digit
+        self.i = FONTSET_ADDRESS + u16::from(self.reg(register_x_id) & 0x0F) * 5;
         self.pc = self.pc.wrapping_add(INSTRUCTION_WIDTH);
     }
 
@@ -531,18 +910,14 @@ impl Cpu {
     ///at location I,I+1,I+2
     ///Doesn't change I
     fn bcd(&mut self, register_x_id: u8) {
-        if self.i >= (4096 - 3) {
-            error!("bcd called with I too large: {}", self.i);
-        } else {
-            let x = self.reg(register_x_id);
-            let x100 = x / 100;
-            let x10 = (x - (x100 * 100)) / 10;
-            let x1 = x - (x100 * 100) - (x10 * 10);
-            let i = self.i;
-            *self.mem_mut(i) = x100;
-            *self.mem_mut(i + 1) = x10;
-            *self.mem_mut(i + 2) = x1;
-        }
+        let x = self.reg(register_x_id);
+        let x100 = x / 100;
+        let x10 = (x - (x100 * 100)) / 10;
+        let x1 = x - (x100 * 100) - (x10 * 10);
+        let i = self.i;
+        *self.mem_mut(i) = x100;
+        *self.mem_mut(i.wrapping_add(1)) = x10;
+        *self.mem_mut(i.wrapping_add(2)) = x1;
         self.pc = self.pc.wrapping_add(INSTRUCTION_WIDTH);
     }
 
@@ -550,11 +925,16 @@ impl Cpu {
     ///I is incremented to point to
     ///the next location on. e.g. I = I + r + 1
     fn str(&mut self, register_x_id: u8) {
-        let r = self.reg(register_x_id);
-        let bound = std::cmp::min(r, 0x0F);
+        let bound = std::cmp::min(register_x_id, 0x0F);
+        let start = self.i;
         for i in 0..=bound {
-            self.memory[usize::from(self.i)] = self.reg(i);
-            self.i += 1;
+            let value = self.reg(i);
+            let address = self.i;
+            *self.mem_mut(address) = value;
+            self.i = self.i.wrapping_add(1) & ADDRESS_MASK;
+        }
+        if self.quirks.load_store {
+            self.i = start;
         }
         self.pc = self.pc.wrapping_add(INSTRUCTION_WIDTH);
     }
@@ -562,11 +942,14 @@ impl Cpu {
     ///fx65 ldr v0-vr   load registers v0-vr from location I onwards
     ///as above.
     fn ldr(&mut self, register_x_id: u8) {
-        let r = self.reg(register_x_id);
-        let bound = std::cmp::min(r, 0x0F);
+        let bound = std::cmp::min(register_x_id, 0x0F);
+        let start = self.i;
         for i in 0..=bound {
-            *self.reg_mut(i) = self.memory[usize::from(self.i)];
-            self.i += 1;
+            *self.reg_mut(i) = self.mem(self.i);
+            self.i = self.i.wrapping_add(1) & ADDRESS_MASK;
+        }
+        if self.quirks.load_store {
+            self.i = start;
         }
         self.pc = self.pc.wrapping_add(INSTRUCTION_WIDTH);
     }
@@ -577,6 +960,172 @@ mod test {
     use super::*;
 
     #[test]
+    fn test_key_mut() {
+        let mut cpu = Cpu::new();
+        *cpu.key_mut(0x0F) = true;
+        assert!(cpu.key[0x0F]);
+        *cpu.key_mut(0x10) = true;
+        assert!(cpu.unknown_key);
+    }
+
+    #[test]
+    fn test_keys_bitmap() {
+        let mut cpu = Cpu::new();
+        cpu.set_keys(0x8021);
+        assert!(cpu.key[0] && cpu.key[5] && cpu.key[0xF]);
+        assert!(!cpu.key[1]);
+        assert_eq!(cpu.keys(), 0x8021);
+    }
+
+    #[test]
+    fn test_save_state() {
+        let mut cpu = Cpu::with_quirks(Quirks::schip());
+        cpu.seed(99);
+        cpu.load_rom(&[0x12, 0x00]).unwrap();
+        cpu.register[3] = 7;
+        cpu.i = 0x321;
+        cpu.delay = 4;
+        cpu.jsr(0x300);
+        cpu.screen[100] = true;
+        cpu.set_keys(0x0010);
+
+        let mut state = vec![0xAA];
+        cpu.save_state(&mut state);
+        state.push(0xBB);
+        let mut input = &state[1..];
+        let mut restored = Cpu::load_state(&mut input).unwrap();
+        assert_eq!(input, &[0xBB]);
+        assert_eq!(restored.register, cpu.register);
+        assert_eq!((restored.i, restored.pc, restored.delay), (0x321, 0x300, 4));
+        assert_eq!(restored.stack(), &[0x202]);
+        assert!(restored.screen[100]);
+        assert_eq!(restored.keys(), 0x0010);
+        assert_eq!(restored.memory[0x200], 0x12);
+        assert_eq!(restored.quirks, Quirks::schip());
+        //the generator carries on where it was
+        cpu.rand(0, 0xFF);
+        restored.rand(0, 0xFF);
+        assert_eq!(restored.register[0], cpu.register[0]);
+
+        assert!(Cpu::load_state(&mut &state[1..100]).is_err());
+        assert!(Cpu::load_state(&mut &[0xFF][..]).is_err());
+    }
+
+    #[test]
+    fn test_semihosting() {
+        //mov v1,42 ; print v1 ; putc v2 ; assert v1 == v3 ; exit 3
+        let rom = [0x61, 0x2A, 0x01, 0x01, 0x01, 0x12, 0x02, 0x13, 0x03, 0x03];
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&rom).unwrap();
+        cpu.tick();
+        cpu.tick();
+        //host calls are unregistered routines until turned on
+        assert_eq!(cpu.pc, 0x202);
+        assert_eq!(
+            cpu.fault(),
+            Some(Fault::UnknownRoutine {
+                pc: 0x202,
+                address: 0x101
+            })
+        );
+
+        cpu.clear_fault();
+        cpu.enable_semihosting();
+        cpu.register[2] = b'!';
+        cpu.tick();
+        cpu.tick();
+        assert_eq!(cpu.semihost_mut().unwrap().take_output(), b"42\n!");
+        cpu.register[3] = 42;
+        cpu.tick();
+        assert_eq!(cpu.exit_code(), None);
+        cpu.tick();
+        assert_eq!(cpu.exit_code(), Some(3));
+        //nothing runs after the exit
+        cpu.tick();
+        assert_eq!(cpu.pc, 0x20A);
+
+        cpu.semihost_mut().unwrap().exit_code = None;
+        cpu.pc = 0x206;
+        cpu.register[3] = 7;
+        cpu.tick();
+        assert_eq!(cpu.exit_code(), Some(1));
+    }
+
+    #[test]
+    fn test_host_call_beats_routine() {
+        //mov v3,5 ; assert v3 == v0, not the built in 0230 cls
+        let rom = [0x63, 0x05, 0x02, 0x30];
+        let mut cpu = Cpu::new();
+        cpu.enable_semihosting();
+        cpu.load_rom(&rom).unwrap();
+        cpu.screen[0] = true;
+        cpu.tick();
+        cpu.tick();
+        assert_eq!(cpu.exit_code(), Some(1));
+        assert!(cpu.screen[0]);
+    }
+
+    #[test]
+    fn test_fault_policy() {
+        //an unknown opcode, then add v0,1
+        let rom = [0xF0, 0xFF, 0x70, 0x01];
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&rom).unwrap();
+        cpu.tick();
+        let fault = Fault::UnknownOpcode {
+            pc: 0x200,
+            opcode: 0xF0FF,
+        };
+        assert_eq!(cpu.fault(), Some(fault));
+        //halted until the fault is cleared
+        cpu.tick();
+        assert_eq!(cpu.pc, 0x200);
+
+        cpu.clear_fault();
+        cpu.set_fault_policy(FaultPolicy::Skip);
+        cpu.tick();
+        cpu.tick();
+        assert_eq!(cpu.fault(), None);
+        assert_eq!(cpu.register[0], 1);
+    }
+
+    #[test]
+    fn test_skip_reg_low_nibble() {
+        //5XYN and 9XYN are only instructions with N = 0
+        for &opcode in &[0x5011, 0x9011] {
+            let mut cpu = Cpu::new();
+            cpu.load_rom(&[(opcode >> 8) as u8, opcode as u8]).unwrap();
+            cpu.tick();
+            let fault = Fault::UnknownOpcode { pc: 0x200, opcode };
+            assert_eq!(cpu.fault(), Some(fault));
+        }
+    }
+
+    #[test]
+    fn test_stack_depth() {
+        //0x200: jsr 0x200, recursing forever
+        let mut cpu = Cpu::with_quirks(Quirks::vip());
+        cpu.load_rom(&[0x22, 0x00]).unwrap();
+        for _ in 0..20 {
+            cpu.tick();
+        }
+        assert_eq!(cpu.sp(), 12);
+        assert_eq!(cpu.stack(), &[0x202; 12][..]);
+        assert_eq!(cpu.fault(), Some(Fault::StackOverflow { pc: 0x200 }));
+
+        //0x200: rts
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&[0x00, 0xEE]).unwrap();
+        cpu.set_stack(&[0x300]);
+        cpu.tick();
+        assert_eq!((cpu.pc, cpu.sp()), (0x300, 0));
+        cpu.set_pc(0x200);
+        cpu.tick();
+        assert_eq!(cpu.fault(), Some(Fault::StackUnderflow { pc: 0x200 }));
+    }
+
+    #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_cls() {
         let mut cpu = Cpu::new();
         cpu.screen[0] = true;
@@ -795,6 +1344,42 @@ mod test {
         assert_eq!(cpu.register[0x0F], 0x00);
     }
 
+    #[test]
+    fn test_sub_reg_result_wins() {
+        //when VF is the destination the result overwrites the flag
+        let mut cpu = Cpu::new();
+        cpu.register[0x0F] = 0x05;
+        cpu.register[0x03] = 0x01;
+        cpu.sub_reg(0xF, 3);
+        assert_eq!(cpu.register[0x0F], 0x04);
+    }
+
+    #[test]
+    fn test_sub_reg_vf_order() {
+        let mut cpu = Cpu::with_quirks(Quirks {
+            vf_order: false,
+            ..Quirks::default()
+        });
+        cpu.register[0x0F] = 0x05;
+        cpu.register[0x03] = 0x01;
+        cpu.sub_reg(0xF, 3);
+        assert_eq!(cpu.register[0x0F], 0x01);
+    }
+
+    #[test]
+    fn test_shift_from_vy() {
+        let mut cpu = Cpu::with_quirks(Quirks::vip());
+        cpu.register[0x07] = 0xFF;
+        cpu.register[0x08] = 0x82;
+        cpu.shr(7, 8);
+        assert_eq!(cpu.register[0x07], 0x41);
+        assert_eq!(cpu.register[0x0F], 0x00);
+        cpu.shl(7, 8);
+        assert_eq!(cpu.register[0x07], 0x04);
+        assert_eq!(cpu.register[0x0F], 0x01);
+        assert_eq!(cpu.register[0x08], 0x82);
+    }
+
     #[test]
     fn test_shl() {
         let mut cpu = Cpu::new();
@@ -835,6 +1420,94 @@ mod test {
         assert_eq!(cpu.pc, 0xF10);
     }
 
+    #[test]
+    fn test_jmi_quirk() {
+        let mut cpu = Cpu::with_quirks(Quirks::schip());
+        *cpu.reg_mut(0) = 0x10;
+        *cpu.reg_mut(3) = 0x20;
+        cpu.jmi(0x300);
+        assert_eq!(cpu.pc, 0x320);
+    }
+
+    #[test]
+    fn test_sprite_clip() {
+        for &(clip, wrapped) in &[(false, true), (true, false)] {
+            let mut cpu = Cpu::with_quirks(Quirks {
+                clip,
+                ..Quirks::default()
+            });
+            cpu.i = 0x300;
+            cpu.memory[0x300] = 0xFF;
+            *cpu.reg_mut(0) = 60;
+            //the starting position always wraps
+            *cpu.reg_mut(1) = 33;
+            cpu.sprite(0, 1, 1);
+            assert!(cpu.screen[SCREEN_WIDTH + 63]);
+            assert_eq!(cpu.screen[SCREEN_WIDTH], wrapped);
+        }
+    }
+
+    #[test]
+    fn test_sprite_collision() {
+        let mut cpu = Cpu::new();
+        cpu.i = 0x300;
+        cpu.memory[0x300] = 0x80;
+        cpu.sprite(0, 0, 1);
+        assert_eq!(cpu.register[0xF], 0);
+        cpu.sprite(0, 0, 1);
+        assert_eq!(cpu.register[0xF], 1);
+        //and cleared again by a draw that doesn't collide
+        cpu.sprite(0, 0, 1);
+        assert_eq!(cpu.register[0xF], 0);
+    }
+
+    #[test]
+    fn test_str_ldr_count_by_index() {
+        let mut cpu = Cpu::new();
+        cpu.i = 0x300;
+        cpu.register[0] = 7;
+        cpu.register[1] = 0xFF;
+        cpu.str(1);
+        assert_eq!(&cpu.memory[0x300..0x303], &[7, 0xFF, 0]);
+        assert_eq!(cpu.i, 0x302);
+        cpu.i = 0x300;
+        cpu.register = [0; 16];
+        cpu.ldr(1);
+        assert_eq!(&cpu.register[..3], &[7, 0xFF, 0]);
+    }
+
+    #[test]
+    fn test_load_store_quirk() {
+        let mut cpu = Cpu::with_quirks(Quirks::schip());
+        cpu.i = 0x300;
+        cpu.str(0);
+        assert_eq!(cpu.i, 0x300);
+        cpu.ldr(0);
+        assert_eq!(cpu.i, 0x300);
+    }
+
+    #[test]
+    fn test_wraps_at_end_of_memory() {
+        let mut cpu = Cpu::new();
+        cpu.register[0] = 1;
+        cpu.register[1] = 2;
+        cpu.i = 0xFFF;
+        cpu.str(1);
+        assert_eq!((cpu.memory[0xFFF], cpu.memory[0], cpu.i), (1, 2, 0x001));
+
+        cpu.i = 0xFFF;
+        cpu.adi(0);
+        assert_eq!((cpu.i, cpu.register[0x0F]), (0x000, 1));
+
+        //the opcode at 0xFFF is split across the end of memory
+        cpu.memory = [0; 4096];
+        cpu.pc = 0xFFF;
+        cpu.memory[0xFFF] = 0x1A;
+        cpu.memory[0] = 0xBC;
+        cpu.tick();
+        assert_eq!(cpu.pc, 0xABC);
+    }
+
     #[test]
     fn test_rand() {
         let mut cpu = Cpu::new();
@@ -844,6 +1517,24 @@ mod test {
         assert_eq!(cpu.reg(0xB) & 0xF0, 0x00);
     }
 
+    #[test]
+    fn test_rand_seed() {
+        let mut a = Cpu::new();
+        let mut b = Cpu::new();
+        a.seed(42);
+        b.seed(42);
+        for _ in 0..16 {
+            a.rand(0, 0xFF);
+            b.rand(0, 0xFF);
+            assert_eq!(a.reg(0), b.reg(0));
+        }
+        //zero is usable as a seed
+        a.seed(0);
+        a.rand(0, 0xFF);
+        a.rand(1, 0xFF);
+        assert_ne!((a.reg(0), a.reg(1)), (0, 0));
+    }
+
     #[test]
     fn test_bcd() {
         let mut cpu = Cpu::new();
@@ -0,0 +1,128 @@
+//! Breakpoints and state reports driven by a symbol map
+
+use cpu::Cpu;
+use std::fmt::Write;
+use symbols::{MonitorTarget, SymbolMap};
+
+///Watches a running `Cpu` for the breakpoints in a symbol map
+pub struct Debugger {
+    symbols: SymbolMap,
+    ///address we last stopped at, so resuming doesn't stop again at once
+    resumed_from: Option<u16>,
+}
+
+impl Debugger {
+    ///new debugger using the breakpoints and monitors in `symbols`
+    pub fn new(symbols: SymbolMap) -> Self {
+        Debugger {
+            symbols,
+            resumed_from: None,
+        }
+    }
+
+    pub fn symbols(&self) -> &SymbolMap {
+        &self.symbols
+    }
+
+    ///call before each `tick`, returns the name of the breakpoint if the
+    ///cpu should stop before executing the instruction at PC
+    pub fn check(&mut self, cpu: &Cpu) -> Option<&str> {
+        let pc = cpu.pc();
+        if self.resumed_from.take() == Some(pc) {
+            return None;
+        }
+        match self.symbols.breakpoints.get(&pc) {
+            Some(name) => {
+                self.resumed_from = Some(pc);
+                Some(name)
+            }
+            None => None,
+        }
+    }
+
+    ///a multi-line dump of the cpu state and every monitor
+    pub fn report(&self, cpu: &Cpu) -> String {
+        let mut out = String::new();
+        let pc = cpu.pc();
+        let _ = write!(out, "pc: 0x{:03X}", pc);
+        if let Some(label) = self.symbols.label_at(pc) {
+            let _ = write!(out, " ({})", label);
+        }
+        if let Some(location) = self.symbols.lines.get(&pc) {
+            let _ = write!(out, " {}:{}", location.file, location.line);
+        }
+        let _ = writeln!(
+            out,
+            "\ni: 0x{:03X} delay: {} sound: {}",
+            cpu.i(),
+            cpu.delay(),
+            cpu.sound()
+        );
+        for (x, value) in cpu.registers().iter().enumerate() {
+            let _ = write!(out, "v{:x}: {:02X}", x, value);
+            out.push(if x % 8 == 7 { '\n' } else { ' ' });
+        }
+        let stack: Vec<String> = cpu.stack().iter().map(|a| format!("0x{:03X}", a)).collect();
+        let _ = writeln!(
+            out,
+            "stack: [{}] sp: {}/{}",
+            stack.join(", "),
+            cpu.sp(),
+            cpu.quirks().stack_depth
+        );
+        if let Some(fault) = cpu.fault() {
+            let _ = writeln!(out, "fault: {}", fault);
+        }
+        for monitor in &self.symbols.monitors {
+            let bytes: Vec<String> = match monitor.target {
+                MonitorTarget::Register(x) => {
+                    vec![format!("{:02X}", cpu.registers()[usize::from(x & 0x0F)])]
+                }
+                MonitorTarget::Memory(address) => cpu
+                    .memory()
+                    .iter()
+                    .skip(usize::from(address))
+                    .take(monitor.length)
+                    .map(|b| format!("{:02X}", b))
+                    .collect(),
+            };
+            let _ = writeln!(out, "{}: {}", monitor.name, bytes.join(" "));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use symbols::Monitor;
+
+    #[test]
+    fn test_breakpoint_stops_once() {
+        let mut symbols = SymbolMap::new();
+        symbols.breakpoints.insert(0x200, "start".into());
+        let mut debugger = Debugger::new(symbols);
+        let cpu = Cpu::new();
+
+        assert_eq!(debugger.check(&cpu), Some("start"));
+        //resuming runs the instruction rather than stopping again
+        assert_eq!(debugger.check(&cpu), None);
+    }
+
+    #[test]
+    fn test_report() {
+        let mut symbols = SymbolMap::new();
+        symbols.labels.insert("main".into(), 0x200);
+        symbols.monitors.push(Monitor {
+            name: "font".into(),
+            target: MonitorTarget::Memory(0x50),
+            length: 2,
+        });
+        let debugger = Debugger::new(symbols);
+        let report = debugger.report(&Cpu::new());
+        assert!(report.starts_with("pc: 0x200 (main)"));
+        assert!(report.contains("v7: 00\n"));
+        assert!(report.contains("stack: [] sp: 0/16\n"));
+        assert!(report.contains("font: F0 90\n"));
+    }
+}
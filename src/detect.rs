@@ -0,0 +1,236 @@
+//! Guessing the quirks a ROM needs by running it under each profile
+//!
+//! Each profile gets the same seed and the same random key presses for a
+//! few hundred frames, headless. A run loses points for faulting, for
+//! reading or writing past the end of memory or writing over the
+//! interpreter, for executing outside the program area and for frames where
+//! nothing at all changes, and gains them for every frame that changes the
+//! screen. A ROM run with the wrong quirks tends to crash, scribble over
+//! memory or sit in a loop with a blank screen, so the best scoring profile
+//! is usually the right one.
+
+use cpu::Cpu;
+use disasm::ROM_START;
+use fault::{Fault, FaultPolicy};
+use headless::{run_frames, screen_hash};
+use instruction::Instruction;
+use quirks::Quirks;
+use rand::prng::XorShiftRng;
+use rand::{Rng, SeedableRng};
+use std::fmt;
+use std::iter;
+
+///The profiles tried, in the order ties are settled
+pub const PROFILES: &[&str] = &["default", "vip", "schip", "octo"];
+
+///Frames each profile runs for unless told otherwise
+pub const DEFAULT_FRAMES: u32 = 300;
+
+///How a ROM behaved under one profile
+#[derive(Debug, Clone, PartialEq)]
+pub struct Run {
+    pub profile: &'static str,
+    pub quirks: Quirks,
+    ///the fault that halted it, if one did
+    pub fault: Option<Fault>,
+    ///instructions that touched memory they shouldn't have
+    pub out_of_bounds: u32,
+    ///frames after which the machine was exactly as it was before
+    pub stuck_frames: u32,
+    ///frames that changed the screen
+    pub screen_changes: u32,
+    pub frames: u32,
+}
+
+impl Run {
+    ///higher is saner
+    pub fn score(&self) -> i64 {
+        let fault = if self.fault.is_some() { 1000 } else { 0 };
+        i64::from(self.screen_changes)
+            - fault
+            - 10 * i64::from(self.out_of_bounds.min(100))
+            - 2 * i64::from(self.stuck_frames)
+    }
+}
+
+///`profile: score N (faults, out of bounds, stuck, screen changes)`
+impl fmt::Display for Run {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:<7} score {:>5}: {} out of bounds, {} of {} frames stuck, {} screen changes",
+            self.profile,
+            self.score(),
+            self.out_of_bounds,
+            self.stuck_frames,
+            self.frames,
+            self.screen_changes
+        )?;
+        if let Some(fault) = self.fault {
+            write!(f, ", halted by {}", fault)?;
+        }
+        Ok(())
+    }
+}
+
+///true if the instruction about to run touches memory outside of what a
+///well behaved ROM would
+fn out_of_bounds(cpu: &Cpu) -> bool {
+    let pc = cpu.pc();
+    if pc < ROM_START {
+        return true;
+    }
+    let i = u32::from(cpu.i());
+    let end = cpu.memory().len() as u32;
+    let past_end = |bytes: u32| i + bytes > end;
+    let below_program = i < u32::from(ROM_START);
+    match Instruction::fetch(cpu.memory(), usize::from(pc)) {
+        None => true,
+        Some(Instruction::Sprite(_, _, lines)) => past_end(u32::from(lines)),
+        Some(Instruction::Bcd(_)) => past_end(3) || below_program,
+        Some(Instruction::Str(x)) => past_end(u32::from(x) + 1) || below_program,
+        Some(Instruction::Ldr(x)) => past_end(u32::from(x) + 1),
+        Some(Instruction::Adi(x)) => past_end(u32::from(cpu.registers()[usize::from(x)]) + 1),
+        Some(_) => false,
+    }
+}
+
+///random keys frame after frame, one key at a time held for a few frames
+///with gaps in between
+fn random_keys(seed: u64) -> impl Iterator<Item = u16> {
+    let mut bytes = [0; 16];
+    bytes[..8].copy_from_slice(&seed.to_le_bytes());
+    bytes[8] = 1;
+    let mut rng = XorShiftRng::from_seed(bytes);
+    let mut down = 0;
+    iter::repeat_with(move || {
+        if rng.gen_range(0, 8) == 0 {
+            down = if down == 0 {
+                1 << rng.gen_range(0, 16)
+            } else {
+                0
+            };
+        }
+        down
+    })
+}
+
+///run `rom` for `frames` frames under `quirks`
+pub fn run(rom: &[u8], profile: &'static str, quirks: Quirks, frames: u32, seed: u64) -> Run {
+    let mut result = Run {
+        profile,
+        quirks,
+        fault: None,
+        out_of_bounds: 0,
+        stuck_frames: 0,
+        screen_changes: 0,
+        frames,
+    };
+    let mut cpu = Cpu::with_quirks(quirks);
+    cpu.set_fault_policy(FaultPolicy::Halt);
+    cpu.seed(seed);
+    if cpu.load_rom(rom).is_err() {
+        result.out_of_bounds = 1;
+        return result;
+    }
+    let mut keys = random_keys(seed);
+    let snapshot = |cpu: &Cpu| {
+        (
+            cpu.pc(),
+            cpu.i(),
+            *cpu.registers(),
+            cpu.stack().to_vec(),
+            screen_hash(cpu),
+        )
+    };
+    let mut before = snapshot(&cpu);
+    for frame in 0..frames {
+        let count = &mut result.out_of_bounds;
+        let down = keys.next().unwrap_or_default();
+        run_frames(
+            &mut cpu,
+            frame..frame + 1,
+            |_| down,
+            |cpu| {
+                if cpu.fault().is_none() && out_of_bounds(cpu) {
+                    *count += 1;
+                }
+                cpu.tick();
+            },
+        );
+        if let Some(fault) = cpu.fault() {
+            result.fault = Some(fault);
+            break;
+        }
+        let after = snapshot(&cpu);
+        if after == before {
+            result.stuck_frames += 1;
+        }
+        if after.4 != before.4 {
+            result.screen_changes += 1;
+        }
+        before = after;
+    }
+    result
+}
+
+///run `rom` under every profile in `PROFILES`, best first
+pub fn detect(rom: &[u8], frames: u32, seed: u64) -> Vec<Run> {
+    let mut runs: Vec<Run> = PROFILES
+        .iter()
+        .map(|&profile| {
+            let quirks = profile.parse().expect("PROFILES has an unknown profile");
+            run(rom, profile, quirks, frames, seed)
+        })
+        .collect();
+    //stable, so ties go to the earlier profile
+    runs.sort_by_key(|run| -run.score());
+    runs
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_load_store() {
+        //store registers in a loop without resetting I, which only stays put
+        //with the load_store quirk
+        let rom = [
+            0xA3, 0x00, //mvi 0x300
+            0xF3, 0x55, //str v0-v3
+            0x70, 0x01, //add v0, 1
+            0x12, 0x02, //jmp 0x202
+        ];
+        let default = run(&rom, "default", Quirks::default(), 40, 1);
+        let schip = run(&rom, "schip", Quirks::schip(), 40, 1);
+        assert_eq!(schip.out_of_bounds, 0);
+        assert!(default.out_of_bounds > 0);
+        assert!(schip.score() > default.score(), "{}\n{}", schip, default);
+    }
+
+    #[test]
+    fn test_detect() {
+        //a stack that only a 16 deep profile can hold
+        let mut rom = Vec::new();
+        for n in 0..13u16 {
+            let next = 0x200 + 2 * (n + 1);
+            rom.extend_from_slice(&[0x20 | (next >> 8) as u8, next as u8]);
+        }
+        //then keep drawing the font's 0 in a new place
+        rom.extend_from_slice(&[0xA0, 0x50, 0xD0, 0x15, 0x70, 0x01, 0x12, 0x1A]);
+        let runs = detect(&rom, 30, 7);
+        assert_eq!(runs.len(), PROFILES.len());
+        assert_eq!(runs[0].profile, "default");
+        let vip = runs.iter().find(|run| run.profile == "vip").unwrap();
+        assert_eq!(vip.fault, Some(Fault::StackOverflow { pc: 0x218 }));
+        assert!(vip.to_string().contains("halted by call stack overflow"));
+    }
+
+    #[test]
+    fn test_many_frames() {
+        //keys are made as they're needed, not a frame's worth up front
+        let run = run(&[0x00, 0xEE], "default", Quirks::default(), u32::MAX, 1);
+        assert_eq!(run.fault, Some(Fault::StackUnderflow { pc: 0x200 }));
+    }
+}
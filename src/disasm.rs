@@ -0,0 +1,484 @@
+//! Disassembler producing annotated listings of CHIP-8 ROMs
+//!
+//! Code is discovered by recursive traversal from the entry point, following
+//! `jmp`, `jsr`, `jmi` and the skip instructions. Bytes that `mvi` points at
+//! and a later `sprite` draws are treated as sprite data and rendered as
+//! ASCII art. Anything not reached is emitted as raw data, so the listing can
+//! always be reassembled into the original ROM.
+
+use instruction::{Instruction, INSTRUCTION_WIDTH};
+use std;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt::Write;
+use std::str::FromStr;
+
+///Address the ROM is loaded at
+pub const ROM_START: u16 = 0x200;
+
+///Output flavour of the listing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    ///the mnemonics from `cpu.rs`, as accepted by `chip8 asm`
+    Plain,
+    ///Octo assembly language
+    Octo,
+}
+
+impl FromStr for Syntax {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(Syntax::Plain),
+            "octo" => Ok(Syntax::Octo),
+            _ => Err(format!("unknown syntax '{}'", s)),
+        }
+    }
+}
+
+///How a byte of the ROM is used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Usage {
+    Unknown,
+    ///first byte of an instruction
+    Code,
+    ///second byte of an instruction
+    CodeTail,
+    Sprite,
+}
+
+///Why an address got a label, which decides its name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    Data,
+    Sprite,
+    Jump,
+    Subroutine,
+}
+
+///The result of analysing a ROM
+pub struct Disassembly<'a> {
+    rom: &'a [u8],
+    usage: Vec<Usage>,
+    labels: BTreeMap<u16, LabelKind>,
+    ///(value of I, height) for every `sprite` reached with I known
+    sprites: Vec<(u16, u8)>,
+    ///addresses reached that hold no instruction this emulator knows
+    undecoded: BTreeSet<u16>,
+}
+
+impl<'a> Disassembly<'a> {
+    ///analyse a ROM that will be loaded at `ROM_START`
+    pub fn new(rom: &'a [u8]) -> Self {
+        let mut disassembly = Disassembly {
+            rom,
+            usage: vec![Usage::Unknown; rom.len()],
+            labels: BTreeMap::new(),
+            sprites: Vec::new(),
+            undecoded: BTreeSet::new(),
+        };
+        disassembly.trace();
+        disassembly.mark_sprites();
+        disassembly
+    }
+
+    ///true if `address` holds the first byte of a decoded instruction
+    pub fn is_code(&self, address: u16) -> bool {
+        self.offset(address)
+            .is_some_and(|o| self.usage[o] == Usage::Code)
+    }
+
+    ///true if `address` holds bytes drawn as a sprite
+    pub fn is_sprite(&self, address: u16) -> bool {
+        self.offset(address)
+            .is_some_and(|o| self.usage[o] == Usage::Sprite)
+    }
+
+    ///addresses the trace reached but couldn't decode, where it stopped
+    pub fn undecoded(&self) -> &BTreeSet<u16> {
+        &self.undecoded
+    }
+
+    ///the name of the label at `address`, if there is one
+    pub fn label(&self, address: u16) -> Option<String> {
+        self.labels.get(&address).map(|kind| {
+            let prefix = match *kind {
+                LabelKind::Subroutine => "sub",
+                LabelKind::Jump => "label",
+                LabelKind::Sprite => "sprite",
+                LabelKind::Data => "data",
+            };
+            format!("{}_{:03X}", prefix, address)
+        })
+    }
+
+    ///render the listing
+    pub fn listing(&self, syntax: Syntax) -> String {
+        let comment = match syntax {
+            Syntax::Plain => ";",
+            Syntax::Octo => "#",
+        };
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{} disassembly of {} bytes loaded at 0x{:03X}",
+            comment,
+            self.rom.len(),
+            ROM_START
+        );
+
+        let mut offset = 0;
+        while offset < self.rom.len() {
+            let address = ROM_START + offset as u16;
+            if let Some(name) = self.label(address) {
+                match syntax {
+                    Syntax::Plain => {
+                        let _ = writeln!(out, "{}:", name);
+                    }
+                    Syntax::Octo => {
+                        let _ = writeln!(out, ": {}", name);
+                    }
+                }
+            }
+
+            match self.usage[offset] {
+                Usage::Code => {
+                    let opcode =
+                        (u16::from(self.rom[offset]) << 8) | u16::from(self.rom[offset + 1]);
+                    let instruction = Instruction::decode(opcode);
+                    let text = match syntax {
+                        Syntax::Plain => self.plain(instruction),
+                        Syntax::Octo => self.octo(instruction),
+                    };
+                    let _ = writeln!(
+                        out,
+                        "    {:<23} {} {:03X}: {:04X}",
+                        text, comment, address, opcode
+                    );
+                    offset += usize::from(INSTRUCTION_WIDTH);
+                }
+                Usage::Sprite => {
+                    let byte = self.rom[offset];
+                    let _ = writeln!(
+                        out,
+                        "    {:<23} {} {}",
+                        self.data(&[byte], syntax),
+                        comment,
+                        sprite_row(byte)
+                    );
+                    offset += 1;
+                }
+                Usage::Unknown | Usage::CodeTail => {
+                    let mut end = offset + 1;
+                    while end < self.rom.len()
+                        && end - offset < 8
+                        && self.usage[end] == Usage::Unknown
+                        && !self.labels.contains_key(&(ROM_START + end as u16))
+                    {
+                        end += 1;
+                    }
+                    let _ = writeln!(
+                        out,
+                        "    {:<23} {} {:03X}",
+                        self.data(&self.rom[offset..end], syntax),
+                        comment,
+                        address
+                    );
+                    offset = end;
+                }
+            }
+        }
+        out
+    }
+
+    fn offset(&self, address: u16) -> Option<usize> {
+        if address < ROM_START {
+            return None;
+        }
+        let offset = usize::from(address - ROM_START);
+        if offset < self.rom.len() {
+            Some(offset)
+        } else {
+            None
+        }
+    }
+
+    ///true if `address` starts a line of the listing, so a label there
+    ///will actually be emitted
+    fn is_line_start(&self, address: u16) -> bool {
+        self.offset(address)
+            .is_some_and(|o| self.usage[o] != Usage::CodeTail)
+    }
+
+    fn add_label(&mut self, address: u16, kind: LabelKind) {
+        if self.offset(address).is_none() {
+            return;
+        }
+        let entry = self.labels.entry(address).or_insert(kind);
+        if kind > *entry {
+            *entry = kind;
+        }
+    }
+
+    ///recursive traversal from the entry point
+    fn trace(&mut self) {
+        //(address, known value of I on entry), paths are revisited when
+        //they are reached with a different I so every sprite is found
+        let mut pending = vec![(ROM_START, None)];
+        let mut seen = HashSet::new();
+        while let Some((start, index)) = pending.pop() {
+            let mut address = start;
+            let mut index: Option<u16> = index;
+            while let Some(offset) = self.offset(address) {
+                if offset + 1 >= self.rom.len() || !seen.insert((address, index)) {
+                    break;
+                }
+                let fresh = self.usage[offset] == Usage::Unknown
+                    && self.usage[offset + 1] == Usage::Unknown;
+                if !fresh && self.usage[offset] != Usage::Code {
+                    //overlaps an instruction or sprite we already found
+                    break;
+                }
+                let opcode = (u16::from(self.rom[offset]) << 8) | u16::from(self.rom[offset + 1]);
+                let instruction = Instruction::decode(opcode);
+                if let Instruction::Unknown(_) = instruction {
+                    if fresh {
+                        self.undecoded.insert(address);
+                    }
+                    break;
+                }
+                self.usage[offset] = Usage::Code;
+                self.usage[offset + 1] = Usage::CodeTail;
+                let next = address.wrapping_add(INSTRUCTION_WIDTH);
+
+                match instruction {
+                    Instruction::Jmp(target) => {
+                        self.add_label(target, LabelKind::Jump);
+                        pending.push((target, index));
+                        break;
+                    }
+                    Instruction::Jmi(target) => {
+                        self.add_label(target, LabelKind::Jump);
+                        pending.push((target, index));
+                        break;
+                    }
+                    Instruction::Jsr(target) => {
+                        self.add_label(target, LabelKind::Subroutine);
+                        pending.push((target, None));
+                        //the subroutine may well have moved I
+                        index = None;
+                    }
+                    Instruction::Rts => break,
+                    Instruction::Mvi(target) => {
+                        self.add_label(target, LabelKind::Data);
+                        index = Some(target);
+                    }
+                    Instruction::Sprite(_, _, lines) => {
+                        if let Some(sprite) = index {
+                            self.sprites.push((sprite, lines));
+                        }
+                    }
+                    Instruction::Adi(_)
+                    | Instruction::Font(_)
+                    | Instruction::Str(_)
+                    | Instruction::Ldr(_) => index = None,
+                    i if i.is_skip() => {
+                        pending.push((next.wrapping_add(INSTRUCTION_WIDTH), index));
+                    }
+                    _ => {}
+                }
+                address = next;
+            }
+        }
+    }
+
+    ///mark the bytes drawn by `sprite` that aren't also code
+    fn mark_sprites(&mut self) {
+        let sprites = std::mem::take(&mut self.sprites);
+        for (address, lines) in sprites {
+            let mut drawn = false;
+            for line in 0..u16::from(lines) {
+                if let Some(offset) = self.offset(address + line) {
+                    if self.usage[offset] == Usage::Unknown || self.usage[offset] == Usage::Sprite {
+                        self.usage[offset] = Usage::Sprite;
+                        drawn = true;
+                    }
+                }
+            }
+            if drawn {
+                self.add_label(address, LabelKind::Sprite);
+            }
+        }
+    }
+
+    ///name for an address operand, a label if one will be emitted
+    fn target_name(&self, address: u16) -> String {
+        if self.is_line_start(address) {
+            if let Some(name) = self.label(address) {
+                return name;
+            }
+        }
+        format!("0x{:03X}", address)
+    }
+
+    fn plain(&self, instruction: Instruction) -> String {
+        match instruction.target() {
+            Some(address) => instruction.format_with_target(&self.target_name(address)),
+            None => instruction.to_string(),
+        }
+    }
+
+    fn octo(&self, instruction: Instruction) -> String {
+        use instruction::Instruction::*;
+
+        match instruction {
+            Sys(address) => format!("0x{:02X} 0x{:02X}", address >> 8, address & 0xFF),
+            Cls => "clear".into(),
+            Rts => "return".into(),
+            Jmp(address) => format!("jump {}", self.target_name(address)),
+            Jsr(address) => {
+                let name = self.target_name(address);
+                if name.starts_with("0x") {
+                    format!(":call {}", name)
+                } else {
+                    name
+                }
+            }
+            SkeqConst(x, kk) => format!("if v{:x} != 0x{:02X} then", x, kk),
+            SkneConst(x, kk) => format!("if v{:x} == 0x{:02X} then", x, kk),
+            SkeqReg(x, y) => format!("if v{:x} != v{:x} then", x, y),
+            SkneReg(x, y) => format!("if v{:x} == v{:x} then", x, y),
+            MovConst(x, kk) => format!("v{:x} := 0x{:02X}", x, kk),
+            AddConst(x, kk) => format!("v{:x} += 0x{:02X}", x, kk),
+            MovReg(x, y) => format!("v{:x} := v{:x}", x, y),
+            OrReg(x, y) => format!("v{:x} |= v{:x}", x, y),
+            AndReg(x, y) => format!("v{:x} &= v{:x}", x, y),
+            XorReg(x, y) => format!("v{:x} ^= v{:x}", x, y),
+            AddReg(x, y) => format!("v{:x} += v{:x}", x, y),
+            SubReg(x, y) => format!("v{:x} -= v{:x}", x, y),
+            Shr(x, y) => format!("v{:x} >>= v{:x}", x, y),
+            Rsb(x, y) => format!("v{:x} =- v{:x}", x, y),
+            Shl(x, y) => format!("v{:x} <<= v{:x}", x, y),
+            Mvi(address) => format!("i := {}", self.target_name(address)),
+            Jmi(address) => format!("jump0 {}", self.target_name(address)),
+            Rand(x, kk) => format!("v{:x} := random 0x{:02X}", x, kk),
+            Sprite(x, y, n) => format!("sprite v{:x} v{:x} {}", x, y, n),
+            Skpr(x) => format!("if v{:x} -key then", x),
+            Skup(x) => format!("if v{:x} key then", x),
+            Gdelay(x) => format!("v{:x} := delay", x),
+            Key(x) => format!("v{:x} := key", x),
+            Sdelay(x) => format!("delay := v{:x}", x),
+            Ssound(x) => format!("buzzer := v{:x}", x),
+            Adi(x) => format!("i += v{:x}", x),
+            Font(x) => format!("i := hex v{:x}", x),
+            Bcd(x) => format!("bcd v{:x}", x),
+            Str(x) => format!("save v{:x}", x),
+            Ldr(x) => format!("load v{:x}", x),
+            Unknown(opcode) => format!("0x{:02X} 0x{:02X}", opcode >> 8, opcode & 0xFF),
+        }
+    }
+
+    fn data(&self, bytes: &[u8], syntax: Syntax) -> String {
+        let values: Vec<String> = bytes.iter().map(|b| format!("0x{:02X}", b)).collect();
+        match syntax {
+            Syntax::Plain => format!("db {}", values.join(", ")),
+            Syntax::Octo => values.join(" "),
+        }
+    }
+}
+
+///render a byte of sprite data as ASCII art
+pub fn sprite_row(byte: u8) -> String {
+    (0..8)
+        .map(|bit| if byte & (0x80 >> bit) != 0 { '#' } else { '.' })
+        .collect()
+}
+
+///disassemble a whole ROM into a listing
+pub fn disassemble(rom: &[u8], syntax: Syntax) -> String {
+    Disassembly::new(rom).listing(syntax)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use octo;
+    use std::fs;
+
+    //the start of roms/MAZE
+    const MAZE: [u8; 34] = [
+        0xa2, 0x1e, 0xc2, 0x01, 0x32, 0x01, 0xa2, 0x1a, 0xd0, 0x14, 0x70, 0x04, 0x30, 0x40, 0x12,
+        0x00, 0x60, 0x00, 0x71, 0x04, 0x31, 0x20, 0x12, 0x00, 0x12, 0x18, 0x80, 0x40, 0x20, 0x10,
+        0x20, 0x40, 0x80, 0x10,
+    ];
+
+    #[test]
+    fn test_code_and_sprites() {
+        let d = Disassembly::new(&MAZE);
+        assert!(d.is_code(0x200));
+        assert!(d.is_code(0x218));
+        assert!(!d.is_code(0x201));
+        //both `mvi` targets are drawn with height 4
+        assert!(d.is_sprite(0x21A));
+        assert!(d.is_sprite(0x21E));
+        assert!(d.is_sprite(0x221));
+    }
+
+    #[test]
+    fn test_labels() {
+        let d = Disassembly::new(&MAZE);
+        assert_eq!(d.label(0x200), Some("label_200".into()));
+        assert_eq!(d.label(0x218), Some("label_218".into()));
+        assert_eq!(d.label(0x21A), Some("sprite_21A".into()));
+        assert_eq!(d.label(0x202), None);
+    }
+
+    #[test]
+    fn test_plain_listing() {
+        let listing = disassemble(&MAZE, Syntax::Plain);
+        assert!(listing.contains("label_200:\n"));
+        assert!(listing.contains("    mvi sprite_21E"));
+        assert!(listing.contains("    jmp label_218"));
+        assert!(listing.contains("db 0x80                 ; #......."));
+    }
+
+    #[test]
+    fn test_octo_listing() {
+        let listing = disassemble(&MAZE, Syntax::Octo);
+        assert!(listing.contains(": label_200\n"));
+        assert!(listing.contains("    i := sprite_21E"));
+        assert!(listing.contains("    if v2 != 0x01 then"));
+        assert!(listing.contains("    0x80                    # #......."));
+    }
+
+    #[test]
+    fn test_octo_round_trip() {
+        for entry in fs::read_dir("roms").unwrap() {
+            let path = entry.unwrap().path();
+            let rom = fs::read(&path).unwrap();
+            let listing = disassemble(&rom, Syntax::Octo);
+            let name = path.display().to_string();
+            let assembly = octo::compile(&listing, &name).unwrap_or_else(|e| panic!("{}", e));
+            assert!(assembly.rom == rom, "{} doesn't reassemble", name);
+        }
+    }
+
+    #[test]
+    fn test_unreached_bytes_are_data() {
+        let rom = [0x12, 0x00, 0xFF, 0xFE];
+        let listing = disassemble(&rom, Syntax::Plain);
+        assert!(listing.contains("    db 0xFF, 0xFE"));
+    }
+
+    #[test]
+    fn test_sprite_row() {
+        assert_eq!(sprite_row(0xF0), "####....");
+        assert_eq!(sprite_row(0x81), "#......#");
+    }
+
+    #[test]
+    fn test_syntax_from_str() {
+        assert_eq!("octo".parse::<Syntax>(), Ok(Syntax::Octo));
+        assert!("bogus".parse::<Syntax>().is_err());
+    }
+}
@@ -0,0 +1,100 @@
+//! What happens when a ROM does something the machine can't
+//!
+//! Faults go through the cpu's `FaultPolicy`, which either halts it with the
+//! fault kept for the frontend to report, or steps over the offending
+//! instruction and carries on.
+
+use failure;
+use std::fmt;
+use std::str::FromStr;
+
+///Something a ROM did that the machine couldn't
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    ///an opcode that decodes to no instruction
+    UnknownOpcode { pc: u16, opcode: u16 },
+    ///a `0NNN` call with no routine registered for NNN
+    UnknownRoutine { pc: u16, address: u16 },
+    ///a 2NNN call with the stack already at the quirks' `stack_depth`
+    StackOverflow { pc: u16 },
+    ///a 00EE return with nothing on the stack
+    StackUnderflow { pc: u16 },
+}
+
+impl Fault {
+    ///where the offending instruction is
+    pub fn pc(&self) -> u16 {
+        match *self {
+            Fault::UnknownOpcode { pc, .. }
+            | Fault::UnknownRoutine { pc, .. }
+            | Fault::StackOverflow { pc }
+            | Fault::StackUnderflow { pc } => pc,
+        }
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Fault::UnknownOpcode { pc, opcode } => {
+                write!(f, "unknown opcode 0x{:04X} at 0x{:03X}", opcode, pc)
+            }
+            Fault::UnknownRoutine { pc, address } => write!(
+                f,
+                "no machine code routine at 0x{:03X}, called from 0x{:03X}",
+                address, pc
+            ),
+            Fault::StackOverflow { pc } => write!(f, "call stack overflow at 0x{:03X}", pc),
+            Fault::StackUnderflow { pc } => {
+                write!(f, "return with an empty call stack at 0x{:03X}", pc)
+            }
+        }
+    }
+}
+
+impl failure::Fail for Fault {}
+
+///What to do about a fault
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FaultPolicy {
+    ///stop running, keeping the fault
+    #[default]
+    Halt,
+    ///log a warning and carry on after the instruction
+    Skip,
+    ///carry on after the instruction without a word
+    Ignore,
+}
+
+impl FromStr for FaultPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "halt" => Ok(FaultPolicy::Halt),
+            "skip" => Ok(FaultPolicy::Skip),
+            "ignore" => Ok(FaultPolicy::Ignore),
+            _ => Err(format!("unknown fault policy '{}'", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let fault = Fault::UnknownRoutine {
+            pc: 0x204,
+            address: 0x123,
+        };
+        assert_eq!(fault.pc(), 0x204);
+        assert_eq!(
+            fault.to_string(),
+            "no machine code routine at 0x123, called from 0x204"
+        );
+        assert_eq!("skip".parse(), Ok(FaultPolicy::Skip));
+        assert!("panic".parse::<FaultPolicy>().is_err());
+    }
+}
@@ -0,0 +1,205 @@
+//! Running without a window, for scripts and tests
+//!
+//! Execution is counted in frames of the 60Hz timer, each running
+//! `TICKS_PER_FRAME` instructions. Key presses come from a script of
+//! `FRAME:KEY[:FRAMES]` entries separated by whitespace or commas, each
+//! holding the hex KEY down from FRAME for FRAMES frames, one by default.
+//! `#` starts a comment that runs to the end of the line.
+
+use cpu::{Cpu, SCREEN_HEIGHT, SCREEN_WIDTH};
+use failure;
+use std::fmt::Write;
+use std::ops::Range;
+
+///Instructions run per frame, the same pace as the windowed runner
+pub const TICKS_PER_FRAME: u32 = 100;
+
+///A key held down for a span of frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyPress {
+    pub frame: u32,
+    pub key: u8,
+    pub frames: u32,
+}
+
+///parse a key script
+pub fn parse_keys(script: &str) -> Result<Vec<KeyPress>, failure::Error> {
+    let mut presses = Vec::new();
+    for line in script.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        for entry in line.split(|c: char| c.is_whitespace() || c == ',') {
+            if entry.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = entry.split(':').collect();
+            let press = match fields[..] {
+                [frame, key] | [frame, key, _] => KeyPress {
+                    frame: frame.parse()?,
+                    key: u8::from_str_radix(key, 16)?,
+                    frames: match fields.get(2) {
+                        Some(frames) => frames.parse()?,
+                        None => 1,
+                    },
+                },
+                _ => bail!("malformed key press '{}'", entry),
+            };
+            if press.key > 0x0F {
+                bail!("no such key '{}' in '{}'", fields[1], entry);
+            }
+            presses.push(press);
+        }
+    }
+    Ok(presses)
+}
+
+///the keys down during `frame` as a bitmap, bit N for key N
+pub fn keys_at(keys: &[KeyPress], frame: u32) -> u16 {
+    keys.iter()
+        .filter(|p| frame >= p.frame && frame - p.frame < p.frames)
+        .fold(0, |down, p| down | 1 << p.key)
+}
+
+///run `frames` frames, pressing the keys in `keys`, with `tick` running a
+///single instruction so callers can profile or trace it
+pub fn run<F>(cpu: &mut Cpu, frames: u32, keys: &[KeyPress], tick: F)
+where
+    F: FnMut(&mut Cpu),
+{
+    run_frames(cpu, 0..frames, |frame| keys_at(keys, frame), tick)
+}
+
+///run each of `frames`, setting the keypad at the start of each to what
+///`keys` returns for it
+pub fn run_frames<K, F>(cpu: &mut Cpu, frames: Range<u32>, mut keys: K, mut tick: F)
+where
+    K: FnMut(u32) -> u16,
+    F: FnMut(&mut Cpu),
+{
+    for frame in frames {
+        cpu.set_keys(keys(frame));
+        for _ in 0..TICKS_PER_FRAME {
+            tick(cpu);
+        }
+        cpu.tick_timers();
+    }
+}
+
+///64-bit FNV-1a hash of the framebuffer, one byte per pixel
+pub fn screen_hash(cpu: &Cpu) -> u64 {
+    cpu.screen()
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325, |hash, &pixel| {
+            (hash ^ u64::from(pixel)).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+///the framebuffer as ASCII art, `#` for lit pixels
+pub fn screen_ascii(cpu: &Cpu) -> String {
+    let mut out = String::with_capacity((SCREEN_WIDTH + 1) * SCREEN_HEIGHT);
+    for row in cpu.screen().chunks(SCREEN_WIDTH) {
+        out.extend(row.iter().map(|&pixel| if pixel { '#' } else { '.' }));
+        out.push('\n');
+    }
+    out
+}
+
+///the machine state as a single JSON object
+pub fn state_json(cpu: &Cpu, frames: u32) -> String {
+    let list = |values: Vec<String>| values.join(",");
+    let mut out = String::new();
+    let _ = write!(
+        out,
+        "{{\"frames\":{},\"pc\":{},\"i\":{},\"registers\":[{}],\"stack\":[{}],\
+         \"delay\":{},\"sound\":{},\"screen_hash\":\"{:016x}\"}}",
+        frames,
+        cpu.pc(),
+        cpu.i(),
+        list(cpu.registers().iter().map(|v| v.to_string()).collect()),
+        list(cpu.stack().iter().map(|a| a.to_string()).collect()),
+        cpu.delay(),
+        cpu.sound(),
+        screen_hash(cpu)
+    );
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_keys() {
+        let keys = parse_keys("10:5, 20:f:3 # fire\n30:A").unwrap();
+        assert_eq!(
+            keys,
+            vec![
+                KeyPress {
+                    frame: 10,
+                    key: 5,
+                    frames: 1
+                },
+                KeyPress {
+                    frame: 20,
+                    key: 0xF,
+                    frames: 3
+                },
+                KeyPress {
+                    frame: 30,
+                    key: 0xA,
+                    frames: 1
+                },
+            ]
+        );
+        assert!(parse_keys("10").is_err());
+        assert!(parse_keys("10:10").is_err());
+        assert!(parse_keys("x:1").is_err());
+    }
+
+    #[test]
+    fn test_keys_at() {
+        let keys = parse_keys("1:0:2 2:f").unwrap();
+        assert_eq!(keys_at(&keys, 0), 0);
+        assert_eq!(keys_at(&keys, 1), 0x0001);
+        assert_eq!(keys_at(&keys, 2), 0x8001);
+        assert_eq!(keys_at(&keys, 3), 0);
+    }
+
+    #[test]
+    fn test_run_presses_keys() {
+        //0x200: skup v0 ; 0x202: add v1,1 ; 0x204: jmp 0x200
+        let rom = [0xE0, 0xA1, 0x71, 0x01, 0x12, 0x00];
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&rom).unwrap();
+        let keys = parse_keys("1:0").unwrap();
+        run(&mut cpu, 3, &keys, |cpu| cpu.tick());
+        //33 loops while key 0 is down in the middle frame, and the add the
+        //last of them was about to run when it was released
+        assert_eq!(cpu.registers()[1], 34);
+    }
+
+    #[test]
+    fn test_state_json() {
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&[0x12, 0x00]).unwrap();
+        run(&mut cpu, 2, &[], |cpu| cpu.tick());
+        let json = state_json(&cpu, 2);
+        assert!(json.starts_with("{\"frames\":2,\"pc\":512,\"i\":0,\"registers\":[0,0,"));
+        assert!(json.contains("\"stack\":[],\"delay\":0,\"sound\":0,"));
+        assert!(json.ends_with(&format!("\"screen_hash\":\"{:016x}\"}}", screen_hash(&cpu))));
+    }
+
+    #[test]
+    fn test_screen() {
+        //mvi 0x050 ; sprite v0,v0,5, drawing the font's 0 at the origin
+        let rom = [0xA0, 0x50, 0xD0, 0x05];
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&rom).unwrap();
+        let blank = screen_hash(&cpu);
+        cpu.tick();
+        cpu.tick();
+        assert_ne!(screen_hash(&cpu), blank);
+        let ascii = screen_ascii(&cpu);
+        assert_eq!(ascii.lines().count(), SCREEN_HEIGHT);
+        assert!(ascii.starts_with(&format!("####{}\n#..#", ".".repeat(SCREEN_WIDTH - 4))));
+    }
+}
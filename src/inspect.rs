@@ -0,0 +1,265 @@
+//! What a ROM is and what it needs, worked out without running it
+//!
+//! The instructions are the ones `disasm::Disassembly` reaches by tracing
+//! from the entry point, plus the opcodes it stopped at, which is where
+//! SUPER-CHIP and XO-CHIP instructions this emulator can't run show up.
+//! Each is counted by its opcode pattern, e.g. `8XY6`.
+
+use disasm::{Disassembly, ROM_START};
+use flate2::Crc;
+use movie::rom_sha1;
+use romdb::{Database, Entry, Platform};
+use std::collections::BTreeMap;
+use std::fmt;
+
+///(mask, value, pattern, platform that added it), the first match wins
+const PATTERNS: &[(u16, u16, &str, Platform)] = &[
+    (0xFFFF, 0x00E0, "00E0", Platform::Chip8),
+    (0xFFFF, 0x00EE, "00EE", Platform::Chip8),
+    (0xFFF0, 0x00C0, "00CN", Platform::Schip),
+    (0xFFF0, 0x00D0, "00DN", Platform::XoChip),
+    (0xFFFF, 0x00FB, "00FB", Platform::Schip),
+    (0xFFFF, 0x00FC, "00FC", Platform::Schip),
+    (0xFFFF, 0x00FD, "00FD", Platform::Schip),
+    (0xFFFF, 0x00FE, "00FE", Platform::Schip),
+    (0xFFFF, 0x00FF, "00FF", Platform::Schip),
+    (0xF000, 0x0000, "0NNN", Platform::Chip8),
+    (0xF000, 0x1000, "1NNN", Platform::Chip8),
+    (0xF000, 0x2000, "2NNN", Platform::Chip8),
+    (0xF000, 0x3000, "3XNN", Platform::Chip8),
+    (0xF000, 0x4000, "4XNN", Platform::Chip8),
+    (0xF00F, 0x5000, "5XY0", Platform::Chip8),
+    (0xF00F, 0x5002, "5XY2", Platform::XoChip),
+    (0xF00F, 0x5003, "5XY3", Platform::XoChip),
+    (0xF000, 0x6000, "6XNN", Platform::Chip8),
+    (0xF000, 0x7000, "7XNN", Platform::Chip8),
+    (0xF00F, 0x8000, "8XY0", Platform::Chip8),
+    (0xF00F, 0x8001, "8XY1", Platform::Chip8),
+    (0xF00F, 0x8002, "8XY2", Platform::Chip8),
+    (0xF00F, 0x8003, "8XY3", Platform::Chip8),
+    (0xF00F, 0x8004, "8XY4", Platform::Chip8),
+    (0xF00F, 0x8005, "8XY5", Platform::Chip8),
+    (0xF00F, 0x8006, "8XY6", Platform::Chip8),
+    (0xF00F, 0x8007, "8XY7", Platform::Chip8),
+    (0xF00F, 0x800E, "8XYE", Platform::Chip8),
+    (0xF00F, 0x9000, "9XY0", Platform::Chip8),
+    (0xF000, 0xA000, "ANNN", Platform::Chip8),
+    (0xF000, 0xB000, "BNNN", Platform::Chip8),
+    (0xF000, 0xC000, "CXNN", Platform::Chip8),
+    (0xF00F, 0xD000, "DXY0", Platform::Schip),
+    (0xF000, 0xD000, "DXYN", Platform::Chip8),
+    (0xF0FF, 0xE09E, "EX9E", Platform::Chip8),
+    (0xF0FF, 0xE0A1, "EXA1", Platform::Chip8),
+    (0xFFFF, 0xF000, "F000", Platform::XoChip),
+    (0xFFFF, 0xF002, "F002", Platform::XoChip),
+    (0xF0FF, 0xF001, "FN01", Platform::XoChip),
+    (0xF0FF, 0xF007, "FX07", Platform::Chip8),
+    (0xF0FF, 0xF00A, "FX0A", Platform::Chip8),
+    (0xF0FF, 0xF015, "FX15", Platform::Chip8),
+    (0xF0FF, 0xF018, "FX18", Platform::Chip8),
+    (0xF0FF, 0xF01E, "FX1E", Platform::Chip8),
+    (0xF0FF, 0xF029, "FX29", Platform::Chip8),
+    (0xF0FF, 0xF030, "FX30", Platform::Schip),
+    (0xF0FF, 0xF033, "FX33", Platform::Chip8),
+    (0xF0FF, 0xF03A, "FX3A", Platform::XoChip),
+    (0xF0FF, 0xF055, "FX55", Platform::Chip8),
+    (0xF0FF, 0xF065, "FX65", Platform::Chip8),
+    (0xF0FF, 0xF075, "FX75", Platform::Schip),
+    (0xF0FF, 0xF085, "FX85", Platform::Schip),
+];
+
+///the opcode patterns that behave differently with each `Quirks` flag
+const QUIRKY: &[(&str, &str)] = &[
+    ("8XY6", "shift"),
+    ("8XYE", "shift"),
+    ("FX55", "load_store"),
+    ("FX65", "load_store"),
+    ("BNNN", "jump"),
+];
+
+///the pattern of an opcode, e.g. `8XY6`, and the platform that added it,
+///or `None` if no platform has it
+pub fn pattern(opcode: u16) -> Option<(&'static str, Platform)> {
+    PATTERNS
+        .iter()
+        .find(|&&(mask, value, _, _)| opcode & mask == value)
+        .map(|&(_, _, pattern, platform)| (pattern, platform))
+}
+
+///Everything `chip8 info` prints about a ROM
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub size: usize,
+    pub sha1: String,
+    pub crc32: u32,
+    pub entry: Option<Entry>,
+    ///how many of each opcode pattern the trace found, `????` for opcodes
+    ///no platform has
+    pub histogram: BTreeMap<&'static str, usize>,
+    ///(address, pattern, platform) of each SUPER-CHIP or XO-CHIP opcode
+    pub extensions: Vec<(u16, &'static str, Platform)>,
+    ///(address, target) of each `0NNN` machine code call
+    pub sys_calls: Vec<(u16, u16)>,
+    ///the addresses of each quirk-sensitive pattern found, by pattern
+    pub quirky: BTreeMap<&'static str, Vec<u16>>,
+}
+
+impl Report {
+    ///inspect a ROM, looking it up in `database`
+    pub fn new(rom: &[u8], database: &Database) -> Self {
+        let mut crc = Crc::new();
+        crc.update(rom);
+        let mut report = Report {
+            size: rom.len(),
+            sha1: rom_sha1(rom),
+            crc32: crc.sum(),
+            entry: database.lookup(rom).cloned(),
+            histogram: BTreeMap::new(),
+            extensions: Vec::new(),
+            sys_calls: Vec::new(),
+            quirky: BTreeMap::new(),
+        };
+
+        let disassembly = Disassembly::new(rom);
+        for (offset, pair) in rom.windows(2).enumerate() {
+            let address = ROM_START + offset as u16;
+            let found = disassembly.is_code(address)
+                || (disassembly.undecoded().contains(&address) && !disassembly.is_sprite(address));
+            if !found {
+                continue;
+            }
+            let opcode = u16::from(pair[0]) << 8 | u16::from(pair[1]);
+            let (pattern, platform) = match pattern(opcode) {
+                Some((pattern, platform)) => (pattern, Some(platform)),
+                None => ("????", None),
+            };
+            *report.histogram.entry(pattern).or_default() += 1;
+            match platform {
+                Some(Platform::Chip8) | None => {}
+                Some(platform) => report.extensions.push((address, pattern, platform)),
+            }
+            if pattern == "0NNN" {
+                report.sys_calls.push((address, opcode & 0x0FFF));
+            }
+            if QUIRKY.iter().any(|&(quirky, _)| quirky == pattern) {
+                report.quirky.entry(pattern).or_default().push(address);
+            }
+        }
+        report
+    }
+}
+
+fn addresses(addresses: &[u16]) -> String {
+    addresses
+        .iter()
+        .map(|address| format!("0x{:03X}", address))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "size: {} bytes", self.size)?;
+        writeln!(f, "sha1: {}", self.sha1)?;
+        writeln!(f, "crc32: {:08x}", self.crc32)?;
+        match self.entry {
+            Some(ref entry) => {
+                writeln!(f, "database: {}", entry)?;
+                if let Some(quirks) = entry.quirks {
+                    writeln!(f, "  quirks: {}", quirks)?;
+                }
+                if let Some(tickrate) = entry.tickrate {
+                    writeln!(f, "  tickrate: {}", tickrate)?;
+                }
+                if let Some(ref keys) = entry.keys {
+                    writeln!(f, "  keys: {}", keys)?;
+                }
+            }
+            None => writeln!(f, "database: not found")?,
+        }
+
+        let total: usize = self.histogram.values().sum();
+        writeln!(f, "instructions: {} found by tracing", total)?;
+        let mut counts: Vec<_> = self.histogram.iter().collect();
+        counts.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (pattern, count) in counts {
+            writeln!(f, "  {} {:>5}", pattern, count)?;
+        }
+
+        if self.extensions.is_empty() {
+            writeln!(f, "extensions: none")?;
+        } else {
+            writeln!(f, "extensions:")?;
+            for &(address, pattern, platform) in &self.extensions {
+                writeln!(f, "  0x{:03X} {} ({})", address, pattern, platform)?;
+            }
+        }
+
+        if self.sys_calls.is_empty() {
+            writeln!(f, "0NNN calls: none")?;
+        } else {
+            writeln!(f, "0NNN calls:")?;
+            for &(address, target) in &self.sys_calls {
+                writeln!(f, "  0x{:03X} calls 0x{:03X}", address, target)?;
+            }
+        }
+
+        if self.quirky.is_empty() {
+            writeln!(f, "quirk-sensitive: none")
+        } else {
+            writeln!(f, "quirk-sensitive:")?;
+            for &(pattern, quirk) in QUIRKY {
+                if let Some(found) = self.quirky.get(pattern) {
+                    writeln!(f, "  {} ({}) at {}", pattern, quirk, addresses(found))?;
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pattern() {
+        assert_eq!(pattern(0x00E0), Some(("00E0", Platform::Chip8)));
+        assert_eq!(pattern(0x0123), Some(("0NNN", Platform::Chip8)));
+        assert_eq!(pattern(0x00FF), Some(("00FF", Platform::Schip)));
+        assert_eq!(pattern(0xD120), Some(("DXY0", Platform::Schip)));
+        assert_eq!(pattern(0xD125), Some(("DXYN", Platform::Chip8)));
+        assert_eq!(pattern(0xF201), Some(("FN01", Platform::XoChip)));
+        assert_eq!(pattern(0x8AB6), Some(("8XY6", Platform::Chip8)));
+        assert_eq!(pattern(0x5121), None);
+    }
+
+    #[test]
+    fn test_report() {
+        let rom = [
+            0x81, 0x26, //shr v1, v2
+            0xF2, 0x55, //str v0-v2
+            0x01, 0x23, //sys 0x123
+            0x00, 0xFF, //high
+            0xF1, 0x75, //save flags, where the trace stops
+        ];
+        let report = Report::new(&rom, &Database::default());
+        assert_eq!(report.size, 10);
+        assert_eq!(report.entry, None);
+        assert_eq!(report.histogram.values().sum::<usize>(), 5);
+        assert_eq!(report.histogram["FX55"], 1);
+        assert_eq!(
+            report.extensions,
+            vec![
+                (0x206, "00FF", Platform::Schip),
+                (0x208, "FX75", Platform::Schip)
+            ]
+        );
+        assert_eq!(report.sys_calls, vec![(0x204, 0x123)]);
+        assert_eq!(report.quirky["8XY6"], vec![0x200]);
+        assert_eq!(report.quirky["FX55"], vec![0x202]);
+        let text = report.to_string();
+        assert!(text.contains("8XY6 (shift) at 0x200"));
+        assert!(text.contains("0x204 calls 0x123"));
+    }
+}
@@ -0,0 +1,334 @@
+//! Decoding of raw CHIP-8 opcodes into instructions
+//!
+//! The mnemonics follow the doc comments on the `Cpu` instruction
+//! implementations, e.g. `skeq vx,rr` or `sprite vx,vy,n`.
+
+use std::fmt;
+
+///Width in bytes of every CHIP-8 instruction
+pub const INSTRUCTION_WIDTH: u16 = 2;
+
+///A single decoded CHIP-8 instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    ///0NNN call machine code routine at NNN
+    Sys(u16),
+    ///00E0
+    Cls,
+    ///00EE
+    Rts,
+    ///1NNN
+    Jmp(u16),
+    ///2NNN
+    Jsr(u16),
+    ///3XRR
+    SkeqConst(u8, u8),
+    ///4XRR
+    SkneConst(u8, u8),
+    ///5XY0
+    SkeqReg(u8, u8),
+    ///6XRR
+    MovConst(u8, u8),
+    ///7XRR
+    AddConst(u8, u8),
+    ///8XY0
+    MovReg(u8, u8),
+    ///8XY1
+    OrReg(u8, u8),
+    ///8XY2
+    AndReg(u8, u8),
+    ///8XY3
+    XorReg(u8, u8),
+    ///8XY4
+    AddReg(u8, u8),
+    ///8XY5
+    SubReg(u8, u8),
+    ///8XY6
+    Shr(u8, u8),
+    ///8XY7
+    Rsb(u8, u8),
+    ///8XYE
+    Shl(u8, u8),
+    ///9XY0
+    SkneReg(u8, u8),
+    ///ANNN
+    Mvi(u16),
+    ///BNNN
+    Jmi(u16),
+    ///CXKK
+    Rand(u8, u8),
+    ///DXYN
+    Sprite(u8, u8, u8),
+    ///EX9E
+    Skpr(u8),
+    ///EXA1
+    Skup(u8),
+    ///FX07
+    Gdelay(u8),
+    ///FX0A
+    Key(u8),
+    ///FX15
+    Sdelay(u8),
+    ///FX18
+    Ssound(u8),
+    ///FX1E
+    Adi(u8),
+    ///FX29
+    Font(u8),
+    ///FX33
+    Bcd(u8),
+    ///FX55
+    Str(u8),
+    ///FX65
+    Ldr(u8),
+    ///anything else, kept verbatim
+    Unknown(u16),
+}
+
+impl Instruction {
+    ///decode a big-endian opcode
+    pub fn decode(opcode: u16) -> Self {
+        use self::Instruction::*;
+
+        let address = opcode & 0x0FFF;
+        let value = (opcode & 0x00FF) as u8;
+        let x = ((opcode >> 8) & 0x000F) as u8;
+        let y = ((opcode >> 4) & 0x000F) as u8;
+        let n = (opcode & 0x000F) as u8;
+        match opcode & 0xF000 {
+            0x0000 => match opcode {
+                0x00E0 => Cls,
+                0x00EE => Rts,
+                _ => Sys(address),
+            },
+            0x1000 => Jmp(address),
+            0x2000 => Jsr(address),
+            0x3000 => SkeqConst(x, value),
+            0x4000 => SkneConst(x, value),
+            0x5000 if n == 0 => SkeqReg(x, y),
+            0x6000 => MovConst(x, value),
+            0x7000 => AddConst(x, value),
+            0x8000 => match n {
+                0x0 => MovReg(x, y),
+                0x1 => OrReg(x, y),
+                0x2 => AndReg(x, y),
+                0x3 => XorReg(x, y),
+                0x4 => AddReg(x, y),
+                0x5 => SubReg(x, y),
+                0x6 => Shr(x, y),
+                0x7 => Rsb(x, y),
+                0xE => Shl(x, y),
+                _ => Unknown(opcode),
+            },
+            0x9000 if n == 0 => SkneReg(x, y),
+            0xA000 => Mvi(address),
+            0xB000 => Jmi(address),
+            0xC000 => Rand(x, value),
+            0xD000 => Sprite(x, y, n),
+            0xE000 => match value {
+                0x9E => Skpr(x),
+                0xA1 => Skup(x),
+                _ => Unknown(opcode),
+            },
+            0xF000 => match value {
+                0x07 => Gdelay(x),
+                0x0A => Key(x),
+                0x15 => Sdelay(x),
+                0x18 => Ssound(x),
+                0x1E => Adi(x),
+                0x29 => Font(x),
+                0x33 => Bcd(x),
+                0x55 => Str(x),
+                0x65 => Ldr(x),
+                _ => Unknown(opcode),
+            },
+            _ => Unknown(opcode),
+        }
+    }
+
+    ///decode the instruction stored big-endian at `address` in `memory`,
+    ///or `None` if it runs off the end
+    pub fn fetch(memory: &[u8], address: usize) -> Option<Self> {
+        if address + 1 < memory.len() {
+            let opcode = (u16::from(memory[address]) << 8) | u16::from(memory[address + 1]);
+            Some(Instruction::decode(opcode))
+        } else {
+            None
+        }
+    }
+
+    ///encode back into a big-endian opcode
+    pub fn encode(&self) -> u16 {
+        use self::Instruction::*;
+
+        let xy =
+            |base: u16, x: u8, y: u8| base | (u16::from(x & 0xF) << 8) | (u16::from(y & 0xF) << 4);
+        let xkk = |base: u16, x: u8, kk: u8| base | (u16::from(x & 0xF) << 8) | u16::from(kk);
+        let fx = |low: u16, x: u8| 0xF000 | (u16::from(x & 0xF) << 8) | low;
+        match *self {
+            Sys(address) => address & 0x0FFF,
+            Cls => 0x00E0,
+            Rts => 0x00EE,
+            Jmp(address) => 0x1000 | (address & 0x0FFF),
+            Jsr(address) => 0x2000 | (address & 0x0FFF),
+            SkeqConst(x, kk) => xkk(0x3000, x, kk),
+            SkneConst(x, kk) => xkk(0x4000, x, kk),
+            SkeqReg(x, y) => xy(0x5000, x, y),
+            MovConst(x, kk) => xkk(0x6000, x, kk),
+            AddConst(x, kk) => xkk(0x7000, x, kk),
+            MovReg(x, y) => xy(0x8000, x, y),
+            OrReg(x, y) => xy(0x8001, x, y),
+            AndReg(x, y) => xy(0x8002, x, y),
+            XorReg(x, y) => xy(0x8003, x, y),
+            AddReg(x, y) => xy(0x8004, x, y),
+            SubReg(x, y) => xy(0x8005, x, y),
+            Shr(x, y) => xy(0x8006, x, y),
+            Rsb(x, y) => xy(0x8007, x, y),
+            Shl(x, y) => xy(0x800E, x, y),
+            SkneReg(x, y) => xy(0x9000, x, y),
+            Mvi(address) => 0xA000 | (address & 0x0FFF),
+            Jmi(address) => 0xB000 | (address & 0x0FFF),
+            Rand(x, kk) => xkk(0xC000, x, kk),
+            Sprite(x, y, n) => xy(0xD000, x, y) | u16::from(n & 0xF),
+            Skpr(x) => xkk(0xE000, x, 0x9E),
+            Skup(x) => xkk(0xE000, x, 0xA1),
+            Gdelay(x) => fx(0x07, x),
+            Key(x) => fx(0x0A, x),
+            Sdelay(x) => fx(0x15, x),
+            Ssound(x) => fx(0x18, x),
+            Adi(x) => fx(0x1E, x),
+            Font(x) => fx(0x29, x),
+            Bcd(x) => fx(0x33, x),
+            Str(x) => fx(0x55, x),
+            Ldr(x) => fx(0x65, x),
+            Unknown(opcode) => opcode,
+        }
+    }
+
+    ///true for the conditional skip instructions
+    pub fn is_skip(&self) -> bool {
+        use self::Instruction::*;
+
+        matches!(
+            *self,
+            SkeqConst(..) | SkneConst(..) | SkeqReg(..) | SkneReg(..) | Skpr(_) | Skup(_)
+        )
+    }
+
+    ///the address operand of a jump, call or index load
+    pub fn target(&self) -> Option<u16> {
+        use self::Instruction::*;
+
+        match *self {
+            Sys(address) | Jmp(address) | Jsr(address) | Mvi(address) | Jmi(address) => {
+                Some(address)
+            }
+            _ => None,
+        }
+    }
+
+    ///render with the address operand replaced by `target`, as used by the
+    ///disassembler to substitute labels
+    pub fn format_with_target(&self, target: &str) -> String {
+        use self::Instruction::*;
+
+        match *self {
+            Sys(_) => format!("sys {}", target),
+            Jmp(_) => format!("jmp {}", target),
+            Jsr(_) => format!("jsr {}", target),
+            Mvi(_) => format!("mvi {}", target),
+            Jmi(_) => format!("jmi {}", target),
+            _ => self.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Instruction::*;
+
+        match *self {
+            Sys(address) => write!(f, "sys 0x{:03X}", address),
+            Cls => write!(f, "cls"),
+            Rts => write!(f, "rts"),
+            Jmp(address) => write!(f, "jmp 0x{:03X}", address),
+            Jsr(address) => write!(f, "jsr 0x{:03X}", address),
+            SkeqConst(x, kk) => write!(f, "skeq v{:x},0x{:02X}", x, kk),
+            SkneConst(x, kk) => write!(f, "skne v{:x},0x{:02X}", x, kk),
+            SkeqReg(x, y) => write!(f, "skeq v{:x},v{:x}", x, y),
+            MovConst(x, kk) => write!(f, "mov v{:x},0x{:02X}", x, kk),
+            AddConst(x, kk) => write!(f, "add v{:x},0x{:02X}", x, kk),
+            MovReg(x, y) => write!(f, "mov v{:x},v{:x}", x, y),
+            OrReg(x, y) => write!(f, "or v{:x},v{:x}", x, y),
+            AndReg(x, y) => write!(f, "and v{:x},v{:x}", x, y),
+            XorReg(x, y) => write!(f, "xor v{:x},v{:x}", x, y),
+            AddReg(x, y) => write!(f, "add v{:x},v{:x}", x, y),
+            SubReg(x, y) => write!(f, "sub v{:x},v{:x}", x, y),
+            Shr(x, 0) => write!(f, "shr v{:x}", x),
+            Shr(x, y) => write!(f, "shr v{:x},v{:x}", x, y),
+            Rsb(x, y) => write!(f, "rsb v{:x},v{:x}", x, y),
+            Shl(x, 0) => write!(f, "shl v{:x}", x),
+            Shl(x, y) => write!(f, "shl v{:x},v{:x}", x, y),
+            SkneReg(x, y) => write!(f, "skne v{:x},v{:x}", x, y),
+            Mvi(address) => write!(f, "mvi 0x{:03X}", address),
+            Jmi(address) => write!(f, "jmi 0x{:03X}", address),
+            Rand(x, kk) => write!(f, "rand v{:x},0x{:02X}", x, kk),
+            Sprite(x, y, n) => write!(f, "sprite v{:x},v{:x},{}", x, y, n),
+            Skpr(x) => write!(f, "skpr v{:x}", x),
+            Skup(x) => write!(f, "skup v{:x}", x),
+            Gdelay(x) => write!(f, "gdelay v{:x}", x),
+            Key(x) => write!(f, "key v{:x}", x),
+            Sdelay(x) => write!(f, "sdelay v{:x}", x),
+            Ssound(x) => write!(f, "ssound v{:x}", x),
+            Adi(x) => write!(f, "adi v{:x}", x),
+            Font(x) => write!(f, "font v{:x}", x),
+            Bcd(x) => write!(f, "bcd v{:x}", x),
+            Str(x) => write!(f, "str v0-v{:x}", x),
+            Ldr(x) => write!(f, "ldr v0-v{:x}", x),
+            Unknown(opcode) => write!(f, "dw 0x{:04X}", opcode),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(Instruction::decode(0x00E0), Instruction::Cls);
+        assert_eq!(Instruction::decode(0x00EE), Instruction::Rts);
+        assert_eq!(Instruction::decode(0x0123), Instruction::Sys(0x123));
+        assert_eq!(Instruction::decode(0xD014), Instruction::Sprite(0, 1, 4));
+        assert_eq!(Instruction::decode(0x8AB6), Instruction::Shr(0xA, 0xB));
+        assert_eq!(Instruction::decode(0xF365), Instruction::Ldr(3));
+        assert_eq!(Instruction::decode(0x5121), Instruction::Unknown(0x5121));
+        assert_eq!(Instruction::decode(0xE1FF), Instruction::Unknown(0xE1FF));
+    }
+
+    #[test]
+    fn test_encode_roundtrip() {
+        for opcode in 0..=0xFFFFu16 {
+            assert_eq!(Instruction::decode(opcode).encode(), opcode);
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Instruction::decode(0x3A10).to_string(), "skeq va,0x10");
+        assert_eq!(Instruction::decode(0xD12F).to_string(), "sprite v1,v2,15");
+        assert_eq!(Instruction::decode(0x8306).to_string(), "shr v3");
+        assert_eq!(Instruction::decode(0xF555).to_string(), "str v0-v5");
+        assert_eq!(Instruction::decode(0xA21E).to_string(), "mvi 0x21E");
+    }
+
+    #[test]
+    fn test_fetch() {
+        let memory = [0x12, 0x00, 0x60];
+        assert_eq!(
+            Instruction::fetch(&memory, 0),
+            Some(Instruction::Jmp(0x200))
+        );
+        assert_eq!(Instruction::fetch(&memory, 2), None);
+    }
+}
@@ -1,4 +1,5 @@
 //standard includes
+#[macro_use]
 pub extern crate failure;
 #[macro_use]
 pub extern crate log;
@@ -7,7 +8,40 @@ pub extern crate stderrlog;
 pub extern crate clap;
 pub extern crate exitfailure;
 
+extern crate flate2;
+extern crate gif;
+#[cfg(unix)]
+extern crate libc;
 extern crate rand;
+extern crate serde_json;
+extern crate sha1;
+extern crate toml;
+extern crate zip;
 
+pub mod asm;
+pub mod bench;
+pub mod cartridge;
+pub mod cfg;
+pub mod config;
+pub mod coverage;
 pub mod cpu;
+pub mod debugger;
+pub mod detect;
+pub mod disasm;
+pub mod fault;
+pub mod headless;
+pub mod inspect;
+pub mod instruction;
+pub mod movie;
+pub mod octo;
+pub mod options;
+pub mod profiler;
+pub mod quirks;
+pub mod rom;
+pub mod romdb;
+pub mod routines;
+pub mod scenario;
+pub mod semihost;
+pub mod symbols;
+pub mod tui;
 pub mod utils;
@@ -0,0 +1,324 @@
+//! Recorded keypad input, for replaying a session exactly
+//!
+//! A movie holds everything that makes a run differ from another run of the
+//! same ROM: the random seed, the quirks profile and the keypad, which is
+//! only read at the start of each frame. It is kept as text, a header and
+//! then a line for every frame on which the keypad changed, giving the frame
+//! and the keys down as a hex bitmap, bit N for key N:
+//!
+//! ```text
+//! chip8-movie 1
+//! rom 7cbb5bd3d1b2ab6bb4fd5a1e3bf6c2cdf85a93b1
+//! seed 200
+//! quirks shift=1 load_store=0 clip=0 jump=0 vf_order=1 stack_depth=16
+//! frames 300
+//! tickrate 20
+//! 30 0020
+//! 35 0000
+//! ```
+//!
+//! The `tickrate` line, instructions per frame, is left out when it's the
+//! usual `TICKS_PER_FRAME`.
+//!
+//! A save state records the frame it was taken on along with the machine, so
+//! a movie can be cut back to that frame and recorded onwards from it.
+
+use cpu::Cpu;
+use failure;
+use headless::TICKS_PER_FRAME;
+use quirks::Quirks;
+use sha1::Sha1;
+use std::collections::BTreeMap;
+use std::fmt;
+
+const MOVIE_HEADER: &str = "chip8-movie 1";
+const STATE_MAGIC: &[u8] = b"CHIP8ST";
+
+///hex SHA-1 of a ROM, as movies identify them
+pub fn rom_sha1(rom: &[u8]) -> String {
+    Sha1::from(rom).digest().to_string()
+}
+
+///Keypad input by frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_sha1: String,
+    pub seed: u64,
+    pub quirks: Quirks,
+    ///length of the recording
+    pub frames: u32,
+    ///instructions per frame
+    pub tickrate: u32,
+    ///the keypad from each frame it changed on
+    inputs: BTreeMap<u32, u16>,
+}
+
+impl Movie {
+    pub fn new(rom: &[u8], seed: u64, quirks: Quirks) -> Self {
+        Movie {
+            rom_sha1: rom_sha1(rom),
+            seed,
+            quirks,
+            frames: 0,
+            tickrate: TICKS_PER_FRAME,
+            inputs: BTreeMap::new(),
+        }
+    }
+
+    ///a cpu in the state the recording started from
+    pub fn start(&self, rom: &[u8]) -> Result<Cpu, failure::Error> {
+        self.check_rom(rom)?;
+        let mut cpu = Cpu::with_quirks(self.quirks);
+        cpu.seed(self.seed);
+        cpu.load_rom(rom)?;
+        Ok(cpu)
+    }
+
+    ///fail unless the movie was recorded with `rom`
+    pub fn check_rom(&self, rom: &[u8]) -> Result<(), failure::Error> {
+        let actual = rom_sha1(rom);
+        if actual != self.rom_sha1 {
+            bail!(
+                "movie was recorded with ROM {}, not {}",
+                self.rom_sha1,
+                actual
+            );
+        }
+        Ok(())
+    }
+
+    ///the keys down during `frame`
+    pub fn keys_at(&self, frame: u32) -> u16 {
+        self.inputs
+            .range(..=frame)
+            .next_back()
+            .map_or(0, |(_, &keys)| keys)
+    }
+
+    ///note the keys down during `frame`, which follows the last recorded
+    pub fn record(&mut self, frame: u32, keys: u16) {
+        if keys != self.keys_at(frame) {
+            self.inputs.insert(frame, keys);
+        }
+        self.frames = self.frames.max(frame + 1);
+    }
+
+    ///forget everything from `frame` on, to record over it
+    pub fn truncate(&mut self, frame: u32) {
+        self.inputs.split_off(&frame);
+        self.frames = self.frames.min(frame);
+    }
+
+    pub fn parse(text: &str) -> Result<Self, failure::Error> {
+        let mut lines = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .peekable();
+        if lines.next() != Some(MOVIE_HEADER) {
+            bail!("not a movie, expected '{}' first", MOVIE_HEADER);
+        }
+        let mut header = |name: &str| match lines.next() {
+            Some(line) if line.starts_with(name) && line[name.len()..].starts_with(' ') => {
+                Ok(line[name.len() + 1..].to_string())
+            }
+            _ => Err(format_err!("movie has no '{}' line", name)),
+        };
+        let rom_sha1 = header("rom")?;
+        let seed = header("seed")?.parse()?;
+        let quirks = header("quirks")?.parse().map_err(failure::err_msg)?;
+        let frames = header("frames")?.parse()?;
+        let tickrate = match lines
+            .peek()
+            .copied()
+            .and_then(|line| line.strip_prefix("tickrate "))
+        {
+            Some(tickrate) => {
+                lines.next();
+                match tickrate.parse() {
+                    Ok(tickrate) if tickrate > 0 => tickrate,
+                    _ => bail!("malformed movie tickrate '{}'", tickrate),
+                }
+            }
+            None => TICKS_PER_FRAME,
+        };
+        let mut inputs = BTreeMap::new();
+        for line in lines {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields[..] {
+                [frame, keys] => {
+                    inputs.insert(frame.parse()?, u16::from_str_radix(keys, 16)?);
+                }
+                _ => bail!("malformed movie input '{}'", line),
+            }
+        }
+        Ok(Movie {
+            rom_sha1,
+            seed,
+            quirks,
+            frames,
+            tickrate,
+            inputs,
+        })
+    }
+}
+
+impl fmt::Display for Movie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", MOVIE_HEADER)?;
+        writeln!(f, "rom {}", self.rom_sha1)?;
+        writeln!(f, "seed {}", self.seed)?;
+        writeln!(f, "quirks {}", self.quirks)?;
+        writeln!(f, "frames {}", self.frames)?;
+        if self.tickrate != TICKS_PER_FRAME {
+            writeln!(f, "tickrate {}", self.tickrate)?;
+        }
+        for (frame, keys) in &self.inputs {
+            writeln!(f, "{} {:04x}", frame, keys)?;
+        }
+        Ok(())
+    }
+}
+
+///the machine as it is at the start of `frame`, as a save state file
+pub fn save_state(cpu: &Cpu, frame: u32) -> Vec<u8> {
+    let mut out = STATE_MAGIC.to_vec();
+    out.extend_from_slice(&frame.to_be_bytes());
+    cpu.save_state(&mut out);
+    out
+}
+
+///read a save state file, returning the machine and the frame it was taken on
+pub fn load_state(state: &[u8]) -> Result<(Cpu, u32), failure::Error> {
+    let mut input = match state.strip_prefix(STATE_MAGIC) {
+        Some(input) if input.len() >= 4 => input,
+        _ => bail!("not a save state"),
+    };
+    let frame = u32::from_be_bytes([input[0], input[1], input[2], input[3]]);
+    input = &input[4..];
+    let cpu = Cpu::load_state(&mut input)?;
+    if !input.is_empty() {
+        bail!("save state has {} bytes of trailing junk", input.len());
+    }
+    Ok((cpu, frame))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use headless;
+
+    //0x200: rnd v0,0xFF ; 0x202: sknp v1 ; 0x204: jmp 0x200 ; 0x206: add v2,v0
+    //0x208: jmp 0x200, so v2 sums the random numbers drawn while key 0 is up
+    const ROM: [u8; 10] = [0xC0, 0xFF, 0xE1, 0xA1, 0x12, 0x00, 0x82, 0x04, 0x12, 0x00];
+
+    fn play(movie: &Movie, cpu: &mut Cpu, from: u32) {
+        headless::run_frames(
+            cpu,
+            from..movie.frames,
+            |frame| movie.keys_at(frame),
+            |cpu| cpu.tick(),
+        );
+    }
+
+    #[test]
+    fn test_record() {
+        let mut movie = Movie::new(&ROM, 3, Quirks::vip());
+        for frame in 0..10 {
+            movie.record(frame, if (2..5).contains(&frame) { 0x0001 } else { 0 });
+        }
+        assert_eq!(movie.frames, 10);
+        assert_eq!(movie.inputs.len(), 2);
+        assert_eq!(movie.keys_at(1), 0);
+        assert_eq!(movie.keys_at(4), 1);
+        assert_eq!(movie.keys_at(9), 0);
+
+        movie.truncate(3);
+        assert_eq!(movie.frames, 3);
+        assert_eq!(movie.keys_at(4), 1);
+        movie.record(3, 0);
+        assert_eq!(movie.keys_at(4), 0);
+    }
+
+    #[test]
+    fn test_text_round_trip() {
+        let mut movie = Movie::new(&ROM, 200, Quirks::schip());
+        movie.record(30, 0x0020);
+        movie.record(35, 0);
+        let text = movie.to_string();
+        assert!(text.starts_with("chip8-movie 1\nrom "));
+        assert!(text.ends_with("frames 36\n30 0020\n35 0000\n"));
+        assert_eq!(Movie::parse(&text).unwrap(), movie);
+
+        assert!(Movie::parse("chip8-movie 2\n").is_err());
+        assert!(Movie::parse(&text.replace("seed", "sneed")).is_err());
+        assert!(Movie::parse(&format!("{}40\n", text)).is_err());
+
+        movie.tickrate = 20;
+        let text = movie.to_string();
+        assert!(text.contains("frames 36\ntickrate 20\n30 0020\n"));
+        assert_eq!(Movie::parse(&text).unwrap(), movie);
+        assert!(Movie::parse(&text.replace("tickrate 20", "tickrate 0")).is_err());
+    }
+
+    #[test]
+    fn test_check_rom() {
+        let movie = Movie::new(&ROM, 1, Quirks::default());
+        assert!(movie.start(&ROM).is_ok());
+        assert!(movie.start(&ROM[..8]).is_err());
+    }
+
+    #[test]
+    fn test_playback_is_exact() {
+        let mut movie = Movie::new(&ROM, 42, Quirks::default());
+        for frame in 0..20 {
+            movie.record(frame, (frame % 3 == 0) as u16);
+        }
+        let mut first = movie.start(&ROM).unwrap();
+        play(&movie, &mut first, 0);
+        let mut second = movie.start(&ROM).unwrap();
+        play(&movie, &mut second, 0);
+        assert_ne!(first.registers()[2], 0);
+        assert_eq!(first.registers(), second.registers());
+    }
+
+    #[test]
+    fn test_append_from_state() {
+        let mut movie = Movie::new(&ROM, 42, Quirks::default());
+        for frame in 0..10 {
+            movie.record(frame, (frame % 2) as u16);
+        }
+        //take a state part way through, then record different input after it
+        let mut cpu = movie.start(&ROM).unwrap();
+        headless::run_frames(
+            &mut cpu,
+            0..6,
+            |frame| movie.keys_at(frame),
+            |cpu| cpu.tick(),
+        );
+        let state = save_state(&cpu, 6);
+        let (mut cpu, frame) = load_state(&state).unwrap();
+        assert_eq!(frame, 6);
+        movie.truncate(frame);
+        headless::run_frames(&mut cpu, frame..12, |_| 1, |cpu| cpu.tick());
+        for frame in frame..12 {
+            movie.record(frame, 1);
+        }
+
+        //the whole movie played from the start ends up in the same place
+        let mut replay = movie.start(&ROM).unwrap();
+        play(&movie, &mut replay, 0);
+        assert_eq!(replay.registers(), cpu.registers());
+        assert_eq!(replay.pc(), cpu.pc());
+    }
+
+    #[test]
+    fn test_load_state_errors() {
+        let state = save_state(&Cpu::new(), 3);
+        assert!(load_state(&state[..3]).is_err());
+        assert!(load_state(&state[..state.len() - 1]).is_err());
+        let mut junk = state.clone();
+        junk.push(0);
+        assert!(load_state(&junk).is_err());
+    }
+}
@@ -0,0 +1,1135 @@
+//! Compiler for Octo assembly language (`.8o` sources)
+//!
+//! Supports labels, `:const`, `:alias`, `:macro`, `:calc`, `:byte`, `:org`,
+//! `:next`, `:unpack`, `:call`, `loop`/`while`/`again`, `if ... then`,
+//! `if ... begin ... else ... end`, `:breakpoint` and `:monitor`. Breakpoints
+//! and monitors end up in the symbol map, which is what the debugger reads.
+//!
+//! The output is plain CHIP-8 bytecode loaded at 0x200.
+
+use asm::{AsmError, Assembly};
+use disasm::ROM_START;
+use instruction::Instruction;
+use std;
+use std::collections::{HashMap, VecDeque};
+use std::f64;
+use std::fs;
+use std::path::Path;
+use symbols::{Monitor, MonitorTarget, SourceLocation, SymbolMap};
+
+///Bound on macro expansions, to stop runaway recursive macros
+const MAX_EXPANSIONS: usize = 100_000;
+
+///compile the `.8o` file at `path`
+pub fn compile_file(path: &Path) -> Result<Assembly, AsmError> {
+    let name = path.to_string_lossy().into_owned();
+    let source = fs::read_to_string(path).map_err(|e| AsmError {
+        file: name.clone(),
+        line: 0,
+        column: 0,
+        message: e.to_string(),
+    })?;
+    compile(&source, &name)
+}
+
+///compile Octo `source`, naming it `name` in errors and the symbol map
+pub fn compile(source: &str, name: &str) -> Result<Assembly, AsmError> {
+    let mut compiler = Compiler {
+        file: name.into(),
+        tokens: tokenize(source, name)?,
+        here: ROM_START,
+        ..Default::default()
+    };
+    compiler.run()?;
+    Ok(Assembly {
+        rom: compiler.rom,
+        symbols: compiler.symbols,
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Token {
+    text: String,
+    ///true if this was a quoted string
+    string: bool,
+    line: usize,
+    column: usize,
+}
+
+fn tokenize(source: &str, name: &str) -> Result<VecDeque<Token>, AsmError> {
+    let mut tokens = VecDeque::new();
+    for (number, text) in source.lines().enumerate() {
+        let chars: Vec<char> = text.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            let column = i + 1;
+            if chars[i].is_whitespace() {
+                i += 1;
+            } else if chars[i] == '#' {
+                break;
+            } else if chars[i] == '"' {
+                let start = i + 1;
+                i += 1;
+                while i < chars.len() && chars[i] != '"' {
+                    i += 1;
+                }
+                if i == chars.len() {
+                    return Err(AsmError {
+                        file: name.into(),
+                        line: number + 1,
+                        column,
+                        message: "unterminated string".into(),
+                    });
+                }
+                tokens.push_back(Token {
+                    text: chars[start..i].iter().collect(),
+                    string: true,
+                    line: number + 1,
+                    column,
+                });
+                i += 1;
+            } else {
+                let start = i;
+                while i < chars.len() && !chars[i].is_whitespace() {
+                    i += 1;
+                }
+                tokens.push_back(Token {
+                    text: chars[start..i].iter().collect(),
+                    string: false,
+                    line: number + 1,
+                    column,
+                });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+///How a forward reference is patched once its label is defined
+#[derive(Debug, Clone, Copy)]
+enum Patch {
+    ///low 12 bits of the opcode at the address
+    Address,
+    ///`nibble << 4 | label >> 8` into the byte at the address
+    UnpackHigh(u8),
+    ///`label & 0xFF` into the byte at the address
+    UnpackLow,
+}
+
+///A condition compiled to setup instructions and a skip opcode
+struct Condition {
+    setup: Vec<u16>,
+    ///skips the next instruction when the condition is false
+    skip_if_false: u16,
+}
+
+impl Condition {
+    ///the skip opcode with the opposite sense
+    fn skip_if_true(&self) -> u16 {
+        let op = self.skip_if_false;
+        match op & 0xF000 {
+            0x3000 => 0x4000 | (op & 0x0FFF),
+            0x4000 => 0x3000 | (op & 0x0FFF),
+            0x5000 => 0x9000 | (op & 0x0FFF),
+            0x9000 => 0x5000 | (op & 0x0FFF),
+            _ => match op & 0x00FF {
+                0x9E => (op & 0xFF00) | 0xA1,
+                _ => (op & 0xFF00) | 0x9E,
+            },
+        }
+    }
+}
+
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Token>,
+    calls: usize,
+}
+
+#[derive(Default)]
+struct Compiler {
+    file: String,
+    tokens: VecDeque<Token>,
+    rom: Vec<u8>,
+    here: u16,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    expansions: usize,
+    ///(address, label, patch kind, referencing token)
+    forward: Vec<(u16, String, Patch, Token)>,
+    ///(loop start, addresses of `while` exit jumps)
+    loops: Vec<(u16, Vec<u16>, Token)>,
+    ///addresses of jumps waiting for the `else` or `end`
+    branches: Vec<(u16, Token)>,
+    ///a jump to `main` is still needed before the next statement
+    main_pending: bool,
+    symbols: SymbolMap,
+}
+
+impl Compiler {
+    fn error<S: Into<String>>(&self, token: &Token, message: S) -> AsmError {
+        AsmError {
+            file: self.file.clone(),
+            line: token.line,
+            column: token.column,
+            message: message.into(),
+        }
+    }
+
+    fn next(&mut self, after: &Token) -> Result<Token, AsmError> {
+        self.tokens
+            .pop_front()
+            .ok_or_else(|| self.error(after, format!("missing operand after '{}'", after.text)))
+    }
+
+    fn expect(&mut self, after: &Token, text: &str) -> Result<(), AsmError> {
+        let token = self.next(after)?;
+        if token.text == text {
+            Ok(())
+        } else {
+            Err(self.error(
+                &token,
+                format!("expected '{}', found '{}'", text, token.text),
+            ))
+        }
+    }
+
+    fn run(&mut self) -> Result<(), AsmError> {
+        //like Octo, begin with a jump to `main` if anything is emitted
+        //before it
+        self.main_pending = self
+            .tokens
+            .iter()
+            .zip(self.tokens.iter().skip(1))
+            .any(|(a, b)| a.text == ":" && b.text == "main");
+
+        while let Some(token) = self.tokens.pop_front() {
+            self.statement(token)?;
+        }
+
+        if let Some((_, _, token)) = self.loops.last() {
+            return Err(self.error(token, "'loop' without 'again'"));
+        }
+        if let Some((_, token)) = self.branches.last() {
+            return Err(self.error(token, "'begin' without 'end'"));
+        }
+        let forward = std::mem::take(&mut self.forward);
+        for (address, name, patch, token) in forward {
+            let target = match self.labels.get(&name) {
+                Some(&target) => target,
+                None => return Err(self.error(&token, format!("undefined name '{}'", name))),
+            };
+            let offset = usize::from(address - ROM_START);
+            match patch {
+                Patch::Address => {
+                    self.rom[offset] |= (target >> 8) as u8 & 0x0F;
+                    self.rom[offset + 1] = target as u8;
+                }
+                Patch::UnpackHigh(nibble) => {
+                    self.rom[offset] = (nibble << 4) | (target >> 8) as u8;
+                }
+                Patch::UnpackLow => self.rom[offset] = target as u8,
+            }
+        }
+        for (name, address) in &self.labels {
+            self.symbols.labels.insert(name.clone(), *address);
+        }
+        Ok(())
+    }
+
+    fn emit_byte(&mut self, byte: u8, token: &Token) -> Result<(), AsmError> {
+        if self.here < ROM_START || self.here > 0xFFF {
+            return Err(self.error(token, "program does not fit in memory"));
+        }
+        let offset = usize::from(self.here - ROM_START);
+        if self.rom.len() <= offset {
+            self.rom.resize(offset + 1, 0);
+        }
+        self.rom[offset] = byte;
+        self.here += 1;
+        Ok(())
+    }
+
+    fn emit_op(&mut self, opcode: u16, token: &Token) -> Result<(), AsmError> {
+        self.symbols.lines.insert(
+            self.here,
+            SourceLocation {
+                file: self.file.clone(),
+                line: token.line,
+            },
+        );
+        self.emit_byte((opcode >> 8) as u8, token)?;
+        self.emit_byte(opcode as u8, token)
+    }
+
+    fn emit(&mut self, instruction: Instruction, token: &Token) -> Result<(), AsmError> {
+        self.emit_op(instruction.encode(), token)
+    }
+
+    ///overwrite the address in an already emitted jump
+    fn patch_jump(&mut self, address: u16, target: u16) {
+        let offset = usize::from(address - ROM_START);
+        self.rom[offset] = 0x10 | (target >> 8) as u8;
+        self.rom[offset + 1] = target as u8;
+    }
+
+    fn define_label(&mut self, token: &Token, address: u16) -> Result<(), AsmError> {
+        let name = self.next(token)?;
+        self.check_name(&name)?;
+        if self.labels.contains_key(&name.text) {
+            return Err(self.error(&name, format!("label '{}' is already defined", name.text)));
+        }
+        self.labels.insert(name.text, address);
+        Ok(())
+    }
+
+    fn check_name(&self, token: &Token) -> Result<(), AsmError> {
+        let valid = !token.string
+            && token
+                .text
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_alphabetic() || c == '_')
+            && token
+                .text
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '_' || c == '-');
+        if valid && self.register(&token.text).is_none() {
+            Ok(())
+        } else {
+            Err(self.error(token, format!("'{}' is not a valid name", token.text)))
+        }
+    }
+
+    fn register(&self, text: &str) -> Option<u8> {
+        if let Some(&register) = self.aliases.get(text) {
+            return Some(register);
+        }
+        let mut chars = text.chars();
+        match (chars.next(), chars.next(), chars.next()) {
+            (Some('v'), Some(digit), None) | (Some('V'), Some(digit), None) => {
+                digit.to_digit(16).map(|d| d as u8)
+            }
+            _ => None,
+        }
+    }
+
+    fn reg(&mut self, after: &Token) -> Result<u8, AsmError> {
+        let token = self.next(after)?;
+        self.register(&token.text).ok_or_else(|| {
+            self.error(
+                &token,
+                format!("expected a register, found '{}'", token.text),
+            )
+        })
+    }
+
+    ///a number literal, constant or already defined label
+    fn number(&self, token: &Token) -> Option<f64> {
+        if token.string {
+            return None;
+        }
+        let text = token.text.as_str();
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text),
+        };
+        let parsed = if let Some(hex) = digits.strip_prefix("0x") {
+            i64::from_str_radix(hex, 16).ok()
+        } else if let Some(binary) = digits.strip_prefix("0b") {
+            i64::from_str_radix(binary, 2).ok()
+        } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+            digits.parse::<i64>().ok()
+        } else {
+            None
+        };
+        if let Some(value) = parsed {
+            return Some(if negative { -value } else { value } as f64);
+        }
+        if let Some(&value) = self.constants.get(text) {
+            return Some(value);
+        }
+        self.labels.get(text).map(|&address| f64::from(address))
+    }
+
+    fn byte_value(&mut self, after: &Token) -> Result<u8, AsmError> {
+        let token = self.next(after)?;
+        let value = self
+            .number(&token)
+            .ok_or_else(|| self.error(&token, format!("undefined name '{}'", token.text)))?
+            as i64;
+        if !(-128..=255).contains(&value) {
+            return Err(self.error(&token, format!("value {} does not fit in a byte", value)));
+        }
+        Ok(value as u8)
+    }
+
+    ///a 12-bit address, which may refer to a label defined later. The
+    ///value is patched into the opcode at `self.here`
+    fn address(&mut self, after: &Token, opcode: u16) -> Result<(), AsmError> {
+        let token = self.next(after)?;
+        if let Some(value) = self.number(&token) {
+            let value = value as i64;
+            if !(0..=0xFFF).contains(&value) {
+                return Err(self.error(&token, format!("address {} out of range", value)));
+            }
+            return self.emit_op(opcode | value as u16, &token);
+        }
+        self.check_name(&token)?;
+        self.forward
+            .push((self.here, token.text.clone(), Patch::Address, token.clone()));
+        self.emit_op(opcode, &token)
+    }
+
+    ///emit the jump to `main` ahead of the first statement that needs an
+    ///address, unless that statement defines `main` itself
+    fn main_jump(&mut self, token: &Token) -> Result<(), AsmError> {
+        if !self.main_pending {
+            return Ok(());
+        }
+        match token.text.as_str() {
+            ":const" | ":alias" | ":macro" | ":calc" | ":breakpoint" | ":monitor" | ":org" => {
+                return Ok(())
+            }
+            ":" if self.tokens.front().is_some_and(|t| t.text == "main") => {
+                self.main_pending = false;
+                return Ok(());
+            }
+            _ => {}
+        }
+        if self.macros.contains_key(&token.text) {
+            return Ok(());
+        }
+        self.main_pending = false;
+        self.forward
+            .push((self.here, "main".into(), Patch::Address, token.clone()));
+        self.emit_op(0x1000, token)
+    }
+
+    fn statement(&mut self, token: Token) -> Result<(), AsmError> {
+        if token.string {
+            return Err(self.error(&token, "unexpected string"));
+        }
+        self.main_jump(&token)?;
+        let text = token.text.clone();
+        match text.as_str() {
+            ":" => {
+                let here = self.here;
+                self.define_label(&token, here)?;
+            }
+            ":next" => {
+                let here = self.here + 1;
+                self.define_label(&token, here)?;
+            }
+            ":const" => {
+                let name = self.next(&token)?;
+                self.check_name(&name)?;
+                let value = self.next(&name)?;
+                let value = self.number(&value).ok_or_else(|| {
+                    self.error(&value, format!("undefined name '{}'", value.text))
+                })?;
+                self.constants.insert(name.text, value);
+            }
+            ":alias" => {
+                let name = self.next(&token)?;
+                self.check_name(&name)?;
+                let register = self.reg(&name)?;
+                self.aliases.insert(name.text, register);
+            }
+            ":calc" => {
+                let name = self.next(&token)?;
+                self.check_name(&name)?;
+                let value = self.calc(&name)?;
+                self.constants.insert(name.text, value);
+            }
+            ":byte" => {
+                let byte = if self.tokens.front().is_some_and(|t| t.text == "{") {
+                    let value = self.calc(&token)? as i64;
+                    value as u8
+                } else {
+                    self.byte_value(&token)?
+                };
+                self.emit_byte(byte, &token)?;
+            }
+            ":org" => {
+                let target = self.next(&token)?;
+                let address = self
+                    .number(&target)
+                    .ok_or_else(|| self.error(&target, "expected an address"))?
+                    as i64;
+                if !(i64::from(ROM_START)..=0xFFF).contains(&address) {
+                    return Err(self.error(&target, "address out of range"));
+                }
+                self.here = address as u16;
+            }
+            ":call" => self.address(&token, 0x2000)?,
+            ":unpack" => {
+                let nibble = self.byte_value(&token)? & 0x0F;
+                let label = self.next(&token)?;
+                let hi = self.aliases.get("unpack-hi").cloned().unwrap_or(0);
+                let lo = self.aliases.get("unpack-lo").cloned().unwrap_or(1);
+                match self.number(&label) {
+                    Some(value) => {
+                        let value = value as u16;
+                        let high = (nibble << 4) | ((value >> 8) as u8 & 0x0F);
+                        self.emit(Instruction::MovConst(hi, high), &label)?;
+                        self.emit(Instruction::MovConst(lo, value as u8), &label)?;
+                    }
+                    None => {
+                        self.check_name(&label)?;
+                        self.forward.push((
+                            self.here + 1,
+                            label.text.clone(),
+                            Patch::UnpackHigh(nibble),
+                            label.clone(),
+                        ));
+                        self.emit(Instruction::MovConst(hi, 0), &label)?;
+                        self.forward.push((
+                            self.here + 1,
+                            label.text.clone(),
+                            Patch::UnpackLow,
+                            label.clone(),
+                        ));
+                        self.emit(Instruction::MovConst(lo, 0), &label)?;
+                    }
+                }
+            }
+            ":breakpoint" => {
+                let name = self.next(&token)?;
+                self.symbols.breakpoints.insert(self.here, name.text);
+            }
+            ":monitor" => {
+                let target = self.next(&token)?;
+                let length = self.next(&target)?;
+                let monitor_target = match self.register(&target.text) {
+                    Some(register) => MonitorTarget::Register(register),
+                    None => match self.number(&target) {
+                        Some(address) => MonitorTarget::Memory(address as u16),
+                        None => {
+                            return Err(self.error(&target, "monitor needs a register or address"))
+                        }
+                    },
+                };
+                let length = if length.string {
+                    //one byte for each format specifier
+                    length.text.matches('%').count().max(1)
+                } else {
+                    self.number(&length)
+                        .ok_or_else(|| self.error(&length, "expected a length"))?
+                        as usize
+                };
+                self.symbols.monitors.push(Monitor {
+                    name: target.text,
+                    target: monitor_target,
+                    length,
+                });
+            }
+            ":macro" => {
+                let name = self.next(&token)?;
+                self.check_name(&name)?;
+                let mut args = Vec::new();
+                loop {
+                    let arg = self.next(&name)?;
+                    if arg.text == "{" {
+                        break;
+                    }
+                    args.push(arg.text);
+                }
+                let body = self.block(&name)?;
+                self.macros.insert(
+                    name.text,
+                    Macro {
+                        args,
+                        body,
+                        calls: 0,
+                    },
+                );
+            }
+            "clear" => self.emit(Instruction::Cls, &token)?,
+            "return" | ";" => self.emit(Instruction::Rts, &token)?,
+            "bcd" => {
+                let x = self.reg(&token)?;
+                self.emit(Instruction::Bcd(x), &token)?;
+            }
+            "save" => {
+                let x = self.reg(&token)?;
+                self.emit(Instruction::Str(x), &token)?;
+            }
+            "load" => {
+                let x = self.reg(&token)?;
+                self.emit(Instruction::Ldr(x), &token)?;
+            }
+            "sprite" => {
+                let x = self.reg(&token)?;
+                let y = self.reg(&token)?;
+                let n = self.byte_value(&token)?;
+                if n > 15 {
+                    return Err(self.error(&token, "sprite height must be 0-15"));
+                }
+                self.emit(Instruction::Sprite(x, y, n), &token)?;
+            }
+            "jump" => self.address(&token, 0x1000)?,
+            "jump0" => self.address(&token, 0xB000)?,
+            "native" => self.address(&token, 0x0000)?,
+            "loop" => self.loops.push((self.here, Vec::new(), token)),
+            "while" => {
+                if self.loops.is_empty() {
+                    return Err(self.error(&token, "'while' outside of a loop"));
+                }
+                let condition = self.condition(&token)?;
+                for op in &condition.setup {
+                    self.emit_op(*op, &token)?;
+                }
+                self.emit_op(condition.skip_if_true(), &token)?;
+                let exit = self.here;
+                self.emit_op(0x1000, &token)?;
+                if let Some(current) = self.loops.last_mut() {
+                    current.1.push(exit);
+                }
+            }
+            "again" => {
+                let (start, exits, _) = self
+                    .loops
+                    .pop()
+                    .ok_or_else(|| self.error(&token, "'again' without 'loop'"))?;
+                self.emit(Instruction::Jmp(start), &token)?;
+                let here = self.here;
+                for exit in exits {
+                    self.patch_jump(exit, here);
+                }
+            }
+            "if" => {
+                let condition = self.condition(&token)?;
+                let form = self.next(&token)?;
+                for op in &condition.setup {
+                    self.emit_op(*op, &token)?;
+                }
+                match form.text.as_str() {
+                    "then" => self.emit_op(condition.skip_if_false, &token)?,
+                    "begin" => {
+                        self.emit_op(condition.skip_if_true(), &token)?;
+                        self.branches.push((self.here, form.clone()));
+                        self.emit_op(0x1000, &form)?;
+                    }
+                    _ => return Err(self.error(&form, "expected 'then' or 'begin'")),
+                }
+            }
+            "else" => {
+                let (jump, _) = self
+                    .branches
+                    .pop()
+                    .ok_or_else(|| self.error(&token, "'else' without 'begin'"))?;
+                let end_jump = self.here;
+                self.emit_op(0x1000, &token)?;
+                let here = self.here;
+                self.patch_jump(jump, here);
+                self.branches.push((end_jump, token));
+            }
+            "end" => {
+                let (jump, _) = self
+                    .branches
+                    .pop()
+                    .ok_or_else(|| self.error(&token, "'end' without 'begin'"))?;
+                let here = self.here;
+                self.patch_jump(jump, here);
+            }
+            "delay" | "buzzer" => {
+                self.expect(&token, ":=")?;
+                let x = self.reg(&token)?;
+                if text == "delay" {
+                    self.emit(Instruction::Sdelay(x), &token)?;
+                } else {
+                    self.emit(Instruction::Ssound(x), &token)?;
+                }
+            }
+            "i" => {
+                let op = self.next(&token)?;
+                match op.text.as_str() {
+                    ":=" => {
+                        if self.tokens.front().is_some_and(|t| t.text == "hex") {
+                            self.tokens.pop_front();
+                            let x = self.reg(&token)?;
+                            self.emit(Instruction::Font(x), &token)?;
+                        } else {
+                            self.address(&token, 0xA000)?;
+                        }
+                    }
+                    "+=" => {
+                        let x = self.reg(&token)?;
+                        self.emit(Instruction::Adi(x), &token)?;
+                    }
+                    _ => return Err(self.error(&op, "expected ':=' or '+='")),
+                }
+            }
+            _ => {
+                if let Some(x) = self.register(&text) {
+                    return self.assignment(x, &token);
+                }
+                if self.macros.contains_key(&text) {
+                    return self.expand(&token);
+                }
+                if let Some(value) = self.number(&token) {
+                    if self.labels.contains_key(&text) {
+                        //a bare label name calls it
+                        return self.emit(Instruction::Jsr(value as u16), &token);
+                    }
+                    let value = value as i64;
+                    if !(-128..=255).contains(&value) {
+                        return Err(
+                            self.error(&token, format!("value {} does not fit in a byte", value))
+                        );
+                    }
+                    return self.emit_byte(value as u8, &token);
+                }
+                if text.starts_with(':') || text.starts_with(|c: char| c.is_ascii_digit()) {
+                    return Err(self.error(&token, format!("unknown directive '{}'", text)));
+                }
+                //a call to a label defined further on
+                self.check_name(&token)?;
+                self.forward
+                    .push((self.here, text, Patch::Address, token.clone()));
+                self.emit_op(0x2000, &token)?;
+            }
+        }
+        Ok(())
+    }
+
+    ///`vx := ...`, `vx += ...` and friends
+    fn assignment(&mut self, x: u8, token: &Token) -> Result<(), AsmError> {
+        let op = self.next(token)?;
+        let rhs = self.next(&op)?;
+        let y = self.register(&rhs.text);
+        let instruction = match (op.text.as_str(), y) {
+            (":=", Some(y)) => Instruction::MovReg(x, y),
+            (":=", None) => match rhs.text.as_str() {
+                "random" => Instruction::Rand(x, self.byte_value(&rhs)?),
+                "key" => Instruction::Key(x),
+                "delay" => Instruction::Gdelay(x),
+                _ => {
+                    self.tokens.push_front(rhs);
+                    Instruction::MovConst(x, self.byte_value(&op)?)
+                }
+            },
+            ("+=", Some(y)) => Instruction::AddReg(x, y),
+            ("+=", None) => {
+                self.tokens.push_front(rhs);
+                Instruction::AddConst(x, self.byte_value(&op)?)
+            }
+            ("-=", Some(y)) => Instruction::SubReg(x, y),
+            ("-=", None) => {
+                self.tokens.push_front(rhs);
+                let value = self.byte_value(&op)?;
+                Instruction::AddConst(x, value.wrapping_neg())
+            }
+            ("=-", Some(y)) => Instruction::Rsb(x, y),
+            ("|=", Some(y)) => Instruction::OrReg(x, y),
+            ("&=", Some(y)) => Instruction::AndReg(x, y),
+            ("^=", Some(y)) => Instruction::XorReg(x, y),
+            (">>=", Some(y)) => Instruction::Shr(x, y),
+            ("<<=", Some(y)) => Instruction::Shl(x, y),
+            _ => return Err(self.error(&op, format!("can't use '{} {}' here", op.text, rhs.text))),
+        };
+        self.emit(instruction, token)
+    }
+
+    ///parse `vx == n`, `vx key`, `vx < vy` etc.
+    fn condition(&mut self, token: &Token) -> Result<Condition, AsmError> {
+        let x = self.reg(token)?;
+        let op = self.next(token)?;
+        match op.text.as_str() {
+            "key" => {
+                return Ok(Condition {
+                    setup: Vec::new(),
+                    skip_if_false: Instruction::Skup(x).encode(),
+                })
+            }
+            "-key" => {
+                return Ok(Condition {
+                    setup: Vec::new(),
+                    skip_if_false: Instruction::Skpr(x).encode(),
+                })
+            }
+            _ => {}
+        }
+        let rhs = self.next(&op)?;
+        let y = self.register(&rhs.text);
+        if y.is_none() {
+            self.tokens.push_front(rhs);
+        }
+        let value = match y {
+            Some(_) => 0,
+            None => self.byte_value(&op)?,
+        };
+        let skip_if_false = match (op.text.as_str(), y) {
+            ("==", Some(y)) => Instruction::SkneReg(x, y),
+            ("==", None) => Instruction::SkneConst(x, value),
+            ("!=", Some(y)) => Instruction::SkeqReg(x, y),
+            ("!=", None) => Instruction::SkeqConst(x, value),
+            ("<", _) | (">", _) | ("<=", _) | (">=", _) => {
+                //compute the carry of a subtraction into vf, which is set
+                //when the left operand is greater or equal to the right
+                let load = match y {
+                    Some(y) => Instruction::MovReg(0xF, y),
+                    None => Instruction::MovConst(0xF, value),
+                };
+                let (setup, flag) = match op.text.as_str() {
+                    //vf = rhs >= vx
+                    ">" => (vec![load, Instruction::SubReg(0xF, x)], 0),
+                    "<=" => (vec![load, Instruction::SubReg(0xF, x)], 1),
+                    //vf = vx >= rhs
+                    "<" => (vec![load, Instruction::Rsb(0xF, x)], 0),
+                    _ => (vec![load, Instruction::Rsb(0xF, x)], 1),
+                };
+                return Ok(Condition {
+                    setup: setup.iter().map(|i| i.encode()).collect(),
+                    skip_if_false: Instruction::SkneConst(0xF, flag).encode(),
+                });
+            }
+            _ => return Err(self.error(&op, format!("unknown comparison '{}'", op.text))),
+        };
+        Ok(Condition {
+            setup: Vec::new(),
+            skip_if_false: skip_if_false.encode(),
+        })
+    }
+
+    ///collect tokens up to the `}` matching an already consumed `{`
+    fn block(&mut self, start: &Token) -> Result<Vec<Token>, AsmError> {
+        let mut depth = 1;
+        let mut body = Vec::new();
+        loop {
+            let token = self
+                .tokens
+                .pop_front()
+                .ok_or_else(|| self.error(start, "missing '}'"))?;
+            match token.text.as_str() {
+                "{" if !token.string => depth += 1,
+                "}" if !token.string => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(body);
+                    }
+                }
+                _ => {}
+            }
+            body.push(token);
+        }
+    }
+
+    fn expand(&mut self, token: &Token) -> Result<(), AsmError> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(self.error(token, "too many macro expansions"));
+        }
+        let count = self.macros[&token.text].args.len();
+        let mut values = Vec::new();
+        for _ in 0..count {
+            values.push(self.next(token)?);
+        }
+        let body = {
+            let expanded = self.macros.get_mut(&token.text).unwrap();
+            expanded.calls += 1;
+            let calls = expanded.calls - 1;
+            expanded
+                .body
+                .iter()
+                .map(|t| {
+                    let mut t = match expanded.args.iter().position(|a| *a == t.text) {
+                        Some(index) if !t.string => values[index].clone(),
+                        _ => t.clone(),
+                    };
+                    if t.text == "CALLS" && !t.string {
+                        t.text = calls.to_string();
+                    }
+                    t
+                })
+                .collect::<Vec<_>>()
+        };
+        for t in body.into_iter().rev() {
+            self.tokens.push_front(t);
+        }
+        Ok(())
+    }
+
+    ///`{ expression }`, evaluated right to left without precedence as Octo
+    ///does
+    fn calc(&mut self, after: &Token) -> Result<f64, AsmError> {
+        self.expect(after, "{")?;
+        let body = self.block(after)?;
+        let mut calc = Calc {
+            compiler: self,
+            tokens: &body,
+            next: 0,
+            start: after,
+        };
+        let value = calc.expression()?;
+        if calc.next < body.len() {
+            return Err(self.error(&body[calc.next], "unexpected token in expression"));
+        }
+        Ok(value)
+    }
+}
+
+struct Calc<'a> {
+    compiler: &'a Compiler,
+    tokens: &'a [Token],
+    next: usize,
+    start: &'a Token,
+}
+
+impl<'a> Calc<'a> {
+    fn token(&mut self) -> Result<&'a Token, AsmError> {
+        let token = self.tokens.get(self.next).ok_or_else(|| {
+            self.compiler
+                .error(self.start, "expression ends unexpectedly")
+        })?;
+        self.next += 1;
+        Ok(token)
+    }
+
+    fn expression(&mut self) -> Result<f64, AsmError> {
+        let left = self.term()?;
+        let op = match self.tokens.get(self.next) {
+            Some(token) if token.text != ")" => token,
+            _ => return Ok(left),
+        };
+        self.next += 1;
+        let right = self.expression()?;
+        let (l, r) = (left as i64, right as i64);
+        Ok(match op.text.as_str() {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" => left / right,
+            "%" => left % right,
+            "&" => (l & r) as f64,
+            "|" => (l | r) as f64,
+            "^" => (l ^ r) as f64,
+            "<<" => l.checked_shl(r as u32).unwrap_or(0) as f64,
+            ">>" => l.checked_shr(r as u32).unwrap_or(0) as f64,
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "<" => bool_value(left < right),
+            ">" => bool_value(left > right),
+            "<=" => bool_value(left <= right),
+            ">=" => bool_value(left >= right),
+            "==" => bool_value((left - right).abs() < f64::EPSILON),
+            "!=" => bool_value((left - right).abs() >= f64::EPSILON),
+            _ => {
+                return Err(self
+                    .compiler
+                    .error(op, format!("unknown operator '{}'", op.text)))
+            }
+        })
+    }
+
+    fn term(&mut self) -> Result<f64, AsmError> {
+        let token = self.token()?;
+        let unary: Option<fn(f64) -> f64> = match token.text.as_str() {
+            "-" => Some(|v| -v),
+            "~" => Some(|v| !(v as i64) as f64),
+            "!" => Some(|v| bool_value(v == 0.0)),
+            "sin" => Some(f64::sin),
+            "cos" => Some(f64::cos),
+            "tan" => Some(f64::tan),
+            "exp" => Some(f64::exp),
+            "log" => Some(f64::ln),
+            "abs" => Some(f64::abs),
+            "sqrt" => Some(f64::sqrt),
+            "sign" => Some(f64::signum),
+            "ceil" => Some(f64::ceil),
+            "floor" => Some(f64::floor),
+            _ => None,
+        };
+        if let Some(function) = unary {
+            return Ok(function(self.term()?));
+        }
+        match token.text.as_str() {
+            "(" => {
+                let value = self.expression()?;
+                let close = self.token()?;
+                if close.text != ")" {
+                    return Err(self.compiler.error(close, "expected ')'"));
+                }
+                Ok(value)
+            }
+            "@" => {
+                let address = self.term()? as i64;
+                let offset = address - i64::from(ROM_START);
+                Ok(if offset >= 0 {
+                    self.compiler
+                        .rom
+                        .get(offset as usize)
+                        .map_or(0.0, |&b| f64::from(b))
+                } else {
+                    0.0
+                })
+            }
+            "HERE" => Ok(f64::from(self.compiler.here)),
+            "PI" => Ok(f64::consts::PI),
+            "E" => Ok(f64::consts::E),
+            _ => self.compiler.number(token).ok_or_else(|| {
+                self.compiler
+                    .error(token, format!("undefined name '{}'", token.text))
+            }),
+        }
+    }
+}
+
+fn bool_value(b: bool) -> f64 {
+    if b {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use disasm;
+
+    fn compile_ok(source: &str) -> Vec<u8> {
+        match compile(source, "test.8o") {
+            Ok(assembly) => assembly.rom,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn test_statements() {
+        let rom = compile_ok(
+            ": main clear v0 := 5 v1 += v0 i := hex v1 sprite v0 v1 5 \
+             v2 -= 1 delay := v0 v3 := random 0x0F return",
+        );
+        assert_eq!(
+            rom,
+            vec![
+                0x00, 0xE0, 0x60, 0x05, 0x81, 0x04, 0xF1, 0x29, 0xD0, 0x15, 0x72, 0xFF, 0xF0, 0x15,
+                0xC3, 0x0F, 0x00, 0xEE,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_jump_to_main() {
+        //data before main means a jump to it gets inserted
+        let rom = compile_ok(": data 0xFF\n: main jump main");
+        assert_eq!(rom, vec![0x12, 0x03, 0xFF, 0x12, 0x03]);
+    }
+
+    #[test]
+    fn test_forward_call_and_labels() {
+        let rom = compile_ok(": main draw ; : draw i := dot return : dot 0x80");
+        assert_eq!(
+            rom,
+            vec![0x22, 0x04, 0x00, 0xEE, 0xA2, 0x08, 0x00, 0xEE, 0x80]
+        );
+    }
+
+    #[test]
+    fn test_const_alias_calc() {
+        let rom = compile_ok(
+            ":const SPEED 3 :alias px v4 :calc DOUBLE { SPEED * 2 } \
+             : main px := SPEED px += DOUBLE :byte { 1 + 2 * 3 }",
+        );
+        assert_eq!(rom, vec![0x64, 0x03, 0x74, 0x06, 0x07]);
+    }
+
+    #[test]
+    fn test_calc_right_to_left() {
+        //no operator precedence, as in Octo
+        let rom = compile_ok(":calc X { 2 * 3 + 4 } : main :byte X");
+        assert_eq!(rom, vec![14]);
+    }
+
+    #[test]
+    fn test_if_then() {
+        let rom = compile_ok(": main if v0 == 3 then v1 := 1 if v2 key then return");
+        assert_eq!(rom, vec![0x40, 0x03, 0x61, 0x01, 0xE2, 0xA1, 0x00, 0xEE]);
+    }
+
+    #[test]
+    fn test_if_begin_else_end() {
+        let rom = compile_ok(": main if v0 != v1 begin v2 := 1 else v2 := 2 end clear");
+        assert_eq!(
+            rom,
+            vec![0x90, 0x10, 0x12, 0x08, 0x62, 0x01, 0x12, 0x0A, 0x62, 0x02, 0x00, 0xE0,]
+        );
+    }
+
+    #[test]
+    fn test_comparison() {
+        let rom = compile_ok(": main if v3 > 5 then clear");
+        assert_eq!(rom, vec![0x6F, 0x05, 0x8F, 0x35, 0x4F, 0x00, 0x00, 0xE0]);
+    }
+
+    #[test]
+    fn test_loop() {
+        let rom = compile_ok(": main loop v0 += 1 while v0 != 10 again clear");
+        assert_eq!(
+            rom,
+            vec![0x70, 0x01, 0x40, 0x0A, 0x12, 0x08, 0x12, 0x00, 0x00, 0xE0]
+        );
+    }
+
+    #[test]
+    fn test_macro() {
+        let rom =
+            compile_ok(":macro twice reg { reg += reg reg += CALLS } : main twice v1 twice v2");
+        assert_eq!(rom, vec![0x81, 0x14, 0x71, 0x00, 0x82, 0x24, 0x72, 0x01]);
+    }
+
+    #[test]
+    fn test_unpack_and_next() {
+        let rom = compile_ok(": main :unpack 0xA target :next target v0 := 0");
+        assert_eq!(rom, vec![0x60, 0xA2, 0x61, 0x05, 0x60, 0x00]);
+    }
+
+    #[test]
+    fn test_breakpoint_and_monitor() {
+        let assembly = compile(
+            ": main clear :breakpoint here v0 := 1 :monitor v0 1 :monitor main 4",
+            "game.8o",
+        )
+        .unwrap();
+        assert_eq!(assembly.symbols.breakpoints[&0x202], "here");
+        assert_eq!(assembly.symbols.monitors.len(), 2);
+        assert_eq!(
+            assembly.symbols.monitors[1].target,
+            MonitorTarget::Memory(0x200)
+        );
+        assert_eq!(assembly.symbols.lines[&0x202].line, 1);
+    }
+
+    #[test]
+    fn test_errors() {
+        let e = compile(": main\n  jump nowhere", "bad.8o").unwrap_err();
+        assert_eq!((e.line, e.column), (2, 8));
+        assert!(e.message.contains("nowhere"));
+
+        let e = compile(": main v0 := 300", "bad.8o").unwrap_err();
+        assert!(e.message.contains("byte"));
+
+        let e = compile(": main loop clear", "bad.8o").unwrap_err();
+        assert!(e.message.contains("again"));
+
+        let e = compile(":macro m { m } : main m", "bad.8o").unwrap_err();
+        assert!(e.message.contains("macro"));
+    }
+
+    #[test]
+    fn test_recompile_disassembly() {
+        //the Octo disassembly of MAZE compiles back to the same bytes
+        let maze = [
+            0xa2, 0x1e, 0xc2, 0x01, 0x32, 0x01, 0xa2, 0x1a, 0xd0, 0x14, 0x70, 0x04, 0x30, 0x40,
+            0x12, 0x00, 0x60, 0x00, 0x71, 0x04, 0x31, 0x20, 0x12, 0x00, 0x12, 0x18, 0x80, 0x40,
+            0x20, 0x10, 0x20, 0x40, 0x80, 0x10,
+        ];
+        let listing = disasm::disassemble(&maze, disasm::Syntax::Octo);
+        assert_eq!(compile_ok(&listing), maze.to_vec());
+    }
+}
@@ -0,0 +1,241 @@
+//! Octo's per-program options: speed, quirks and colours
+//!
+//! Octo keeps these as a JSON object alongside a program, with keys such as
+//! `"tickrate": 20`, `"shiftQuirks": true` and `"fillColor": "#FFCC00"`.
+//! Keys for things this emulator doesn't have, like XO-CHIP's second plane
+//! colours or screen rotation, are ignored. Anything left out keeps the
+//! emulator's own setting.
+
+use failure;
+use quirks::Quirks;
+use serde_json::{self, Map, Value};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+///A colour, as Octo writes it, e.g. `#FFCC00`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl fmt::Display for Rgb {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{:02X}{:02X}{:02X}", self.0, self.1, self.2)
+    }
+}
+
+impl FromStr for Rgb {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        let channel = |at: usize| {
+            hex.get(at..at + 2)
+                .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+        };
+        match (hex.len(), channel(0), channel(2), channel(4)) {
+            (6, Some(r), Some(g), Some(b)) => Ok(Rgb(r, g, b)),
+            _ => Err(format!("bad colour '{}', expected #RRGGBB", s)),
+        }
+    }
+}
+
+///The colours of the screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub background: Rgb,
+    pub fill: Rgb,
+}
+
+///White on black
+impl Default for Palette {
+    fn default() -> Self {
+        Palette {
+            background: Rgb(0x00, 0x00, 0x00),
+            fill: Rgb(0xFF, 0xFF, 0xFF),
+        }
+    }
+}
+
+///The settings from an Octo options object, each `None` when not given
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Options {
+    ///instructions per 60Hz frame
+    pub tickrate: Option<u32>,
+    pub shift_quirks: Option<bool>,
+    pub load_store_quirks: Option<bool>,
+    pub clip_quirks: Option<bool>,
+    pub jump_quirks: Option<bool>,
+    pub vf_order_quirks: Option<bool>,
+    pub background_color: Option<Rgb>,
+    pub fill_color: Option<Rgb>,
+}
+
+impl Options {
+    ///options from the JSON file at `path`
+    pub fn load(path: &Path) -> Result<Self, failure::Error> {
+        let text =
+            fs::read_to_string(path).map_err(|e| format_err!("{}: {}", path.display(), e))?;
+        Options::parse(&text).map_err(|e| format_err!("{}: {}", path.display(), e))
+    }
+
+    ///the options file Octo would export for the ROM at `rom`, the same
+    ///name with a `.json` extension, if there is one
+    pub fn sibling(rom: &Path) -> Option<PathBuf> {
+        let path = rom.with_extension("json");
+        if path != rom && path.is_file() {
+            Some(path)
+        } else {
+            None
+        }
+    }
+
+    ///these options, with those not given here taken from `fallback`
+    pub fn or(self, fallback: Options) -> Options {
+        Options {
+            tickrate: self.tickrate.or(fallback.tickrate),
+            shift_quirks: self.shift_quirks.or(fallback.shift_quirks),
+            load_store_quirks: self.load_store_quirks.or(fallback.load_store_quirks),
+            clip_quirks: self.clip_quirks.or(fallback.clip_quirks),
+            jump_quirks: self.jump_quirks.or(fallback.jump_quirks),
+            vf_order_quirks: self.vf_order_quirks.or(fallback.vf_order_quirks),
+            background_color: self.background_color.or(fallback.background_color),
+            fill_color: self.fill_color.or(fallback.fill_color),
+        }
+    }
+
+    ///options from the text of a JSON object
+    pub fn parse(text: &str) -> Result<Self, failure::Error> {
+        let value: Value =
+            serde_json::from_str(text).map_err(|e| format_err!("bad options JSON: {}", e))?;
+        match value {
+            Value::Object(ref object) => Options::from_json(object),
+            _ => bail!("options should be a JSON object"),
+        }
+    }
+
+    ///options from a JSON object, as Octo writes them
+    pub fn from_json(object: &Map<String, Value>) -> Result<Self, failure::Error> {
+        let flag = |key: &str| match object.get(key) {
+            None | Some(Value::Null) => Ok(None),
+            Some(&Value::Bool(value)) => Ok(Some(value)),
+            Some(other) => Err(format_err!(
+                "option {} should be true or false, not {}",
+                key,
+                other
+            )),
+        };
+        let color = |key: &str| match object.get(key) {
+            None | Some(Value::Null) => Ok(None),
+            Some(Value::String(value)) => value
+                .parse()
+                .map(Some)
+                .map_err(|e| format_err!("option {}: {}", key, e)),
+            Some(other) => Err(format_err!(
+                "option {} should be a colour, not {}",
+                key,
+                other
+            )),
+        };
+        let tickrate = match object.get("tickrate") {
+            None | Some(Value::Null) => None,
+            Some(value) => match value.as_u64() {
+                Some(rate) if rate > 0 && rate <= 1_000_000 => Some(rate as u32),
+                _ => bail!("option tickrate should be a positive number, not {}", value),
+            },
+        };
+        Ok(Options {
+            tickrate,
+            shift_quirks: flag("shiftQuirks")?,
+            load_store_quirks: flag("loadStoreQuirks")?,
+            clip_quirks: flag("clipQuirks")?,
+            jump_quirks: flag("jumpQuirks")?,
+            vf_order_quirks: flag("vfOrderQuirks")?,
+            background_color: color("backgroundColor")?,
+            fill_color: color("fillColor")?,
+        })
+    }
+
+    ///whether any of the quirk options are given
+    pub fn has_quirks(&self) -> bool {
+        [
+            self.shift_quirks,
+            self.load_store_quirks,
+            self.clip_quirks,
+            self.jump_quirks,
+            self.vf_order_quirks,
+        ]
+        .iter()
+        .any(Option::is_some)
+    }
+
+    ///`quirks` with the quirk options given here changed
+    pub fn quirks(&self, quirks: Quirks) -> Quirks {
+        Quirks {
+            shift: self.shift_quirks.unwrap_or(quirks.shift),
+            load_store: self.load_store_quirks.unwrap_or(quirks.load_store),
+            clip: self.clip_quirks.unwrap_or(quirks.clip),
+            jump: self.jump_quirks.unwrap_or(quirks.jump),
+            vf_order: self.vf_order_quirks.unwrap_or(quirks.vf_order),
+            ..quirks
+        }
+    }
+
+    ///`palette` with the colour options given here changed
+    pub fn palette(&self, palette: Palette) -> Palette {
+        Palette {
+            background: self.background_color.unwrap_or(palette.background),
+            fill: self.fill_color.unwrap_or(palette.fill),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let options = Options::parse(
+            r##"{"tickrate": 20, "shiftQuirks": false, "clipQuirks": true,
+                 "fillColor": "#FFCC00", "screenRotation": 0}"##,
+        )
+        .unwrap();
+        assert_eq!(options.tickrate, Some(20));
+        assert_eq!(
+            options.quirks(Quirks::default()),
+            Quirks {
+                shift: false,
+                clip: true,
+                ..Quirks::default()
+            }
+        );
+        let palette = options.palette(Palette::default());
+        assert_eq!(palette.fill, Rgb(0xFF, 0xCC, 0x00));
+        assert_eq!(palette.background, Palette::default().background);
+        assert_eq!(palette.fill.to_string(), "#FFCC00");
+    }
+
+    #[test]
+    fn test_or() {
+        let file = Options::parse(r#"{"tickrate": 7, "jumpQuirks": false}"#).unwrap();
+        let cartridge =
+            Options::parse(r##"{"tickrate": 20, "jumpQuirks": true, "fillColor": "#FF0000"}"##)
+                .unwrap();
+        let options = file.or(cartridge);
+        assert_eq!(options.tickrate, Some(7));
+        assert_eq!(options.jump_quirks, Some(false));
+        assert_eq!(options.fill_color, Some(Rgb(0xFF, 0, 0)));
+        assert_eq!(options.shift_quirks, None);
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |text| Options::parse(text).unwrap_err().to_string();
+        assert!(error(r#"{"shiftQuirks": 1}"#).contains("shiftQuirks should be true or false"));
+        assert!(error(r#"{"fillColor": "yellow"}"#).contains("bad colour 'yellow'"));
+        assert!(error(r#"{"tickrate": -3}"#).contains("tickrate"));
+        assert!(error("[]").contains("JSON object"));
+        assert!(error("{").starts_with("bad options JSON"));
+    }
+}
@@ -0,0 +1,298 @@
+//! Instruction-level execution profiler
+//!
+//! Every instruction executed is charged to its PC and to the subroutine it
+//! runs in. The call stack is recovered from the return addresses `jsr`
+//! pushes: the instruction before each return address is the `jsr` that
+//! entered the next frame. Cycle counts approximate the COSMAC VIP
+//! interpreter, in machine cycles of 8 clock periods, so they show where a
+//! game will be slow on original hardware rather than in this emulator.
+
+use cpu::Cpu;
+use disasm::ROM_START;
+use instruction::{Instruction, INSTRUCTION_WIDTH};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use symbols::SymbolMap;
+
+///Number of PCs listed in the hot-spot report
+const HOT_SPOTS: usize = 20;
+
+///What has been executed at one PC, or within one subroutine
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counts {
+    pub instructions: u64,
+    pub cycles: u64,
+}
+
+impl Counts {
+    fn add(&mut self, cycles: u64) {
+        self.instructions += 1;
+        self.cycles += cycles;
+    }
+}
+
+///Per subroutine, keyed by entry address, `ROM_START` for the top level
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SubroutineCounts {
+    ///spent in the subroutine itself
+    pub own: Counts,
+    ///including everything it called
+    pub total: Counts,
+}
+
+#[derive(Debug, Default)]
+pub struct Profiler {
+    pcs: BTreeMap<u16, Counts>,
+    subroutines: BTreeMap<u16, SubroutineCounts>,
+    ///cycles per call stack, outermost entry first
+    stacks: BTreeMap<Vec<u16>, u64>,
+    total: Counts,
+}
+
+///approximate COSMAC VIP machine cycles taken by `instruction`, `skipped` is
+///true when a skip instruction took its branch
+pub fn vip_cycles(instruction: Instruction, skipped: bool) -> u64 {
+    use instruction::Instruction::*;
+
+    let skip = if skipped { 4 } else { 0 };
+    match instruction {
+        Cls => 24 + 3072 / 8,
+        Rts => 10,
+        Jmp(_) => 12,
+        Jsr(_) => 26,
+        SkeqConst(..) | SkneConst(..) => 10 + skip,
+        SkeqReg(..) | SkneReg(..) => 14 + skip,
+        MovConst(..) => 6,
+        AddConst(..) => 10,
+        MovReg(..) | OrReg(..) | AndReg(..) | XorReg(..) | AddReg(..) | SubReg(..) | Shr(..)
+        | Rsb(..) | Shl(..) => 44,
+        Mvi(_) => 12,
+        Jmi(_) => 22,
+        Rand(..) => 36,
+        Sprite(_, _, lines) => 26 + 46 * u64::from(lines),
+        Skpr(_) | Skup(_) => 14 + skip,
+        Gdelay(_) | Sdelay(_) | Ssound(_) => 10,
+        Key(_) => 18,
+        Adi(_) => 16,
+        Font(_) => 20,
+        Bcd(_) => 84,
+        Str(x) | Ldr(x) => 14 + 14 * u64::from(x & 0x0F),
+        Sys(_) | Unknown(_) => 0,
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    ///run a single instruction on `cpu`, charging it to the current PC and
+    ///call stack
+    pub fn tick(&mut self, cpu: &mut Cpu) {
+        let pc = cpu.pc();
+        let stack = call_stack(cpu);
+        let instruction = Instruction::fetch(cpu.memory(), usize::from(pc));
+        cpu.tick();
+
+        let instruction = match instruction {
+            Some(instruction) => instruction,
+            None => return,
+        };
+        let skipped = instruction.is_skip() && cpu.pc() == pc.wrapping_add(2 * INSTRUCTION_WIDTH);
+        let cycles = vip_cycles(instruction, skipped);
+
+        self.total.add(cycles);
+        self.pcs.entry(pc).or_default().add(cycles);
+        if let Some(&entry) = stack.last() {
+            self.subroutines.entry(entry).or_default().own.add(cycles);
+        }
+        //recursion shouldn't count a frame's cost more than once
+        let mut entries = stack.clone();
+        entries.sort_unstable();
+        entries.dedup();
+        for entry in entries {
+            self.subroutines.entry(entry).or_default().total.add(cycles);
+        }
+        *self.stacks.entry(stack).or_insert(0) += cycles;
+    }
+
+    pub fn total(&self) -> Counts {
+        self.total
+    }
+
+    pub fn pcs(&self) -> &BTreeMap<u16, Counts> {
+        &self.pcs
+    }
+
+    pub fn subroutines(&self) -> &BTreeMap<u16, SubroutineCounts> {
+        &self.subroutines
+    }
+
+    ///the hottest PCs and every subroutine, by cycles, naming addresses with
+    ///`symbols` where it can
+    pub fn report(&self, cpu: &Cpu, symbols: &SymbolMap) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{} instructions, {} VIP machine cycles",
+            self.total.instructions, self.total.cycles
+        );
+        let percent = |cycles: u64| 100.0 * cycles as f64 / self.total.cycles.max(1) as f64;
+
+        let _ = writeln!(out, "\nhot spots:");
+        let _ = writeln!(
+            out,
+            "{:>6} {:>12} {:>12}  {:<5} {:<20} source",
+            "%", "cycles", "count", "pc", "instruction"
+        );
+        let mut pcs: Vec<(&u16, &Counts)> = self.pcs.iter().collect();
+        pcs.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then(a.0.cmp(b.0)));
+        for (&pc, counts) in pcs.into_iter().take(HOT_SPOTS) {
+            let instruction = Instruction::fetch(cpu.memory(), usize::from(pc))
+                .map(|i| i.to_string())
+                .unwrap_or_default();
+            let source = match symbols.lines.get(&pc) {
+                Some(location) => format!("{}:{}", location.file, location.line),
+                None => String::new(),
+            };
+            let _ = writeln!(
+                out,
+                "{:>6.2} {:>12} {:>12}  {:03X}   {:<20} {}",
+                percent(counts.cycles),
+                counts.cycles,
+                counts.instructions,
+                pc,
+                instruction,
+                source
+            );
+        }
+
+        let _ = writeln!(out, "\nsubroutines:");
+        let _ = writeln!(
+            out,
+            "{:>6} {:>12} {:>6} {:>12}  name",
+            "self%", "self", "total%", "total"
+        );
+        let mut subroutines: Vec<(&u16, &SubroutineCounts)> = self.subroutines.iter().collect();
+        subroutines.sort_by(|a, b| b.1.own.cycles.cmp(&a.1.own.cycles).then(a.0.cmp(b.0)));
+        for (&entry, counts) in subroutines {
+            let _ = writeln!(
+                out,
+                "{:>6.2} {:>12} {:>6.2} {:>12}  {}",
+                percent(counts.own.cycles),
+                counts.own.cycles,
+                percent(counts.total.cycles),
+                counts.total.cycles,
+                frame_name(entry, symbols)
+            );
+        }
+        out
+    }
+
+    ///cycles per call stack in the folded format read by flamegraph.pl and
+    ///inferno, one `outer;inner cycles` line per stack
+    pub fn folded(&self, symbols: &SymbolMap) -> String {
+        let mut out = String::new();
+        for (stack, cycles) in &self.stacks {
+            let names: Vec<String> = stack.iter().map(|&e| frame_name(e, symbols)).collect();
+            let _ = writeln!(out, "{} {}", names.join(";"), cycles);
+        }
+        out
+    }
+}
+
+///entry addresses of the frames on `cpu`'s stack, outermost first
+fn call_stack(cpu: &Cpu) -> Vec<u16> {
+    let mut stack = vec![ROM_START];
+    for &address in cpu.stack() {
+        let call = address.wrapping_sub(INSTRUCTION_WIDTH);
+        match Instruction::fetch(cpu.memory(), usize::from(call)) {
+            Some(Instruction::Jsr(entry)) => stack.push(entry),
+            //something rewrote the stack or the call, keep the frame anyway
+            _ => stack.push(call),
+        }
+    }
+    stack
+}
+
+fn frame_name(entry: u16, symbols: &SymbolMap) -> String {
+    match symbols.label_at(entry) {
+        Some(label) => label.to_string(),
+        None if entry == ROM_START => "main".into(),
+        None => format!("sub_{:03X}", entry),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    //0x200: mov v0,3
+    //0x202: jsr 0x208
+    //0x204: jmp 0x204
+    //0x208: add v0,0xFF
+    //0x20A: skeq v0,0
+    //0x20C: jmp 0x208
+    //0x20E: rts
+    const ROM: [u8; 16] = [
+        0x60, 0x03, 0x22, 0x08, 0x12, 0x04, 0x00, 0x00, 0x70, 0xFF, 0x30, 0x00, 0x12, 0x08, 0x00,
+        0xEE,
+    ];
+
+    fn profile(ticks: usize) -> (Profiler, Cpu) {
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&ROM).unwrap();
+        let mut profiler = Profiler::new();
+        for _ in 0..ticks {
+            profiler.tick(&mut cpu);
+        }
+        (profiler, cpu)
+    }
+
+    #[test]
+    fn test_counts_per_pc() {
+        //mov, jsr, 3 * (add, skeq), 2 * jmp, rts, jmp
+        let (profiler, _) = profile(12);
+        assert_eq!(profiler.total().instructions, 12);
+        assert_eq!(profiler.pcs()[&0x208].instructions, 3);
+        assert_eq!(profiler.pcs()[&0x20C].instructions, 2);
+        assert_eq!(
+            profiler.pcs()[&0x20A].cycles,
+            2 * vip_cycles(Instruction::SkeqConst(0, 0), false)
+                + vip_cycles(Instruction::SkeqConst(0, 0), true)
+        );
+    }
+
+    #[test]
+    fn test_counts_per_subroutine() {
+        let (profiler, _) = profile(12);
+        let subroutine = profiler.subroutines()[&0x208];
+        //add, skeq, jmp and rts, everything but the loop's exit
+        assert_eq!(subroutine.own.instructions, 9);
+        assert_eq!(subroutine.own, subroutine.total);
+        let main = profiler.subroutines()[&ROM_START];
+        assert_eq!(main.own.instructions, 3);
+        assert_eq!(main.total.instructions, 12);
+    }
+
+    #[test]
+    fn test_folded() {
+        let (profiler, _) = profile(12);
+        let mut symbols = SymbolMap::new();
+        symbols.labels.insert("countdown".into(), 0x208);
+        let folded = profiler.folded(&symbols);
+        let lines: Vec<&str> = folded.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("main "));
+        assert!(lines[1].starts_with("main;countdown "));
+    }
+
+    #[test]
+    fn test_report() {
+        let (profiler, cpu) = profile(12);
+        let report = profiler.report(&cpu, &SymbolMap::new());
+        assert!(report.starts_with("12 instructions, "));
+        assert!(report.contains("208   add v0,0xFF"));
+        assert!(report.contains("  sub_208\n"));
+    }
+}
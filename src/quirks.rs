@@ -0,0 +1,179 @@
+//! Behaviours that differ between CHIP-8 interpreters
+//!
+//! ROMs written for the COSMAC VIP, for SUPER-CHIP on HP48 calculators and
+//! for Octo each rely on their own interpreter's take on a handful of
+//! instructions, and on how deep their call stack is. The flags are named
+//! after the equivalent Octo options.
+
+use cpu::MAX_STACK_DEPTH;
+use std::fmt;
+use std::str::FromStr;
+
+///Which interpretation of the ambiguous instructions to use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    ///8XY6/8XYE shift VX in place rather than setting VX to VY shifted
+    pub shift: bool,
+    ///FX55/FX65 leave I alone rather than advancing it past the registers
+    pub load_store: bool,
+    ///sprites are cut off at the screen edges rather than wrapping around
+    pub clip: bool,
+    ///BXNN jumps to XNN + VX rather than NNN + V0
+    pub jump: bool,
+    ///8XYN arithmetic writes VF before the result, so `vf` as the
+    ///destination keeps the result rather than the flag
+    pub vf_order: bool,
+    ///how many return addresses 2NNN can push before overflowing, at most
+    ///`MAX_STACK_DEPTH`
+    pub stack_depth: u8,
+}
+
+///The behaviour this emulator has always had
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks {
+            shift: true,
+            load_store: false,
+            clip: false,
+            jump: false,
+            vf_order: true,
+            stack_depth: 16,
+        }
+    }
+}
+
+impl Quirks {
+    ///the original COSMAC VIP interpreter
+    pub fn vip() -> Self {
+        Quirks {
+            shift: false,
+            load_store: false,
+            clip: true,
+            jump: false,
+            vf_order: false,
+            stack_depth: 12,
+        }
+    }
+
+    ///SUPER-CHIP 1.1
+    pub fn schip() -> Self {
+        Quirks {
+            shift: true,
+            load_store: true,
+            clip: true,
+            jump: true,
+            vf_order: false,
+            stack_depth: 16,
+        }
+    }
+
+    ///Octo with every quirk option turned off
+    pub fn octo() -> Self {
+        Quirks {
+            shift: false,
+            load_store: false,
+            clip: false,
+            jump: false,
+            vf_order: false,
+            stack_depth: 16,
+        }
+    }
+}
+
+///Every setting spelled out, e.g.
+///`shift=1 load_store=0 clip=0 jump=0 vf_order=1 stack_depth=16`
+impl fmt::Display for Quirks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "shift={} load_store={} clip={} jump={} vf_order={} stack_depth={}",
+            self.shift as u8,
+            self.load_store as u8,
+            self.clip as u8,
+            self.jump as u8,
+            self.vf_order as u8,
+            self.stack_depth
+        )
+    }
+}
+
+///A profile name, or flags as written by `Display` starting from the
+///default profile
+impl FromStr for Quirks {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => return Ok(Quirks::default()),
+            "vip" => return Ok(Quirks::vip()),
+            "schip" => return Ok(Quirks::schip()),
+            "octo" => return Ok(Quirks::octo()),
+            _ if !s.contains('=') => return Err(format!("unknown quirks profile '{}'", s)),
+            _ => {}
+        }
+        let mut quirks = Quirks::default();
+        for setting in s.split_whitespace() {
+            let (name, value) = match setting.split_once('=') {
+                Some(pair) => pair,
+                None => return Err(format!("malformed quirk '{}'", setting)),
+            };
+            let flag = || match value {
+                "1" | "true" => Ok(true),
+                "0" | "false" => Ok(false),
+                _ => Err(format!("bad value for quirk '{}'", setting)),
+            };
+            match name {
+                "shift" => quirks.shift = flag()?,
+                "load_store" => quirks.load_store = flag()?,
+                "clip" => quirks.clip = flag()?,
+                "jump" => quirks.jump = flag()?,
+                "vf_order" => quirks.vf_order = flag()?,
+                "stack_depth" => {
+                    quirks.stack_depth = match value.parse() {
+                        Ok(depth) if depth > 0 && usize::from(depth) <= MAX_STACK_DEPTH => depth,
+                        _ => {
+                            return Err(format!(
+                                "stack_depth must be 1 to {}, not '{}'",
+                                MAX_STACK_DEPTH, value
+                            ))
+                        }
+                    }
+                }
+                _ => return Err(format!("unknown quirk '{}'", name)),
+            }
+        }
+        Ok(quirks)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!("schip".parse::<Quirks>(), Ok(Quirks::schip()));
+        assert_eq!("default".parse::<Quirks>(), Ok(Quirks::default()));
+        assert!("bogus".parse::<Quirks>().is_err());
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        let quirks = Quirks::schip();
+        assert_eq!(
+            quirks.to_string(),
+            "shift=1 load_store=1 clip=1 jump=1 vf_order=0 stack_depth=16"
+        );
+        assert_eq!(quirks.to_string().parse::<Quirks>(), Ok(quirks));
+        let clip = Quirks {
+            clip: true,
+            ..Quirks::default()
+        };
+        assert_eq!("clip=1".parse::<Quirks>(), Ok(clip));
+        assert!("clip=2".parse::<Quirks>().is_err());
+        assert_eq!("stack_depth=12".parse::<Quirks>().unwrap().stack_depth, 12);
+        assert!("stack_depth=17".parse::<Quirks>().is_err());
+        assert!("stack_depth=0".parse::<Quirks>().is_err());
+        assert!("wrap=1".parse::<Quirks>().is_err());
+    }
+}
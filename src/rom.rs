@@ -0,0 +1,333 @@
+//! Reading ROMs from the formats they get passed around in
+//!
+//! Besides raw binaries, ROMs turn up as hex text (`xxd -p` output, or
+//! bytes written `0x12, 0x00`), as Intel HEX from other toolchains and
+//! squeezed into gzip or zip archives. `load` works out which from the
+//! extension where that is unambiguous and otherwise from the contents.
+//!
+//! Intel HEX addresses are taken to be where the bytes sit in memory when
+//! none is below 0x200, where ROMs are loaded, and offsets into the ROM
+//! otherwise.
+
+use cpu::MAX_ROM_SIZE;
+use failure;
+use flate2::read::GzDecoder;
+use std::fmt;
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::Path;
+use zip::ZipArchive;
+
+const ROM_START: usize = 0x200;
+const GZIP_MAGIC: &[u8] = &[0x1F, 0x8B];
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+///extensions of plain binary ROMs, for CHIP-8, SUPER-CHIP and XO-CHIP
+const RAW_EXTENSIONS: &[&str] = &["ch8", "c8", "sc8", "xo8"];
+
+///How a ROM file is encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Raw,
+    HexText,
+    IntelHex,
+    Gzip,
+    Zip,
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Format::Raw => "raw",
+            Format::HexText => "hex text",
+            Format::IntelHex => "Intel HEX",
+            Format::Gzip => "gzip",
+            Format::Zip => "zip",
+        })
+    }
+}
+
+impl Format {
+    ///the format of `bytes`, read from a file called `name`, if it can be
+    ///told
+    pub fn detect(bytes: &[u8], name: &str) -> Option<Format> {
+        let extension = Path::new(name)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("gz") => return Some(Format::Gzip),
+            Some("zip") => return Some(Format::Zip),
+            Some(extension) if RAW_EXTENSIONS.contains(&extension) => return Some(Format::Raw),
+            _ => {}
+        }
+        if bytes.starts_with(GZIP_MAGIC) {
+            return Some(Format::Gzip);
+        }
+        if bytes.starts_with(ZIP_MAGIC) {
+            return Some(Format::Zip);
+        }
+        let text = match text(bytes) {
+            Some(text) => text,
+            //anything that isn't text can only be a binary
+            None => return Some(Format::Raw),
+        };
+        if text.trim_start().starts_with(':') {
+            Some(Format::IntelHex)
+        } else if parse_hex_text(text).is_ok() {
+            Some(Format::HexText)
+        } else {
+            None
+        }
+    }
+}
+
+///the ROM in the file at `path`, in any of the formats in `Format`
+pub fn load(path: &Path) -> Result<Vec<u8>, failure::Error> {
+    let bytes = fs::read(path).map_err(|e| format_err!("{}: {}", path.display(), e))?;
+    decode(&bytes, &path.display().to_string())
+}
+
+///the ROM held in `bytes`, read from a file called `name`, which errors
+///mention
+pub fn decode(bytes: &[u8], name: &str) -> Result<Vec<u8>, failure::Error> {
+    let rom = unpack(bytes, name, true)?;
+    if rom.is_empty() {
+        bail!("{}: the ROM is empty", name);
+    }
+    if rom.len() > MAX_ROM_SIZE {
+        bail!(
+            "{}: the ROM is {} bytes, more than the {} that fit in memory",
+            name,
+            rom.len(),
+            MAX_ROM_SIZE
+        );
+    }
+    Ok(rom)
+}
+
+///decode one layer, and the ROM inside an archive if `archives` allows one
+fn unpack(bytes: &[u8], name: &str, archives: bool) -> Result<Vec<u8>, failure::Error> {
+    let format = match Format::detect(bytes, name) {
+        Some(format) => format,
+        None => bail!(
+            "{}: can't tell what format the ROM is in, expected a binary, hex text, \
+             Intel HEX, gzip or zip",
+            name
+        ),
+    };
+    match format {
+        Format::Gzip | Format::Zip if !archives => {
+            bail!("{}: a {} archive inside another archive", name, format)
+        }
+        Format::Raw => Ok(bytes.to_vec()),
+        Format::HexText => {
+            let text = text(bytes).ok_or_else(|| format_err!("{}: not hex text", name))?;
+            parse_hex_text(text).map_err(|e| format_err!("{}: {}", name, e))
+        }
+        Format::IntelHex => {
+            let text = text(bytes).ok_or_else(|| format_err!("{}: not Intel HEX", name))?;
+            parse_intel_hex(text).map_err(|e| format_err!("{}: {}", name, e))
+        }
+        Format::Gzip => {
+            let inner = read_limited(GzDecoder::new(bytes))
+                .map_err(|e| format_err!("{}: bad gzip data: {}", name, e))?;
+            //game.ch8.gz holds game.ch8
+            let inner_name = name.trim_end_matches(".gz").trim_end_matches(".GZ");
+            unpack(&inner, inner_name, false)
+        }
+        Format::Zip => {
+            let mut archive = ZipArchive::new(Cursor::new(bytes))
+                .map_err(|e| format_err!("{}: bad zip archive: {}", name, e))?;
+            let mut files = Vec::new();
+            for index in 0..archive.len() {
+                let file = archive
+                    .by_index(index)
+                    .map_err(|e| format_err!("{}: bad zip archive: {}", name, e))?;
+                if !file.is_dir() {
+                    files.push(index);
+                }
+            }
+            if files.len() != 1 {
+                bail!(
+                    "{}: the zip archive holds {} files rather than a single ROM",
+                    name,
+                    files.len()
+                );
+            }
+            let file = archive
+                .by_index(files[0])
+                .map_err(|e| format_err!("{}: bad zip archive: {}", name, e))?;
+            let inner_name = format!("{}:{}", name, file.name());
+            let inner = read_limited(file)
+                .map_err(|e| format_err!("{}: bad zip data: {}", inner_name, e))?;
+            unpack(&inner, &inner_name, false)
+        }
+    }
+}
+
+///read the whole of a decompressor, giving up well past anything that
+///could be a ROM rather than inflating a bomb
+fn read_limited<R: Read>(reader: R) -> Result<Vec<u8>, failure::Error> {
+    //hex text takes a little over two characters a byte
+    let limit = 8 * MAX_ROM_SIZE as u64;
+    let mut out = Vec::new();
+    reader.take(limit + 1).read_to_end(&mut out)?;
+    if out.len() as u64 > limit {
+        bail!("it unpacks to more than {} bytes", limit);
+    }
+    Ok(out)
+}
+
+///`bytes` as text, if it has nothing but printable ASCII and whitespace
+fn text(bytes: &[u8]) -> Option<&str> {
+    if bytes
+        .iter()
+        .all(|&b| b.is_ascii_graphic() || b.is_ascii_whitespace())
+    {
+        ::std::str::from_utf8(bytes).ok()
+    } else {
+        None
+    }
+}
+
+///bytes written in hex, separated by whitespace or commas or run together,
+///each optionally with a `0x`
+fn parse_hex_text(text: &str) -> Result<Vec<u8>, String> {
+    let mut rom = Vec::new();
+    for word in text.split(|c: char| c.is_whitespace() || c == ',') {
+        let digits = word
+            .strip_prefix("0x")
+            .or_else(|| word.strip_prefix("0X"))
+            .unwrap_or(word);
+        if !digits.len().is_multiple_of(2) || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("'{}' isn't hex bytes", word));
+        }
+        for pair in digits.as_bytes().chunks(2) {
+            let pair = ::std::str::from_utf8(pair).map_err(|e| e.to_string())?;
+            rom.push(u8::from_str_radix(pair, 16).map_err(|e| e.to_string())?);
+        }
+    }
+    Ok(rom)
+}
+
+///Intel HEX records, with data records placed at their addresses
+fn parse_intel_hex(text: &str) -> Result<Vec<u8>, String> {
+    let mut chunks = Vec::new();
+    let mut base = 0;
+    for (n, line) in text
+        .lines()
+        .enumerate()
+        .map(|(n, line)| (n + 1, line.trim()))
+    {
+        if line.is_empty() {
+            continue;
+        }
+        let record = line
+            .strip_prefix(':')
+            .and_then(|hex| parse_hex_text(hex).ok())
+            .filter(|record| record.len() >= 5 && record.len() == 5 + usize::from(record[0]))
+            .ok_or_else(|| format!("line {}: malformed Intel HEX record", n))?;
+        if record.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) != 0 {
+            return Err(format!("line {}: bad checksum", n));
+        }
+        let address = usize::from(record[1]) << 8 | usize::from(record[2]);
+        let data = &record[4..record.len() - 1];
+        match record[3] {
+            0x00 => chunks.push((base + address, data.to_vec())),
+            0x01 => break,
+            0x02 | 0x04 if data.len() != 2 => {
+                return Err(format!("line {}: malformed extended address", n))
+            }
+            //extended segment and linear addresses
+            0x02 => base = (usize::from(data[0]) << 8 | usize::from(data[1])) << 4,
+            0x04 => base = (usize::from(data[0]) << 8 | usize::from(data[1])) << 16,
+            //start addresses mean nothing here
+            0x03 | 0x05 => {}
+            kind => return Err(format!("line {}: unknown record type {:02X}", n, kind)),
+        }
+    }
+    let start = match chunks.iter().map(|&(address, _)| address).min() {
+        Some(lowest) if lowest >= ROM_START => ROM_START,
+        Some(_) => 0,
+        None => return Ok(Vec::new()),
+    };
+    let mut rom = Vec::new();
+    for (address, data) in chunks {
+        let offset = address - start;
+        if offset + data.len() > 4096 {
+            return Err(format!("data at 0x{:X} is past the end of memory", address));
+        }
+        if rom.len() < offset + data.len() {
+            rom.resize(offset + data.len(), 0);
+        }
+        rom[offset..offset + data.len()].copy_from_slice(&data);
+    }
+    Ok(rom)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+    use zip::write::{FileOptions, ZipWriter};
+
+    const ROM: [u8; 4] = [0x12, 0x00, 0xA2, 0x2A];
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for &(name, bytes) in files {
+            writer.start_file(name, FileOptions::default()).unwrap();
+            writer.write_all(bytes).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_formats() {
+        assert_eq!(decode(&ROM, "game.ch8").unwrap(), ROM);
+        assert_eq!(decode(&ROM, "GAME").unwrap(), ROM);
+        assert_eq!(decode(b"1200a22a\n", "game.hex").unwrap(), ROM);
+        assert_eq!(decode(b"0x12, 0x00,\n0xA2, 0x2A", "game.txt").unwrap(), ROM);
+        let intel = ":040200001200A22A1C\n:00000001FF\n";
+        assert_eq!(decode(intel.as_bytes(), "game.ihx").unwrap(), ROM);
+        assert_eq!(decode(&gzip(&ROM), "game.ch8.gz").unwrap(), ROM);
+        assert_eq!(decode(&gzip(b"1200a22a"), "game").unwrap(), ROM);
+        let archive = zip(&[("game.ch8", &ROM)]);
+        assert_eq!(decode(&archive, "game.zip").unwrap(), ROM);
+        assert_eq!(Format::detect(&archive, "game"), Some(Format::Zip));
+    }
+
+    #[test]
+    fn test_intel_hex_addresses() {
+        //offsets into the ROM rather than addresses in memory
+        let intel = ":020002001200EA\n:00000001FF\n";
+        assert_eq!(
+            decode(intel.as_bytes(), "game.ihx").unwrap(),
+            [0, 0, 0x12, 0]
+        );
+        let intel = ":020002001200EB\n";
+        assert!(decode(intel.as_bytes(), "game.ihx")
+            .unwrap_err()
+            .to_string()
+            .contains("line 1: bad checksum"));
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |bytes: &[u8], name| decode(bytes, name).unwrap_err().to_string();
+        assert!(error(b"hello world", "game.txt").starts_with("game.txt: can't tell"));
+        assert!(error(&[0; 3585], "big.ch8").contains("3585 bytes"));
+        assert!(error(b"", "empty.ch8").contains("empty"));
+        assert!(error(&zip(&[("a.ch8", &ROM), ("b.ch8", &ROM)]), "two.zip").contains("2 files"));
+        assert!(error(&gzip(&gzip(&ROM)), "game.gz").contains("inside another archive"));
+        assert!(error(&ROM[..2], "game.gz").starts_with("game.gz: bad gzip data"));
+    }
+}
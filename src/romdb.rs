@@ -0,0 +1,346 @@
+//! Known ROMs and the settings they need, keyed by the SHA-1 of the image
+//!
+//! A database is a JSON object from hex SHA-1 to an entry such as
+//! `{"title": "Blitz", "author": "David Winter", "platform": "CHIP-8",
+//! "quirks": "clip=1", "tickrate": 20, "keys": "5 drops a bomb"}`. Only the
+//! title is required, `quirks` takes anything `Quirks` parses. One database
+//! of the ROMs in `roms/` and other well known ones is built in, and entries
+//! in the user's `roms.json` add to it or replace its own.
+
+use config;
+use failure;
+use movie::rom_sha1;
+use quirks::Quirks;
+use serde_json::{self, Map, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+const BUNDLED: &str = include_str!("romdb.json");
+
+///The machine a ROM was written for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    Chip8,
+    Schip,
+    XoChip,
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Platform::Chip8 => write!(f, "CHIP-8"),
+            Platform::Schip => write!(f, "SCHIP"),
+            Platform::XoChip => write!(f, "XO-CHIP"),
+        }
+    }
+}
+
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().replace('-', "").as_str() {
+            "CHIP8" => Ok(Platform::Chip8),
+            "SCHIP" | "SUPERCHIP" => Ok(Platform::Schip),
+            "XOCHIP" | "XO" => Ok(Platform::XoChip),
+            _ => Err(format!("unknown platform '{}'", s)),
+        }
+    }
+}
+
+///What is known about a ROM
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub title: String,
+    pub author: Option<String>,
+    pub platform: Platform,
+    ///the quirks the ROM needs to run properly
+    pub quirks: Option<Quirks>,
+    ///recommended instructions per 60Hz frame
+    pub tickrate: Option<u32>,
+    ///what the keys do, for the player
+    pub keys: Option<String>,
+}
+
+///`title by author (platform)`
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.title)?;
+        if let Some(ref author) = self.author {
+            write!(f, " by {}", author)?;
+        }
+        write!(f, " ({})", self.platform)
+    }
+}
+
+impl Entry {
+    ///an entry for a CHIP-8 ROM with nothing known but its title
+    pub fn new(title: &str) -> Self {
+        Entry {
+            title: title.to_string(),
+            author: None,
+            platform: Platform::Chip8,
+            quirks: None,
+            tickrate: None,
+            keys: None,
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let mut object = Map::new();
+        let mut text = |key: &str, value: String| {
+            object.insert(key.to_string(), Value::String(value));
+        };
+        text("title", self.title.clone());
+        if let Some(ref author) = self.author {
+            text("author", author.clone());
+        }
+        text("platform", self.platform.to_string());
+        if let Some(quirks) = self.quirks {
+            text("quirks", quirks.to_string());
+        }
+        if let Some(ref keys) = self.keys {
+            text("keys", keys.clone());
+        }
+        if let Some(tickrate) = self.tickrate {
+            object.insert("tickrate".to_string(), Value::from(tickrate));
+        }
+        Value::Object(object)
+    }
+
+    fn from_json(object: &Map<String, Value>) -> Result<Self, failure::Error> {
+        let text = |key: &str| match object.get(key) {
+            None | Some(Value::Null) => Ok(None),
+            Some(Value::String(value)) => Ok(Some(value.clone())),
+            Some(other) => Err(format_err!("{} should be a string, not {}", key, other)),
+        };
+        let title = match text("title")? {
+            Some(title) => title,
+            None => bail!("the entry has no title"),
+        };
+        let platform = match text("platform")? {
+            Some(platform) => platform.parse().map_err(failure::err_msg)?,
+            None => Platform::Chip8,
+        };
+        let quirks = match text("quirks")? {
+            Some(quirks) => Some(quirks.parse().map_err(failure::err_msg)?),
+            None => None,
+        };
+        let tickrate = match object.get("tickrate") {
+            None | Some(Value::Null) => None,
+            Some(value) => match value.as_u64() {
+                Some(rate) if rate > 0 && rate <= 1_000_000 => Some(rate as u32),
+                _ => bail!("tickrate should be a positive number, not {}", value),
+            },
+        };
+        Ok(Entry {
+            title,
+            author: text("author")?,
+            platform,
+            quirks,
+            tickrate,
+            keys: text("keys")?,
+        })
+    }
+}
+
+///ROM entries by hex SHA-1
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Database {
+    entries: BTreeMap<String, Entry>,
+}
+
+impl Database {
+    ///the database that comes with the emulator
+    pub fn bundled() -> Self {
+        Database::parse(BUNDLED).expect("the bundled ROM database is broken")
+    }
+
+    ///the bundled database, with the user's entries from `user_path` on top
+    pub fn with_user_entries() -> Result<Self, failure::Error> {
+        let mut database = Database::bundled();
+        match Database::user_path() {
+            Some(ref path) if path.exists() => database.extend(Database::load(path)?),
+            _ => {}
+        }
+        Ok(database)
+    }
+
+    ///where the user's own entries live: `roms.json` in `config::dir`
+    pub fn user_path() -> Option<PathBuf> {
+        config::dir().map(|dir| dir.join("roms.json"))
+    }
+
+    ///the database in the JSON file at `path`
+    pub fn load(path: &Path) -> Result<Self, failure::Error> {
+        let text =
+            fs::read_to_string(path).map_err(|e| format_err!("{}: {}", path.display(), e))?;
+        Database::parse(&text).map_err(|e| format_err!("{}: {}", path.display(), e))
+    }
+
+    ///a database from the text of a JSON object
+    pub fn parse(text: &str) -> Result<Self, failure::Error> {
+        let value: Value =
+            serde_json::from_str(text).map_err(|e| format_err!("bad ROM database JSON: {}", e))?;
+        let object = match value {
+            Value::Object(object) => object,
+            _ => bail!("a ROM database should be a JSON object"),
+        };
+        let mut entries = BTreeMap::new();
+        for (sha1, entry) in object.iter() {
+            if sha1.len() != 40 || !sha1.chars().all(|c| c.is_ascii_hexdigit()) {
+                bail!("'{}' is not a SHA-1", sha1);
+            }
+            let entry = match *entry {
+                Value::Object(ref entry) => {
+                    Entry::from_json(entry).map_err(|e| format_err!("{}: {}", sha1, e))?
+                }
+                _ => bail!("{}: the entry should be a JSON object", sha1),
+            };
+            entries.insert(sha1.to_lowercase(), entry);
+        }
+        Ok(Database { entries })
+    }
+
+    ///the entry for a ROM
+    pub fn lookup(&self, rom: &[u8]) -> Option<&Entry> {
+        self.entries.get(&rom_sha1(rom))
+    }
+
+    ///add `other`'s entries, replacing any for the same ROMs
+    pub fn extend(&mut self, other: Database) {
+        self.entries.extend(other.entries);
+    }
+
+    ///add or replace the entry for a ROM
+    pub fn insert(&mut self, rom: &[u8], entry: Entry) {
+        self.entries.insert(rom_sha1(rom), entry);
+    }
+
+    ///the database as the JSON `parse` reads
+    pub fn to_json(&self) -> String {
+        let object: Map<String, Value> = self
+            .entries
+            .iter()
+            .map(|(sha1, entry)| (sha1.clone(), entry.to_json()))
+            .collect();
+        let mut json = serde_json::to_string_pretty(&Value::Object(object))
+            .expect("a ROM database is always valid JSON");
+        json.push('\n');
+        json
+    }
+
+    ///write the database to `path`, making its directory if need be
+    pub fn save(&self, path: &Path) -> Result<(), failure::Error> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format_err!("{}: {}", dir.display(), e))?;
+        }
+        fs::write(path, self.to_json()).map_err(|e| format_err!("{}: {}", path.display(), e))
+    }
+}
+
+///record `quirks` for `rom` in the user's database, keeping whatever else
+///is known about it or titling it `title` if nothing is, and return where
+///the database is
+pub fn save_user_quirks(
+    rom: &[u8],
+    title: &str,
+    quirks: Quirks,
+) -> Result<PathBuf, failure::Error> {
+    let path = match Database::user_path() {
+        Some(path) => path,
+        None => bail!("there's no config directory to keep the ROM database in"),
+    };
+    let mut user = if path.exists() {
+        Database::load(&path)?
+    } else {
+        Database::default()
+    };
+    let mut entry = match Database::with_user_entries()?.lookup(rom) {
+        Some(entry) => entry.clone(),
+        None => Entry::new(title),
+    };
+    entry.quirks = Some(quirks);
+    user.insert(rom, entry);
+    user.save(&path)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bundled() {
+        let database = Database::bundled();
+        let mut configurations = Vec::new();
+        for entry in fs::read_dir("roms").unwrap() {
+            let path = entry.unwrap().path();
+            let entry = database
+                .lookup(&fs::read(&path).unwrap())
+                .unwrap_or_else(|| panic!("{} isn't in the database", path.display()));
+            let configuration = (entry.quirks, entry.tickrate);
+            if !configurations.contains(&configuration) {
+                configurations.push(configuration);
+            }
+        }
+        assert!(configurations.len() >= 3);
+
+        let blitz = database.lookup(&fs::read("roms/BLITZ").unwrap()).unwrap();
+        assert_eq!(blitz.to_string(), "Blitz by David Winter (CHIP-8)");
+        assert!(blitz.quirks.unwrap().clip);
+    }
+
+    #[test]
+    fn test_user_entries() {
+        let mut database = Database::bundled();
+        let maze = fs::read("roms/MAZE").unwrap();
+        let user = Database::parse(&format!(
+            r#"{{"{}": {{"title": "My Maze", "platform": "schip", "quirks": "octo"}}}}"#,
+            rom_sha1(&maze).to_uppercase()
+        ))
+        .unwrap();
+        database.extend(user);
+        let entry = database.lookup(&maze).unwrap();
+        assert_eq!(entry.to_string(), "My Maze (SCHIP)");
+        assert_eq!(entry.quirks, Some(Quirks::octo()));
+        assert_eq!(entry.tickrate, None);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let database = Database::bundled();
+        assert_eq!(Database::parse(&database.to_json()).unwrap(), database);
+
+        let mut database = Database::default();
+        let entry = Entry {
+            quirks: Some(Quirks::vip()),
+            ..Entry::new("Untitled")
+        };
+        database.insert(b"\x12\x00", entry.clone());
+        let database = Database::parse(&database.to_json()).unwrap();
+        assert_eq!(database.lookup(b"\x12\x00"), Some(&entry));
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |text: &str| Database::parse(text).unwrap_err().to_string();
+        let sha1 = "0123456789abcdef0123456789abcdef01234567";
+        assert!(error(r#"{"abc": {"title": "x"}}"#).contains("not a SHA-1"));
+        assert!(error(&format!(r#"{{"{}": {{}}}}"#, sha1)).contains("no title"));
+        assert!(error(&format!(
+            r#"{{"{}": {{"title": "x", "quirks": "fast"}}}}"#,
+            sha1
+        ))
+        .contains("unknown quirks profile"));
+        assert!(error(&format!(
+            r#"{{"{}": {{"title": "x", "platform": "nes"}}}}"#,
+            sha1
+        ))
+        .contains("unknown platform"));
+        assert!(error("[]").contains("JSON object"));
+    }
+}
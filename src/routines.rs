@@ -0,0 +1,145 @@
+//! Native stand-ins for `0NNN` machine code routines
+//!
+//! On the COSMAC VIP `0NNN` called 1802 machine code at NNN, and a few ROMs
+//! rely on routines that were in the interpreter or that they brought along.
+//! Embedders register a closure for an address, which runs with the cpu's
+//! PC already past the call, so it can return by doing nothing or jump by
+//! setting the PC. Every cpu starts with the `builtin` routines.
+//!
+//! ```
+//! # use chip8_tismith::cpu::Cpu;
+//! let mut cpu = Cpu::new();
+//! //0x200: sys 0x123
+//! cpu.load_rom(&[0x01, 0x23]).unwrap();
+//! cpu.routines_mut().register(0x123, |cpu| cpu.registers_mut()[0] = 7);
+//! cpu.tick();
+//! assert_eq!(cpu.registers()[0], 7);
+//! ```
+
+use cpu::Cpu;
+use std::collections::HashMap;
+
+///A routine, given the whole machine
+pub type Routine = Box<dyn FnMut(&mut Cpu)>;
+
+///Routines by the address `0NNN` calls
+#[derive(Default)]
+pub struct Routines {
+    routines: HashMap<u16, Routine>,
+}
+
+impl Routines {
+    ///no routines at all
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    ///the well-known routines:
+    ///
+    /// * `0230` clears the screen, as the two-page hi-res interpreter does
+    /// * `0151` waits for the delay timer to reach zero, as in CHIP-8E
+    /// * `0188` skips the next instruction, as in CHIP-8E
+    /// * `02A0` steps the CHIP-8X background colour, which with only the
+    ///   one colour does nothing
+    pub fn builtin() -> Self {
+        let mut routines = Routines::new();
+        routines.register(0x230, |cpu| {
+            cpu.screen_mut().iter_mut().for_each(|p| *p = false)
+        });
+        routines.register(0x151, |cpu| {
+            if cpu.delay() > 0 {
+                let pc = cpu.pc();
+                cpu.set_pc(pc.wrapping_sub(2));
+            }
+        });
+        routines.register(0x188, |cpu| {
+            let pc = cpu.pc();
+            cpu.set_pc(pc.wrapping_add(2));
+        });
+        routines.register(0x2A0, |_| {});
+        routines
+    }
+
+    ///run `routine` for `0NNN` calls to `address`, replacing any already
+    ///there
+    pub fn register<F>(&mut self, address: u16, routine: F)
+    where
+        F: FnMut(&mut Cpu) + 'static,
+    {
+        self.routines.insert(address & 0x0FFF, Box::new(routine));
+    }
+
+    ///forget the routine at `address`, so calls to it fault
+    pub fn unregister(&mut self, address: u16) {
+        self.routines.remove(&(address & 0x0FFF));
+    }
+
+    pub fn contains(&self, address: u16) -> bool {
+        self.routines.contains_key(&(address & 0x0FFF))
+    }
+
+    ///take the routine out while it runs, as it borrows the cpu holding it
+    pub(crate) fn take(&mut self, address: u16) -> Option<Routine> {
+        self.routines.remove(&address)
+    }
+
+    ///put a routine back after `take`, unless it registered a new one
+    pub(crate) fn restore(&mut self, address: u16, routine: Routine) {
+        self.routines.entry(address).or_insert(routine);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fault::Fault;
+
+    #[test]
+    fn test_builtin() {
+        //sys 0x151 ; sys 0x188 ; jmp 0x200 ; sys 0x230
+        let rom = [0x01, 0x51, 0x01, 0x88, 0x12, 0x00, 0x02, 0x30];
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&rom).unwrap();
+        cpu.set_delay(1);
+        cpu.tick();
+        assert_eq!(cpu.pc(), 0x200);
+        cpu.tick_timers();
+        cpu.tick();
+        assert_eq!(cpu.pc(), 0x202);
+        cpu.tick();
+        assert_eq!(cpu.pc(), 0x206);
+        cpu.screen_mut()[5] = true;
+        cpu.tick();
+        assert!(!cpu.screen()[5]);
+        assert_eq!(cpu.fault(), None);
+    }
+
+    #[test]
+    fn test_register() {
+        let mut cpu = Cpu::new();
+        //sys 0x123 ; sys 0x123
+        cpu.load_rom(&[0x01, 0x23, 0x01, 0x23]).unwrap();
+        let mut calls = 0;
+        cpu.routines_mut().register(0x123, move |cpu| {
+            calls += 1;
+            cpu.registers_mut()[1] = calls;
+        });
+        cpu.tick();
+        cpu.tick();
+        assert_eq!(cpu.registers()[1], 2);
+
+        //only the low 12 bits are an address
+        assert!(cpu.routines().contains(0x1123));
+        cpu.routines_mut().unregister(0x1123);
+        assert!(!cpu.routines().contains(0x123));
+        cpu.set_pc(0x200);
+        cpu.tick();
+        assert_eq!(
+            cpu.fault(),
+            Some(Fault::UnknownRoutine {
+                pc: 0x200,
+                address: 0x123
+            })
+        );
+    }
+}
@@ -0,0 +1,684 @@
+//! Scripted checks of how a ROM behaves
+//!
+//! A scenario is a list of steps run against a fresh `Cpu`, built either
+//! with the `Scenario` builder methods or parsed from text, one step a line:
+//!
+//! ```text
+//! # comments start with a hash
+//! load ../roms/PONG quirks vip seed 7
+//! run 10
+//! hold 5 3
+//! wait until pc == 0x2A0 or 200 frames
+//! assert v3 == 2
+//! assert screen 0 12
+//!     #.
+//!     #?
+//! ```
+//!
+//! `load` paths are relative to the scenario file. `hold` holds a hex key
+//! down for a number of frames. `wait` runs until the condition holds,
+//! checking before every instruction, or gives up after the frames and
+//! carries on so a later `assert` reports what went wrong. Conditions compare
+//! `v0`-`vf`, `i`, `pc`, `delay`, `sound`, `sp` (the stack depth) or
+//! `mem[ADDR]` with `==`, `!=`, `<`, `<=`, `>` or `>=`. The indented rows
+//! after `assert screen X Y` are matched against the screen from (X, Y), `#`
+//! lit, `.` dark and `?` either.
+
+use cpu::{Cpu, SCREEN_HEIGHT, SCREEN_WIDTH};
+use failure;
+use headless::{screen_ascii, TICKS_PER_FRAME};
+use quirks::Quirks;
+use rom;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+///The part of the machine a condition looks at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Register(u8),
+    I,
+    Pc,
+    Delay,
+    Sound,
+    ///number of return addresses on the stack
+    StackDepth,
+    Memory(u16),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+///A comparison of part of the machine with a constant
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    pub operand: Operand,
+    pub comparison: Comparison,
+    pub value: u16,
+}
+
+///Where a scenario gets its ROM from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rom {
+    Path(PathBuf),
+    Bytes(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    Load {
+        rom: Rom,
+        quirks: Quirks,
+        seed: u64,
+    },
+    Run {
+        frames: u32,
+    },
+    Hold {
+        key: u8,
+        frames: u32,
+    },
+    WaitUntil {
+        condition: Condition,
+        frames: u32,
+    },
+    Assert(Condition),
+    ///rows of `#`, `.` and `?` matched from (x, y)
+    AssertScreen {
+        x: usize,
+        y: usize,
+        rows: Vec<String>,
+    },
+}
+
+///A failed step, with the screen at the time
+#[derive(Debug)]
+pub struct Failure {
+    pub scenario: String,
+    ///1-based
+    pub step: usize,
+    ///line of the scenario file, if it was parsed from one
+    pub line: Option<usize>,
+    pub description: String,
+    pub message: String,
+    pub screen: Option<String>,
+}
+
+///A named list of steps
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scenario {
+    name: String,
+    steps: Vec<(Option<usize>, Step)>,
+}
+
+impl Condition {
+    pub fn new(operand: Operand, comparison: Comparison, value: u16) -> Self {
+        Condition {
+            operand,
+            comparison,
+            value,
+        }
+    }
+
+    ///the current value of the operand
+    pub fn actual(&self, cpu: &Cpu) -> u16 {
+        match self.operand {
+            Operand::Register(x) => u16::from(cpu.registers()[usize::from(x & 0x0F)]),
+            Operand::I => cpu.i(),
+            Operand::Pc => cpu.pc(),
+            Operand::Delay => u16::from(cpu.delay()),
+            Operand::Sound => u16::from(cpu.sound()),
+            Operand::StackDepth => cpu.stack().len() as u16,
+            Operand::Memory(address) => u16::from(cpu.memory()[usize::from(address & 0xFFF)]),
+        }
+    }
+
+    pub fn holds(&self, cpu: &Cpu) -> bool {
+        let actual = self.actual(cpu);
+        match self.comparison {
+            Comparison::Eq => actual == self.value,
+            Comparison::Ne => actual != self.value,
+            Comparison::Lt => actual < self.value,
+            Comparison::Le => actual <= self.value,
+            Comparison::Gt => actual > self.value,
+            Comparison::Ge => actual >= self.value,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.operand {
+            Operand::Register(x) => write!(f, "v{:x}", x)?,
+            Operand::I => write!(f, "i")?,
+            Operand::Pc => write!(f, "pc")?,
+            Operand::Delay => write!(f, "delay")?,
+            Operand::Sound => write!(f, "sound")?,
+            Operand::StackDepth => write!(f, "sp")?,
+            Operand::Memory(address) => write!(f, "mem[0x{:03X}]", address)?,
+        }
+        let comparison = match self.comparison {
+            Comparison::Eq => "==",
+            Comparison::Ne => "!=",
+            Comparison::Lt => "<",
+            Comparison::Le => "<=",
+            Comparison::Gt => ">",
+            Comparison::Ge => ">=",
+        };
+        write!(f, " {} 0x{:X}", comparison, self.value)
+    }
+}
+
+impl FromStr for Condition {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        let (operand, comparison, value) = match words[..] {
+            [operand, comparison, value] => (operand, comparison, value),
+            _ => bail!("expected OPERAND COMPARISON VALUE, found '{}'", s),
+        };
+        let operand = match operand {
+            "i" => Operand::I,
+            "pc" => Operand::Pc,
+            "delay" => Operand::Delay,
+            "sound" => Operand::Sound,
+            "sp" => Operand::StackDepth,
+            _ => {
+                if let Some(address) = operand
+                    .strip_prefix("mem[")
+                    .and_then(|rest| rest.strip_suffix(']'))
+                {
+                    Operand::Memory(parse_number(address)?)
+                } else if let Some(register) = operand.strip_prefix('v') {
+                    match u8::from_str_radix(register, 16) {
+                        Ok(x) if register.len() == 1 => Operand::Register(x),
+                        _ => bail!("no such register '{}'", operand),
+                    }
+                } else {
+                    bail!("unknown operand '{}'", operand)
+                }
+            }
+        };
+        let comparison = match comparison {
+            "==" => Comparison::Eq,
+            "!=" => Comparison::Ne,
+            "<" => Comparison::Lt,
+            "<=" => Comparison::Le,
+            ">" => Comparison::Gt,
+            ">=" => Comparison::Ge,
+            _ => bail!("unknown comparison '{}'", comparison),
+        };
+        Ok(Condition::new(operand, comparison, parse_number(value)?))
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Step::Load { ref rom, seed, .. } => match *rom {
+                Rom::Path(ref path) => write!(f, "load {} seed {}", path.display(), seed),
+                Rom::Bytes(ref bytes) => write!(f, "load {} bytes seed {}", bytes.len(), seed),
+            },
+            Step::Run { frames } => write!(f, "run {}", frames),
+            Step::Hold { key, frames } => write!(f, "hold {:x} {}", key, frames),
+            Step::WaitUntil { condition, frames } => {
+                write!(f, "wait until {} or {} frames", condition, frames)
+            }
+            Step::Assert(condition) => write!(f, "assert {}", condition),
+            Step::AssertScreen { x, y, .. } => write!(f, "assert screen {} {}", x, y),
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: step {}", self.scenario, self.step)?;
+        if let Some(line) = self.line {
+            write!(f, " (line {})", line)?;
+        }
+        write!(f, " `{}` failed: {}", self.description, self.message)?;
+        if let Some(ref screen) = self.screen {
+            write!(f, "\n{}", screen)?;
+        }
+        Ok(())
+    }
+}
+
+impl failure::Fail for Failure {}
+
+///Executes steps, keeping count of where in the frame it is
+struct Runner {
+    cpu: Option<Cpu>,
+    ///instructions run so far in the current frame
+    ticks: u32,
+}
+
+impl Runner {
+    fn tick(cpu: &mut Cpu, ticks: &mut u32) {
+        cpu.tick();
+        *ticks += 1;
+        if *ticks == TICKS_PER_FRAME {
+            *ticks = 0;
+            cpu.tick_timers();
+        }
+    }
+
+    fn step(&mut self, step: &Step) -> Result<(), String> {
+        if let Step::Load {
+            rom: ref source,
+            quirks,
+            seed,
+        } = *step
+        {
+            let mut cpu = Cpu::with_quirks(quirks);
+            cpu.seed(seed);
+            let bytes = match *source {
+                Rom::Path(ref path) => rom::load(path).map_err(|e| e.to_string())?,
+                Rom::Bytes(ref bytes) => bytes.clone(),
+            };
+            cpu.load_rom(&bytes).map_err(|e| e.to_string())?;
+            self.cpu = Some(cpu);
+            self.ticks = 0;
+            return Ok(());
+        }
+
+        let ticks = &mut self.ticks;
+        let cpu = self.cpu.as_mut().ok_or("no ROM has been loaded")?;
+        match *step {
+            Step::Load { .. } => unreachable!(),
+            Step::Run { frames } => {
+                for _ in 0..u64::from(frames) * u64::from(TICKS_PER_FRAME) {
+                    Runner::tick(cpu, ticks);
+                }
+            }
+            Step::Hold { key, frames } => {
+                *cpu.key_mut(key) = true;
+                for _ in 0..u64::from(frames) * u64::from(TICKS_PER_FRAME) {
+                    Runner::tick(cpu, ticks);
+                }
+                *cpu.key_mut(key) = false;
+            }
+            Step::WaitUntil { condition, frames } => {
+                for _ in 0..u64::from(frames) * u64::from(TICKS_PER_FRAME) {
+                    if condition.holds(cpu) {
+                        break;
+                    }
+                    Runner::tick(cpu, ticks);
+                }
+            }
+            Step::Assert(condition) => {
+                if !condition.holds(cpu) {
+                    return Err(format!("found 0x{:X}", condition.actual(cpu)));
+                }
+            }
+            Step::AssertScreen { x, y, ref rows } => {
+                for (row_index, row) in rows.iter().enumerate() {
+                    for (column, expected) in row.chars().enumerate() {
+                        let (px, py) = (x + column, y + row_index);
+                        if px >= SCREEN_WIDTH || py >= SCREEN_HEIGHT {
+                            return Err(format!("({}, {}) is off the screen", px, py));
+                        }
+                        let lit = cpu.screen()[py * SCREEN_WIDTH + px];
+                        let matches = match expected {
+                            '#' => lit,
+                            '.' => !lit,
+                            _ => true,
+                        };
+                        if !matches {
+                            return Err(format!(
+                                "pixel ({}, {}) is {}",
+                                px,
+                                py,
+                                if lit { "lit" } else { "dark" }
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Scenario {
+    ///new scenario with no steps, `name` is used in failure reports
+    pub fn new(name: &str) -> Self {
+        Scenario {
+            name: name.into(),
+            steps: Vec::new(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn steps(&self) -> impl Iterator<Item = &Step> {
+        self.steps.iter().map(|(_, step)| step)
+    }
+
+    ///append a step
+    pub fn step(mut self, step: Step) -> Self {
+        self.steps.push((None, step));
+        self
+    }
+
+    ///start again with the ROM at `path`, seeding `rand` with zero
+    pub fn load<P: AsRef<Path>>(self, path: P, quirks: Quirks) -> Self {
+        self.step(Step::Load {
+            rom: Rom::Path(path.as_ref().to_path_buf()),
+            quirks,
+            seed: 0,
+        })
+    }
+
+    ///start again with `rom`, seeding `rand` with zero
+    pub fn load_rom(self, rom: &[u8], quirks: Quirks) -> Self {
+        self.step(Step::Load {
+            rom: Rom::Bytes(rom.to_vec()),
+            quirks,
+            seed: 0,
+        })
+    }
+
+    pub fn run(self, frames: u32) -> Self {
+        self.step(Step::Run { frames })
+    }
+
+    pub fn hold(self, key: u8, frames: u32) -> Self {
+        self.step(Step::Hold { key, frames })
+    }
+
+    pub fn wait_until(self, condition: Condition, frames: u32) -> Self {
+        self.step(Step::WaitUntil { condition, frames })
+    }
+
+    pub fn assert(self, condition: Condition) -> Self {
+        self.step(Step::Assert(condition))
+    }
+
+    pub fn assert_screen(self, x: usize, y: usize, rows: &[&str]) -> Self {
+        self.step(Step::AssertScreen {
+            x,
+            y,
+            rows: rows.iter().map(|row| row.to_string()).collect(),
+        })
+    }
+
+    ///run every step, stopping at the first that fails
+    pub fn check(&self) -> Result<(), Failure> {
+        let mut runner = Runner {
+            cpu: None,
+            ticks: 0,
+        };
+        for (index, (line, step)) in self.steps.iter().enumerate() {
+            if let Err(message) = runner.step(step) {
+                return Err(Failure {
+                    scenario: self.name.clone(),
+                    step: index + 1,
+                    line: *line,
+                    description: step.to_string(),
+                    message,
+                    screen: runner.cpu.as_ref().map(screen_ascii),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    ///read a scenario file
+    pub fn from_file(path: &Path) -> Result<Self, failure::Error> {
+        let text =
+            fs::read_to_string(path).map_err(|e| format_err!("{}: {}", path.display(), e))?;
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        Scenario::parse(&text, &path.display().to_string(), base)
+    }
+
+    ///parse the text form, resolving ROM paths against `base`
+    pub fn parse(text: &str, name: &str, base: &Path) -> Result<Self, failure::Error> {
+        let mut scenario = Scenario::new(name);
+        let mut lines = text.lines().enumerate().peekable();
+        while let Some((index, line)) = lines.next() {
+            let number = index + 1;
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let step = parse_step(line, base, &mut || {
+                //the indented pattern rows of `assert screen`
+                let mut rows = Vec::new();
+                while let Some(&(_, row)) = lines.peek() {
+                    let is_row = row.starts_with(char::is_whitespace)
+                        && !row.trim().is_empty()
+                        && row.trim().chars().all(|c| "#.?".contains(c));
+                    if !is_row {
+                        break;
+                    }
+                    rows.push(row.trim().to_string());
+                    lines.next();
+                }
+                rows
+            })
+            .map_err(|e| format_err!("{}:{}: {}", name, number, e))?;
+            scenario.steps.push((Some(number), step));
+        }
+        Ok(scenario)
+    }
+}
+
+fn parse_step<F>(line: &str, base: &Path, rows: &mut F) -> Result<Step, failure::Error>
+where
+    F: FnMut() -> Vec<String>,
+{
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let step = match words[..] {
+        ["load", path, ref options @ ..] => {
+            let mut quirks = Quirks::default();
+            let mut seed = 0;
+            for option in options.chunks(2) {
+                match *option {
+                    ["quirks", profile] => quirks = profile.parse().map_err(failure::err_msg)?,
+                    ["seed", value] => seed = parse_number(value)?.into(),
+                    _ => bail!("unknown load option '{}'", option.join(" ")),
+                }
+            }
+            Step::Load {
+                rom: Rom::Path(base.join(path)),
+                quirks,
+                seed,
+            }
+        }
+        ["run", frames] => Step::Run {
+            frames: frames.parse()?,
+        },
+        ["hold", key, frames] => {
+            let key = u8::from_str_radix(key, 16)?;
+            if key > 0x0F {
+                bail!("no such key '{:x}'", key);
+            }
+            Step::Hold {
+                key,
+                frames: frames.parse()?,
+            }
+        }
+        ["wait", "until", operand, comparison, value, "or", frames, "frames"] => Step::WaitUntil {
+            condition: [operand, comparison, value].join(" ").parse()?,
+            frames: frames.parse()?,
+        },
+        ["assert", "screen", x, y] => {
+            let rows = rows();
+            if rows.is_empty() {
+                bail!("`assert screen` needs indented rows of '#', '.' and '?' below it");
+            }
+            Step::AssertScreen {
+                x: x.parse()?,
+                y: y.parse()?,
+                rows,
+            }
+        }
+        ["assert", ..] => Step::Assert(words[1..].join(" ").parse()?),
+        _ => bail!("unknown step '{}'", line),
+    };
+    Ok(step)
+}
+
+///decimal, or hex with a `0x` prefix
+fn parse_number(text: &str) -> Result<u16, failure::Error> {
+    Ok(match text.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16)?,
+        None => text.parse()?,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    //0x200: mov v5,5
+    //0x202: skup v5
+    //0x204: add v3,1
+    //0x206: mvi 0x050 (the font's 0)
+    //0x208: sprite v0,v0,5
+    //0x20A: jmp 0x20A
+    const ROM: [u8; 12] = [
+        0x65, 0x05, 0xE5, 0xA1, 0x73, 0x01, 0xA0, 0x50, 0xD0, 0x05, 0x12, 0x0A,
+    ];
+
+    #[test]
+    fn test_builder() {
+        let result = Scenario::new("builder")
+            .load_rom(&ROM, Quirks::vip())
+            .hold(5, 1)
+            .wait_until(Condition::new(Operand::Pc, Comparison::Eq, 0x20A), 10)
+            .assert(Condition::new(Operand::Register(3), Comparison::Eq, 1))
+            .assert(Condition::new(Operand::I, Comparison::Eq, 0x50))
+            .assert_screen(0, 0, &["####.", "#..#?"])
+            .check();
+        assert!(result.is_ok(), "{}", result.unwrap_err());
+    }
+
+    #[test]
+    fn test_failure_reports_step() {
+        let failure = Scenario::new("failing")
+            .load_rom(&ROM, Quirks::default())
+            .run(1)
+            .assert(Condition::new(Operand::Register(3), Comparison::Eq, 1))
+            .check()
+            .unwrap_err();
+        assert_eq!(failure.step, 3);
+        assert_eq!(failure.message, "found 0x0");
+        let report = failure.to_string();
+        assert!(report.starts_with("failing: step 3 `assert v3 == 0x1` failed: found 0x0\n"));
+        assert!(report.contains("\n####...."));
+    }
+
+    #[test]
+    fn test_needs_load() {
+        let failure = Scenario::new("empty").run(1).check().unwrap_err();
+        assert_eq!(failure.message, "no ROM has been loaded");
+        assert!(failure.screen.is_none());
+    }
+
+    #[test]
+    fn test_wait_stops_mid_frame() {
+        Scenario::new("wait")
+            .load_rom(&ROM, Quirks::default())
+            .wait_until(Condition::new(Operand::Pc, Comparison::Ge, 0x206), 1)
+            .assert(Condition::new(Operand::Pc, Comparison::Eq, 0x206))
+            .check()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_many_frames() {
+        //more instructions than a u32 counts, though the wait is over at once
+        let scenario = Scenario::parse("run 50000000", "many", Path::new("")).unwrap();
+        assert_eq!(
+            *scenario.steps().next().unwrap(),
+            Step::Run { frames: 50_000_000 }
+        );
+        Scenario::new("many")
+            .load_rom(&ROM, Quirks::default())
+            .wait_until(Condition::new(Operand::Pc, Comparison::Eq, 0x200), u32::MAX)
+            .check()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_parse() {
+        let text = "# a comment\n\
+                    load game.ch8 quirks schip seed 0x10\n\
+                    hold f 3\n\
+                    wait until mem[0x300] != 0 or 200 frames\n\
+                    assert sp <= 2\n\
+                    assert screen 1 2\n\
+                    \x20   #.?\n\
+                    \x20   ..#\n\
+                    run 4 # trailing comment\n";
+        let scenario = Scenario::parse(text, "test", Path::new("dir")).unwrap();
+        let steps: Vec<&Step> = scenario.steps().collect();
+        assert_eq!(
+            *steps[0],
+            Step::Load {
+                rom: Rom::Path(Path::new("dir").join("game.ch8")),
+                quirks: Quirks::schip(),
+                seed: 16,
+            }
+        );
+        assert_eq!(*steps[1], Step::Hold { key: 15, frames: 3 });
+        assert_eq!(
+            *steps[2],
+            Step::WaitUntil {
+                condition: Condition::new(Operand::Memory(0x300), Comparison::Ne, 0),
+                frames: 200,
+            }
+        );
+        assert_eq!(
+            *steps[3],
+            Step::Assert(Condition::new(Operand::StackDepth, Comparison::Le, 2))
+        );
+        assert_eq!(
+            *steps[4],
+            Step::AssertScreen {
+                x: 1,
+                y: 2,
+                rows: vec!["#.?".into(), "..#".into()],
+            }
+        );
+        assert_eq!(*steps[5], Step::Run { frames: 4 });
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let parse = |text| Scenario::parse(text, "bad", Path::new(""));
+        assert_eq!(
+            parse("run 1\nfly 3").unwrap_err().to_string(),
+            "bad:2: unknown step 'fly 3'"
+        );
+        assert!(parse("assert vg == 1").is_err());
+        assert!(parse("assert v1 = 1").is_err());
+        assert!(parse("hold 10 1").is_err());
+        assert!(parse("load x quirks bogus").is_err());
+        assert!(parse("assert screen 0 0\nrun 1").is_err());
+    }
+
+    #[test]
+    fn test_parsed_line_in_failure() {
+        let text = "load x.ch8\n\nrun 1\n";
+        let failure = Scenario::parse(text, "missing", Path::new("/nonexistent"))
+            .unwrap()
+            .check()
+            .unwrap_err();
+        assert_eq!(failure.step, 1);
+        assert_eq!(failure.line, Some(1));
+    }
+}
@@ -0,0 +1,83 @@
+//! Host calls for self-checking test ROMs
+//!
+//! With semihosting turned on, a few of the `0NNN` machine code calls out to
+//! the emulator instead:
+//!
+//! | opcode | call                                                  |
+//! |--------|-------------------------------------------------------|
+//! | `010X` | print VX in decimal, then a newline                   |
+//! | `011X` | print VX as a character                               |
+//! | `02XY` | assert VX == VY, exiting with code 1 if it isn't      |
+//! | `03NN` | exit with code NN                                     |
+//!
+//! In Octo source the opcodes are written as bytes, e.g. `0x02 0x12` to
+//! assert that v1 equals v2.
+//!
+//! While semihosting is on, host calls win over any routine registered at
+//! the same address, such as the built in `0230` and `02A0`.
+
+use std::mem;
+
+///A call from the ROM to the emulator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostCall {
+    ///010X
+    PrintDecimal(u8),
+    ///011X
+    PrintChar(u8),
+    ///02XY
+    AssertEq(u8, u8),
+    ///03NN
+    Exit(u8),
+}
+
+impl HostCall {
+    ///the host call for the machine code address of a `0NNN`, if it is one
+    pub fn decode(address: u16) -> Option<Self> {
+        let x = ((address >> 4) & 0x0F) as u8;
+        let y = (address & 0x0F) as u8;
+        match address & 0x0F00 {
+            0x0100 if x == 0 => Some(HostCall::PrintDecimal(y)),
+            0x0100 if x == 1 => Some(HostCall::PrintChar(y)),
+            0x0200 => Some(HostCall::AssertEq(x, y)),
+            0x0300 => Some(HostCall::Exit((address & 0xFF) as u8)),
+            _ => None,
+        }
+    }
+}
+
+///What the host calls have done so far
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Semihost {
+    ///printed and not yet taken
+    pub output: Vec<u8>,
+    ///set once the ROM has exited, after which the cpu runs no further
+    pub exit_code: Option<u8>,
+}
+
+impl Semihost {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    ///everything printed since the last call
+    pub fn take_output(&mut self) -> Vec<u8> {
+        mem::take(&mut self.output)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(HostCall::decode(0x105), Some(HostCall::PrintDecimal(5)));
+        assert_eq!(HostCall::decode(0x11A), Some(HostCall::PrintChar(0xA)));
+        assert_eq!(HostCall::decode(0x212), Some(HostCall::AssertEq(1, 2)));
+        assert_eq!(HostCall::decode(0x37F), Some(HostCall::Exit(0x7F)));
+        assert_eq!(HostCall::decode(0x125), None);
+        assert_eq!(HostCall::decode(0x0FB), None);
+        assert_eq!(HostCall::decode(0x400), None);
+    }
+}
@@ -0,0 +1,178 @@
+//! Symbol maps tying ROM addresses back to assembler source
+//!
+//! The map is a plain text file with one record per line:
+//!
+//! ```text
+//! label main 0x200
+//! line 0x200 12 game.s
+//! break 0x204 before-draw
+//! monitor v3 1 v3
+//! monitor 0x300 8 score
+//! ```
+//!
+//! Lines starting with `;` are comments.
+
+use failure;
+use std::collections::BTreeMap;
+use std::fmt;
+
+///Where an instruction or data byte came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub file: String,
+    ///1-based
+    pub line: usize,
+}
+
+///What a monitor watches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MonitorTarget {
+    Register(u8),
+    Memory(u16),
+}
+
+///A value the debugger shows whenever execution stops
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Monitor {
+    pub name: String,
+    pub target: MonitorTarget,
+    ///number of bytes to show
+    pub length: usize,
+}
+
+///Labels, source lines, breakpoints and monitors produced by an assembler
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolMap {
+    pub labels: BTreeMap<String, u16>,
+    pub lines: BTreeMap<u16, SourceLocation>,
+    ///breakpoint names, keyed by address
+    pub breakpoints: BTreeMap<u16, String>,
+    pub monitors: Vec<Monitor>,
+}
+
+impl SymbolMap {
+    ///new, empty map
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    ///the label defined at `address`, if any
+    pub fn label_at(&self, address: u16) -> Option<&str> {
+        self.labels
+            .iter()
+            .find(|&(_, &a)| a == address)
+            .map(|(name, _)| name.as_str())
+    }
+
+    ///parse the text form written by `Display`
+    pub fn parse(text: &str) -> Result<Self, failure::Error> {
+        let mut map = SymbolMap::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+            let mut fields = line.splitn(4, ' ');
+            match (fields.next(), fields.next(), fields.next(), fields.next()) {
+                (Some("label"), Some(name), Some(address), None) => {
+                    map.labels.insert(name.into(), parse_address(address)?);
+                }
+                (Some("line"), Some(address), Some(source_line), Some(file)) => {
+                    let location = SourceLocation {
+                        file: file.into(),
+                        line: source_line.parse()?,
+                    };
+                    map.lines.insert(parse_address(address)?, location);
+                }
+                (Some("break"), Some(address), Some(name), None) => {
+                    map.breakpoints.insert(parse_address(address)?, name.into());
+                }
+                (Some("monitor"), Some(target), Some(length), Some(name)) => {
+                    let target = match target.strip_prefix('v') {
+                        Some(register) => {
+                            MonitorTarget::Register(u8::from_str_radix(register, 16)?)
+                        }
+                        None => MonitorTarget::Memory(parse_address(target)?),
+                    };
+                    map.monitors.push(Monitor {
+                        name: name.into(),
+                        target,
+                        length: length.parse()?,
+                    });
+                }
+                _ => bail!("malformed symbol map record on line {}", number + 1),
+            }
+        }
+        Ok(map)
+    }
+}
+
+fn parse_address(text: &str) -> Result<u16, failure::Error> {
+    let digits = text.trim_start_matches("0x");
+    Ok(u16::from_str_radix(digits, 16)?)
+}
+
+impl fmt::Display for SymbolMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "; chip8 symbol map")?;
+        for (name, address) in &self.labels {
+            writeln!(f, "label {} 0x{:03X}", name, address)?;
+        }
+        for (address, location) in &self.lines {
+            writeln!(
+                f,
+                "line 0x{:03X} {} {}",
+                address, location.line, location.file
+            )?;
+        }
+        for (address, name) in &self.breakpoints {
+            writeln!(f, "break 0x{:03X} {}", address, name)?;
+        }
+        for monitor in &self.monitors {
+            match monitor.target {
+                MonitorTarget::Register(x) => write!(f, "monitor v{:x}", x)?,
+                MonitorTarget::Memory(address) => write!(f, "monitor 0x{:03X}", address)?,
+            }
+            writeln!(f, " {} {}", monitor.length, monitor.name)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let mut map = SymbolMap::new();
+        map.labels.insert("main".into(), 0x200);
+        map.lines.insert(
+            0x202,
+            SourceLocation {
+                file: "my game.s".into(),
+                line: 7,
+            },
+        );
+        map.breakpoints.insert(0x204, "draw".into());
+        map.monitors.push(Monitor {
+            name: "v3".into(),
+            target: MonitorTarget::Register(3),
+            length: 1,
+        });
+        map.monitors.push(Monitor {
+            name: "score".into(),
+            target: MonitorTarget::Memory(0x300),
+            length: 3,
+        });
+        let parsed = SymbolMap::parse(&map.to_string()).unwrap();
+        assert_eq!(parsed, map);
+        assert_eq!(parsed.label_at(0x200), Some("main"));
+    }
+
+    #[test]
+    fn test_malformed() {
+        assert!(SymbolMap::parse("label main").is_err());
+        assert!(SymbolMap::parse("line 0xZZZ 1 a.s").is_err());
+    }
+}
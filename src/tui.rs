@@ -0,0 +1,454 @@
+//! Running ROMs in a terminal, for machines with no display to open a
+//! window on, such as over SSH
+//!
+//! The screen is drawn with Unicode half blocks, two pixels to a character
+//! so 64x32 fits in 64x16 cells, or with braille, eight to a character so it
+//! fits in 32x8 (a 128x64 screen would fit in 64x16). Colours are sent as
+//! 24-bit escape codes, with a status line of the PC, I and timers under
+//! the screen.
+//!
+//! Terminals send key presses but not releases, so a keypad key counts as
+//! held for `HOLD_FRAMES` frames after the terminal last sent it. That's long
+//! enough to bridge the pause before the terminal starts repeating a key
+//! that's held down.
+
+use cpu::{Cpu, SCREEN_HEIGHT, SCREEN_WIDTH};
+use failure;
+use options::{Palette, Rgb};
+use std::fmt;
+use std::str::FromStr;
+
+///Frames a keypad key stays down after the terminal sends it
+pub const HOLD_FRAMES: u8 = 30;
+
+///How the screen is drawn in a terminal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Glyphs {
+    ///`▀`, `▄` and `█`, a pixel wide and two high
+    HalfBlocks,
+    ///braille patterns, two pixels wide and four high
+    Braille,
+}
+
+///Where a ROM is shown when it isn't run headless
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Frontend {
+    ///an SDL window
+    #[default]
+    Sdl,
+    ///the terminal it's run from
+    Tui(Glyphs),
+}
+
+impl fmt::Display for Frontend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Frontend::Sdl => write!(f, "sdl"),
+            Frontend::Tui(Glyphs::HalfBlocks) => write!(f, "tui"),
+            Frontend::Tui(Glyphs::Braille) => write!(f, "braille"),
+        }
+    }
+}
+
+impl FromStr for Frontend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sdl" => Ok(Frontend::Sdl),
+            "tui" => Ok(Frontend::Tui(Glyphs::HalfBlocks)),
+            "braille" => Ok(Frontend::Tui(Glyphs::Braille)),
+            _ => Err(format!(
+                "unknown frontend '{}', expected sdl, tui or braille",
+                s
+            )),
+        }
+    }
+}
+
+///A key, or something else the terminal sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    ///a printable character, lower case, or space, tab or return
+    Char(char),
+    Up,
+    Down,
+    Left,
+    Right,
+    F2,
+    F5,
+    F6,
+    Escape,
+    ///ctrl-C, which raw mode delivers rather than acting on
+    Interrupt,
+}
+
+///the inputs in what the terminal sent, skipping escape sequences for keys
+///that mean nothing here
+pub fn decode(bytes: &[u8]) -> Vec<Input> {
+    let mut inputs = Vec::new();
+    let mut at = 0;
+    while at < bytes.len() {
+        let byte = bytes[at];
+        at += 1;
+        let input = match byte {
+            0x03 => Input::Interrupt,
+            0x1B => match bytes.get(at) {
+                Some(&b'[') | Some(&b'O') => {
+                    //parameters, then a final byte from @ to ~
+                    let start = at + 1;
+                    let end = bytes[start..]
+                        .iter()
+                        .position(|&b| (0x40..=0x7E).contains(&b))
+                        .map_or(bytes.len(), |found| start + found + 1);
+                    at = end;
+                    match &bytes[start..end] {
+                        b"A" => Input::Up,
+                        b"B" => Input::Down,
+                        b"C" => Input::Right,
+                        b"D" => Input::Left,
+                        b"Q" | b"12~" => Input::F2,
+                        b"15~" => Input::F5,
+                        b"17~" => Input::F6,
+                        _ => continue,
+                    }
+                }
+                _ => Input::Escape,
+            },
+            b'\r' | b'\n' => Input::Char('\r'),
+            b'\t' | b' ' => Input::Char(byte as char),
+            0x21..=0x7E => Input::Char((byte as char).to_ascii_lowercase()),
+            _ => continue,
+        };
+        inputs.push(input);
+    }
+    inputs
+}
+
+///The terminal input for each keypad key
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keymap {
+    inputs: Vec<(Input, u16)>,
+}
+
+impl Keymap {
+    ///from the same key names the window takes: a single character, or
+    ///`Space`, `Tab`, `Return`, `Up`, `Down`, `Left` or `Right`
+    pub fn new(names: &[String; 16]) -> Result<Self, failure::Error> {
+        let mut inputs: Vec<(Input, u16)> = Vec::new();
+        for (key, name) in names.iter().enumerate() {
+            let mut chars = name.chars();
+            let input = match (chars.next(), chars.next()) {
+                (Some(c), None) if c.is_ascii_graphic() => Input::Char(c.to_ascii_lowercase()),
+                _ => match name.to_lowercase().as_str() {
+                    "space" => Input::Char(' '),
+                    "tab" => Input::Char('\t'),
+                    "return" => Input::Char('\r'),
+                    "up" => Input::Up,
+                    "down" => Input::Down,
+                    "left" => Input::Left,
+                    "right" => Input::Right,
+                    _ => bail!("a terminal can't read the key '{}'", name),
+                },
+            };
+            match inputs.iter_mut().find(|&&mut (mapped, _)| mapped == input) {
+                Some(&mut (_, ref mut keys)) => *keys |= 1 << key,
+                None => inputs.push((input, 1 << key)),
+            }
+        }
+        Ok(Keymap { inputs })
+    }
+
+    ///the keypad bits for an input, 0 if it's not mapped
+    pub fn keys(&self, input: Input) -> u16 {
+        self.inputs
+            .iter()
+            .find(|&&(mapped, _)| mapped == input)
+            .map_or(0, |&(_, keys)| keys)
+    }
+}
+
+///The keypad, with each key let go `HOLD_FRAMES` after it was pressed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Keypad {
+    ///frames left before each key is let go
+    held: [u8; 16],
+}
+
+impl Keypad {
+    ///press the keys in `keys`, or keep them down if they are already
+    pub fn press(&mut self, keys: u16) {
+        for (key, held) in self.held.iter_mut().enumerate() {
+            if keys & (1 << key) != 0 {
+                *held = HOLD_FRAMES;
+            }
+        }
+    }
+
+    ///call at the end of each frame
+    pub fn frame(&mut self) {
+        for held in self.held.iter_mut() {
+            *held = held.saturating_sub(1);
+        }
+    }
+
+    ///the keys down, as `Cpu::set_keys` takes them
+    pub fn keys(&self) -> u16 {
+        self.held
+            .iter()
+            .enumerate()
+            .filter(|&(_, &held)| held > 0)
+            .fold(0, |keys, (key, _)| keys | 1 << key)
+    }
+}
+
+///the framebuffer as lines of text
+pub fn render(screen: &[bool], glyphs: Glyphs) -> Vec<String> {
+    let lit = |x: usize, y: usize| screen[y * SCREEN_WIDTH + x];
+    match glyphs {
+        Glyphs::HalfBlocks => (0..SCREEN_HEIGHT)
+            .step_by(2)
+            .map(|y| {
+                (0..SCREEN_WIDTH)
+                    .map(|x| match (lit(x, y), lit(x, y + 1)) {
+                        (false, false) => ' ',
+                        (true, false) => '▀',
+                        (false, true) => '▄',
+                        (true, true) => '█',
+                    })
+                    .collect()
+            })
+            .collect(),
+        Glyphs::Braille => {
+            //the dot for each pixel of a 2x4 cell, by row then column
+            const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+            (0..SCREEN_HEIGHT)
+                .step_by(4)
+                .map(|y| {
+                    (0..SCREEN_WIDTH)
+                        .step_by(2)
+                        .map(|x| {
+                            let mut dots = 0;
+                            for (row, bits) in DOTS.iter().enumerate() {
+                                for (column, bit) in bits.iter().enumerate() {
+                                    if lit(x + column, y + row) {
+                                        dots |= bit;
+                                    }
+                                }
+                            }
+                            //a blank pattern is narrower than a space in some fonts
+                            if dots == 0 {
+                                ' '
+                            } else {
+                                ::std::char::from_u32(0x2800 + dots).unwrap_or(' ')
+                            }
+                        })
+                        .collect()
+                })
+                .collect()
+        }
+    }
+}
+
+///`PC 0x200  I 0x21E  DT 0  ST 0  frame 12`
+pub fn status(cpu: &Cpu, frame: u32) -> String {
+    format!(
+        "PC 0x{:03X}  I 0x{:03X}  DT {}  ST {}  frame {}",
+        cpu.pc(),
+        cpu.i(),
+        cpu.delay(),
+        cpu.sound(),
+        frame
+    )
+}
+
+fn colour(rgb: Rgb, layer: u8) -> String {
+    format!("\x1b[{};2;{};{};{}m", layer, rgb.0, rgb.1, rgb.2)
+}
+
+///the text that redraws the whole terminal: the screen in `palette`'s
+///colours, then `status`, which may run to several lines
+pub fn draw(screen: &[bool], glyphs: Glyphs, palette: Palette, status: &str) -> String {
+    let mut out = String::from("\x1b[H");
+    for line in render(screen, glyphs) {
+        out.push_str(&colour(palette.fill, 38));
+        out.push_str(&colour(palette.background, 48));
+        out.push_str(&line);
+        out.push_str("\x1b[0m\x1b[K\r\n");
+    }
+    for line in status.lines() {
+        out.push_str(line);
+        out.push_str("\x1b[K\r\n");
+    }
+    out.push_str("\x1b[J");
+    out
+}
+
+///The terminal in raw mode on the alternate screen, put back as it was
+///when dropped
+#[cfg(unix)]
+pub struct Terminal {
+    original: ::libc::termios,
+}
+
+#[cfg(unix)]
+impl Terminal {
+    ///take over the terminal on stdin and stdout
+    pub fn new() -> Result<Self, failure::Error> {
+        use libc;
+        use std::mem;
+
+        let original = unsafe {
+            let mut original: libc::termios = mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut original) != 0 {
+                bail!("the terminal frontend needs stdin to be a terminal");
+            }
+            let mut raw = original;
+            libc::cfmakeraw(&mut raw);
+            //reads return at once, with whatever there is
+            raw.c_cc[libc::VMIN] = 0;
+            raw.c_cc[libc::VTIME] = 0;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+                bail!("can't put the terminal in raw mode");
+            }
+            original
+        };
+        let mut terminal = Terminal { original };
+        terminal.write("\x1b[?1049h\x1b[?25l\x1b[2J")?;
+        Ok(terminal)
+    }
+
+    ///everything typed since the last read, without waiting
+    pub fn read(&mut self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut buffer = [0u8; 64];
+        loop {
+            let read = unsafe {
+                ::libc::read(
+                    ::libc::STDIN_FILENO,
+                    buffer.as_mut_ptr() as *mut ::libc::c_void,
+                    buffer.len(),
+                )
+            };
+            if read <= 0 {
+                return bytes;
+            }
+            bytes.extend_from_slice(&buffer[..read as usize]);
+        }
+    }
+
+    pub fn write(&mut self, text: &str) -> Result<(), failure::Error> {
+        use std::io::Write;
+        let stdout = ::std::io::stdout();
+        let mut stdout = stdout.lock();
+        stdout.write_all(text.as_bytes())?;
+        stdout.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = self.write("\x1b[0m\x1b[?25h\x1b[?1049l");
+        unsafe {
+            ::libc::tcsetattr(::libc::STDIN_FILENO, ::libc::TCSANOW, &self.original);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_frontend() {
+        for name in &["sdl", "tui", "braille"] {
+            assert_eq!(name.parse::<Frontend>().unwrap().to_string(), *name);
+        }
+        assert_eq!(
+            "braille".parse::<Frontend>(),
+            Ok(Frontend::Tui(Glyphs::Braille))
+        );
+        assert!("curses".parse::<Frontend>().is_err());
+    }
+
+    #[test]
+    fn test_render() {
+        let mut screen = [false; SCREEN_WIDTH * SCREEN_HEIGHT];
+        screen[0] = true;
+        screen[SCREEN_WIDTH + 1] = true;
+        screen[SCREEN_WIDTH * 3 + 1] = true;
+        screen[SCREEN_WIDTH * 31 + 63] = true;
+
+        let half = render(&screen, Glyphs::HalfBlocks);
+        assert_eq!(half.len(), 16);
+        assert!(half.iter().all(|line| line.chars().count() == 64));
+        assert!(half[0].starts_with("▀▄ "));
+        assert!(half[1].starts_with(" ▄ "));
+        assert!(half[15].ends_with('▄'));
+
+        let braille = render(&screen, Glyphs::Braille);
+        assert_eq!(braille.len(), 8);
+        assert!(braille.iter().all(|line| line.chars().count() == 32));
+        //dots 1, 5 and 8
+        assert!(braille[0].starts_with("\u{2891} "));
+        assert!(braille[7].ends_with('\u{2880}'));
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(
+            decode(b"wA \x1b[D\x1b[15~\x1bOQ\x1b[1;5C\x03\x1b"),
+            vec![
+                Input::Char('w'),
+                Input::Char('a'),
+                Input::Char(' '),
+                Input::Left,
+                Input::F5,
+                Input::F2,
+                Input::Interrupt,
+                Input::Escape,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_keys() {
+        let mut names: [String; 16] = Default::default();
+        for (key, name) in names.iter_mut().enumerate() {
+            *name = format!("{:X}", key);
+        }
+        names[5] = "W".into();
+        names[4] = "Left".into();
+        names[6] = "left".into();
+        let keymap = Keymap::new(&names).unwrap();
+        assert_eq!(keymap.keys(Input::Char('w')), 1 << 5);
+        assert_eq!(keymap.keys(Input::Left), 1 << 4 | 1 << 6);
+        assert_eq!(keymap.keys(Input::Char('a')), 1 << 0xA);
+        assert_eq!(keymap.keys(Input::Right), 0);
+
+        names[0] = "Keypad 0".into();
+        let error = Keymap::new(&names).unwrap_err().to_string();
+        assert!(error.contains("can't read the key 'Keypad 0'"));
+
+        let mut keypad = Keypad::default();
+        keypad.press(keymap.keys(Input::Char('w')));
+        assert_eq!(keypad.keys(), 1 << 5);
+        for _ in 1..HOLD_FRAMES {
+            keypad.frame();
+        }
+        assert_eq!(keypad.keys(), 1 << 5);
+        keypad.frame();
+        assert_eq!(keypad.keys(), 0);
+    }
+
+    #[test]
+    fn test_draw() {
+        let screen = [true; SCREEN_WIDTH * SCREEN_HEIGHT];
+        let text = draw(&screen, Glyphs::HalfBlocks, Palette::default(), "one\ntwo");
+        assert!(text.starts_with("\x1b[H\x1b[38;2;255;255;255m\x1b[48;2;0;0;0m█"));
+        assert_eq!(text.matches('\n').count(), 18);
+        assert!(text.ends_with("two\x1b[K\r\n\x1b[J"));
+    }
+}
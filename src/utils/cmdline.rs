@@ -1,4 +1,12 @@
+use bench;
 use clap;
+use config;
+use detect;
+use disasm;
+use fault::FaultPolicy;
+use quirks::Quirks;
+use std::path::Path;
+use tui::Frontend;
 use utils::types;
 
 pub fn parse_cmdline() -> types::Settings {
@@ -13,28 +21,273 @@ fn matcher<'a, 'b>() -> clap::App<'a, 'b> {
     clap::App::new("chip8")
         .version(crate_version!())
         .author(crate_authors!())
+        .usage("chip8 [FLAGS] [OPTIONS] <rom>\n    chip8 [FLAGS] [OPTIONS] <SUBCOMMAND>")
         .arg(
             clap::Arg::with_name("verbosity")
                 .short("v")
                 .multiple(true)
+                .global(true)
                 .help("Increase message verbosity, maximum 4"),
-        ).arg(
-            clap::Arg::with_name("rom")
-                .short("r")
-                .takes_value(true)
-                .help("Path to the ROM to load"),
         ).arg(
             clap::Arg::with_name("quiet")
                 .short("q")
                 .long("quiet")
+                .global(true)
                 .help("Silence all output"),
         ).arg(
             clap::Arg::with_name("timestamp")
                 .short("t")
                 .long("timestamp")
+                .global(true)
                 .help("prepend log lines with a timestamp")
                 .takes_value(true)
                 .possible_values(&["none", "sec", "ms", "ns"]),
+        ).arg(
+            clap::Arg::with_name("rom")
+                .help("Path to a ROM to run, the same as chip8 run <rom>"),
+        ).subcommand(
+            clap::SubCommand::with_name("run")
+                .about("Run a ROM, or compile and run an Octo .8o source")
+                .arg(
+                    clap::Arg::with_name("symbols")
+                        .short("m")
+                        .long("symbols")
+                        .takes_value(true)
+                        .help("Symbol map with breakpoints and monitors for the debugger"),
+                ).arg(
+                    clap::Arg::with_name("profile")
+                        .short("p")
+                        .long("profile")
+                        .takes_value(true)
+                        .help("Profile execution, writing a hot-spot report here and folded stacks beside it"),
+                ).arg(
+                    clap::Arg::with_name("coverage")
+                        .short("c")
+                        .long("coverage")
+                        .takes_value(true)
+                        .help("Record coverage, writing an lcov tracefile here"),
+                ).arg(
+                    clap::Arg::with_name("headless")
+                        .long("headless")
+                        .requires("length")
+                        .help("Run without a window and print the final state as JSON"),
+                ).arg(
+                    clap::Arg::with_name("frames")
+                        .short("f")
+                        .long("frames")
+                        .takes_value(true)
+                        .requires("headless")
+                        .help("Number of 60Hz frames to run headless, by default the whole movie played"),
+                ).arg(
+                    clap::Arg::with_name("keys")
+                        .short("k")
+                        .long("keys")
+                        .takes_value(true)
+                        .requires("headless")
+                        .conflicts_with("play")
+                        .help("Script of FRAME:KEY[:FRAMES] key presses"),
+                ).arg(
+                    clap::Arg::with_name("screen")
+                        .short("s")
+                        .long("screen")
+                        .takes_value(true)
+                        .requires("headless")
+                        .help("Write the final framebuffer here as ASCII art"),
+                ).arg(
+                    clap::Arg::with_name("record")
+                        .long("record")
+                        .takes_value(true)
+                        .conflicts_with("play")
+                        .help("Record the keypad to this movie, appending to it when starting from a save state"),
+                ).arg(
+                    clap::Arg::with_name("play")
+                        .long("play")
+                        .takes_value(true)
+                        .help("Play the keypad back from this movie"),
+                ).arg(
+                    clap::Arg::with_name("seed")
+                        .long("seed")
+                        .takes_value(true)
+                        .conflicts_with("play")
+                        .help("Seed for the random number generator"),
+                ).arg(
+                    clap::Arg::with_name("quirks")
+                        .long("quirks")
+                        .takes_value(true)
+                        .conflicts_with("play")
+                        .help("Quirks profile: default, vip, schip or octo, or auto to detect and remember it"),
+                ).arg(
+                    clap::Arg::with_name("tickrate")
+                        .long("tickrate")
+                        .takes_value(true)
+                        .help("Instructions to run per 60Hz frame"),
+                ).arg(
+                    clap::Arg::with_name("scale")
+                        .long("scale")
+                        .takes_value(true)
+                        .help("Window pixels to a CHIP-8 pixel"),
+                ).arg(
+                    clap::Arg::with_name("frontend")
+                        .long("frontend")
+                        .takes_value(true)
+                        .conflicts_with("headless")
+                        .possible_values(&["sdl", "tui", "braille"])
+                        .help("Where to show the screen: an SDL window, or the terminal in half blocks or braille"),
+                ).arg(
+                    clap::Arg::with_name("mute")
+                        .long("mute")
+                        .help("Don't beep"),
+                ).arg(
+                    clap::Arg::with_name("print-config")
+                        .long("print-config")
+                        .help("Print the settings the ROM would run with, and where each comes from, then stop"),
+                ).arg(
+                    clap::Arg::with_name("options")
+                        .long("options")
+                        .takes_value(true)
+                        .help("Octo options JSON with the tickrate, quirks and colours, defaults to the ROM's name with .json"),
+                ).arg(
+                    clap::Arg::with_name("semihosting")
+                        .long("semihosting")
+                        .help("Treat 0NNN calls as host calls to print, assert and exit with a code"),
+                ).arg(
+                    clap::Arg::with_name("faults")
+                        .long("faults")
+                        .takes_value(true)
+                        .possible_values(&["halt", "skip", "ignore"])
+                        .default_value("halt")
+                        .help("What to do on an unknown opcode or machine code routine"),
+                ).arg(
+                    clap::Arg::with_name("load-state")
+                        .long("load-state")
+                        .takes_value(true)
+                        .help("Start from this save state"),
+                ).arg(
+                    clap::Arg::with_name("save-state")
+                        .long("save-state")
+                        .takes_value(true)
+                        .help("Save state here at the end of a headless run, or on F2"),
+                ).group(clap::ArgGroup::with_name("length").args(&["frames", "play"]))
+                .arg(
+                    clap::Arg::with_name("rom")
+                        .required(true)
+                        .help("Path to the ROM, .8o source or Octo cartridge .gif to run"),
+                ),
+        ).subcommand(
+            clap::SubCommand::with_name("debug")
+                .about("Run a ROM in a window, paused in the debugger before its first instruction")
+                .arg(
+                    clap::Arg::with_name("symbols")
+                        .short("m")
+                        .long("symbols")
+                        .takes_value(true)
+                        .help("Symbol map with breakpoints and monitors"),
+                ).arg(
+                    clap::Arg::with_name("quirks")
+                        .long("quirks")
+                        .takes_value(true)
+                        .help("Quirks profile: default, vip, schip or octo"),
+                ).arg(
+                    clap::Arg::with_name("frontend")
+                        .long("frontend")
+                        .takes_value(true)
+                        .possible_values(&["sdl", "tui", "braille"])
+                        .help("Where to show the screen: an SDL window, or the terminal in half blocks or braille"),
+                ).arg(
+                    clap::Arg::with_name("rom")
+                        .required(true)
+                        .help("Path to the ROM, .8o source or Octo cartridge .gif to debug"),
+                ),
+        ).subcommand(
+            clap::SubCommand::with_name("disasm")
+                .about("Print an annotated listing of a ROM")
+                .arg(
+                    clap::Arg::with_name("syntax")
+                        .short("s")
+                        .long("syntax")
+                        .help("Listing syntax")
+                        .takes_value(true)
+                        .possible_values(&["plain", "octo"])
+                        .default_value("plain"),
+                ).arg(
+                    clap::Arg::with_name("rom")
+                        .required(true)
+                        .help("Path to the ROM to disassemble"),
+                ),
+        ).subcommand(
+            clap::SubCommand::with_name("info")
+                .about("Print what is known about a ROM and the instructions it uses")
+                .arg(
+                    clap::Arg::with_name("rom")
+                        .required(true)
+                        .help("Path to the ROM to inspect"),
+                ),
+        ).subcommand(
+            clap::SubCommand::with_name("detect")
+                .about("Work out the quirks a ROM needs by running it under each profile")
+                .arg(
+                    clap::Arg::with_name("frames")
+                        .short("f")
+                        .long("frames")
+                        .takes_value(true)
+                        .help("Frames to run each profile for, 300 by default"),
+                ).arg(
+                    clap::Arg::with_name("no-save")
+                        .long("no-save")
+                        .help("Only print the scores, without adding the best to the ROM database"),
+                ).arg(
+                    clap::Arg::with_name("rom")
+                        .required(true)
+                        .help("Path to the ROM to try"),
+                ),
+        ).subcommand(
+            clap::SubCommand::with_name("asm")
+                .about("Assemble a source file into a ROM")
+                .arg(
+                    clap::Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .takes_value(true)
+                        .help("Path of the ROM to write, defaults to the source with a .ch8 extension"),
+                ).arg(
+                    clap::Arg::with_name("symbols")
+                        .short("m")
+                        .long("symbols")
+                        .takes_value(true)
+                        .help("Also write a symbol map for the debugger"),
+                ).arg(
+                    clap::Arg::with_name("source")
+                        .required(true)
+                        .help("Path to the assembly source"),
+                ),
+        ).subcommand(
+            clap::SubCommand::with_name("bench")
+                .about("Time how fast a ROM runs headless, flat out")
+                .arg(
+                    clap::Arg::with_name("frames")
+                        .short("f")
+                        .long("frames")
+                        .takes_value(true)
+                        .help("Frames to run, 3600 (a minute of play) by default"),
+                ).arg(
+                    clap::Arg::with_name("quirks")
+                        .long("quirks")
+                        .takes_value(true)
+                        .help("Quirks profile: default, vip, schip or octo"),
+                ).arg(
+                    clap::Arg::with_name("rom")
+                        .required(true)
+                        .help("Path to the ROM, .8o source or Octo cartridge .gif to time"),
+                ),
+        ).subcommand(
+            clap::SubCommand::with_name("test")
+                .about("Check ROM behaviour against scenario files")
+                .arg(
+                    clap::Arg::with_name("scenarios")
+                        .required(true)
+                        .multiple(true)
+                        .help("Paths to the scenario files"),
+                ),
         )
 }
 
@@ -59,13 +312,185 @@ fn parse(matches: &clap::ArgMatches) -> Result<types::Settings, clap::Error> {
             info: None,
         })?,
     };
-    let rom_path = matches.value_of("rom").map(|x| x.to_string());
+    let mut rom_path = matches.value_of("rom").map(|x| x.to_string());
+    let mut symbols_path = None;
+    let mut profile_path = None;
+    let mut coverage_path = None;
+    let mut headless = false;
+    let mut frames = 0;
+    let mut keys_path = None;
+    let mut screen_path = None;
+    let mut record_path = None;
+    let mut play_path = None;
+    let mut seed = None;
+    let mut quirks = None;
+    let mut detect_quirks = false;
+    let mut options_path = None;
+    let mut tickrate = None;
+    let mut scale = None;
+    let mut mute = false;
+    let mut frontend = None;
+    let mut print_config = false;
+    let mut semihosting = false;
+    let mut fault_policy = FaultPolicy::default();
+    let mut load_state_path = None;
+    let mut save_state_path = None;
+    let command = match matches.subcommand() {
+        ("run", Some(sub)) => {
+            rom_path = sub.value_of("rom").map(|x| x.to_string());
+            symbols_path = sub.value_of("symbols").map(|x| x.to_string());
+            profile_path = sub.value_of("profile").map(|x| x.to_string());
+            coverage_path = sub.value_of("coverage").map(|x| x.to_string());
+            headless = sub.is_present("headless");
+            if sub.is_present("frames") {
+                frames = value_t!(sub, "frames", u32)?;
+            }
+            keys_path = sub.value_of("keys").map(|x| x.to_string());
+            screen_path = sub.value_of("screen").map(|x| x.to_string());
+            record_path = sub.value_of("record").map(|x| x.to_string());
+            play_path = sub.value_of("play").map(|x| x.to_string());
+            if sub.is_present("seed") {
+                seed = Some(value_t!(sub, "seed", u64)?);
+            }
+            if sub.value_of("quirks") == Some("auto") {
+                detect_quirks = true;
+            } else if sub.is_present("quirks") {
+                quirks = Some(value_t!(sub, "quirks", Quirks)?);
+            }
+            options_path = sub.value_of("options").map(|x| x.to_string());
+            //the same limits as in config.toml
+            let count = |name: &str, max: u32| {
+                let n = value_t!(sub, name, u32)?;
+                config::count(name, i64::from(n), max).map_err(|message| clap::Error {
+                    message,
+                    kind: clap::ErrorKind::InvalidValue,
+                    info: None,
+                })
+            };
+            if sub.is_present("tickrate") {
+                tickrate = Some(count("tickrate", config::MAX_TICKRATE)?);
+            }
+            if sub.is_present("scale") {
+                scale = Some(count("scale", config::MAX_SCALE)?);
+            }
+            mute = sub.is_present("mute");
+            if sub.is_present("frontend") {
+                frontend = Some(value_t!(sub, "frontend", Frontend)?);
+            }
+            print_config = sub.is_present("print-config");
+            semihosting = sub.is_present("semihosting");
+            fault_policy = value_t!(sub, "faults", FaultPolicy)?;
+            load_state_path = sub.value_of("load-state").map(|x| x.to_string());
+            save_state_path = sub.value_of("save-state").map(|x| x.to_string());
+            types::Command::Run
+        }
+        ("debug", Some(sub)) => {
+            rom_path = sub.value_of("rom").map(|x| x.to_string());
+            symbols_path = sub.value_of("symbols").map(|x| x.to_string());
+            if sub.is_present("quirks") {
+                quirks = Some(value_t!(sub, "quirks", Quirks)?);
+            }
+            if sub.is_present("frontend") {
+                frontend = Some(value_t!(sub, "frontend", Frontend)?);
+            }
+            types::Command::Debug
+        }
+        ("disasm", Some(sub)) => {
+            rom_path = sub.value_of("rom").map(|x| x.to_string());
+            let syntax = value_t!(sub, "syntax", disasm::Syntax)?;
+            types::Command::Disasm(syntax)
+        }
+        ("info", Some(sub)) => {
+            rom_path = sub.value_of("rom").map(|x| x.to_string());
+            types::Command::Info
+        }
+        ("detect", Some(sub)) => {
+            rom_path = sub.value_of("rom").map(|x| x.to_string());
+            let frames = if sub.is_present("frames") {
+                value_t!(sub, "frames", u32)?
+            } else {
+                detect::DEFAULT_FRAMES
+            };
+            types::Command::Detect {
+                frames,
+                save: !sub.is_present("no-save"),
+            }
+        }
+        ("asm", Some(sub)) => {
+            let source = sub.value_of("source").unwrap_or_default().to_string();
+            let output = match sub.value_of("output") {
+                Some(output) => output.to_string(),
+                None => Path::new(&source)
+                    .with_extension("ch8")
+                    .to_string_lossy()
+                    .into_owned(),
+            };
+            types::Command::Asm {
+                source,
+                output,
+                symbols: sub.value_of("symbols").map(|x| x.to_string()),
+            }
+        }
+        ("bench", Some(sub)) => {
+            rom_path = sub.value_of("rom").map(|x| x.to_string());
+            if sub.is_present("quirks") {
+                quirks = Some(value_t!(sub, "quirks", Quirks)?);
+            }
+            let frames = if sub.is_present("frames") {
+                value_t!(sub, "frames", u32)?
+            } else {
+                bench::DEFAULT_FRAMES
+            };
+            types::Command::Bench { frames }
+        }
+        ("test", Some(sub)) => types::Command::Test(
+            sub.values_of("scenarios")
+                .map(|values| values.map(|x| x.to_string()).collect())
+                .unwrap_or_default(),
+        ),
+        _ => {
+            if rom_path.is_none() {
+                Err(clap::Error {
+                    message: format!(
+                        "error: no ROM given\n\n{}\n\nFor more information try --help",
+                        matches.usage()
+                    ),
+                    kind: clap::ErrorKind::MissingRequiredArgument,
+                    info: None,
+                })?
+            }
+            types::Command::Run
+        }
+    };
 
     Ok(types::Settings {
         verbosity,
         quiet,
         timestamp,
         rom_path,
+        symbols_path,
+        profile_path,
+        coverage_path,
+        headless,
+        frames,
+        keys_path,
+        screen_path,
+        record_path,
+        play_path,
+        seed,
+        quirks,
+        detect_quirks,
+        options_path,
+        tickrate,
+        scale,
+        mute,
+        frontend,
+        print_config,
+        semihosting,
+        fault_policy,
+        load_state_path,
+        save_state_path,
+        command,
         ..Default::default()
     })
 }
@@ -73,6 +498,7 @@ fn parse(matches: &clap::ArgMatches) -> Result<types::Settings, clap::Error> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use tui::Glyphs;
 
     #[test]
     fn test_too_much_verbosity() {
@@ -82,16 +508,48 @@ mod tests {
 
     #[test]
     fn test_just_enough_verbosity() {
-        let m = matcher().get_matches_from_safe(vec!["", "-vvv"]).unwrap();
+        let m = matcher()
+            .get_matches_from_safe(vec!["", "-vvv", "roms/MAZE"])
+            .unwrap();
+        let s = parse(&m).unwrap();
+
+        assert_eq!(s.verbosity, 3);
+    }
+
+    #[test]
+    fn test_global_flags() {
+        let m = matcher()
+            .get_matches_from_safe(vec!["", "-q", "info", "-vvv", "-t", "ms", "roms/MAZE"])
+            .unwrap();
         let s = parse(&m).unwrap();
 
         assert_eq!(s.verbosity, 3);
+        assert!(s.quiet);
+        match s.timestamp {
+            types::Timestamp::Microsecond => (),
+            _ => panic!("unexpected parse"),
+        }
+    }
+
+    #[test]
+    fn test_bare_rom() {
+        let m = matcher()
+            .get_matches_from_safe(vec!["", "-v", "roms/MAZE"])
+            .unwrap();
+        let s = parse(&m).unwrap();
+
+        assert_eq!(s.command, types::Command::Run);
+        assert_eq!(s.rom_path, Some("roms/MAZE".into()));
+
+        let m = matcher().get_matches_from_safe(vec!["", "-v"]).unwrap();
+        let e = parse(&m).unwrap_err();
+        assert_eq!(e.kind, clap::ErrorKind::MissingRequiredArgument);
     }
 
     #[test]
     fn test_timestamps() {
         let m = matcher()
-            .get_matches_from_safe(vec!["", "-t", "sec"])
+            .get_matches_from_safe(vec!["", "-t", "sec", "roms/MAZE"])
             .unwrap();
         let s = parse(&m).unwrap();
 
@@ -101,6 +559,344 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_run() {
+        let m = matcher()
+            .get_matches_from_safe(vec!["", "run", "-m", "game.sym", "game.8o"])
+            .unwrap();
+        let s = parse(&m).unwrap();
+
+        assert_eq!(s.command, types::Command::Run);
+        assert_eq!(s.rom_path, Some("game.8o".into()));
+        assert_eq!(s.symbols_path, Some("game.sym".into()));
+        assert_eq!(s.profile_path, None);
+    }
+
+    #[test]
+    fn test_run_profile_and_coverage() {
+        let m = matcher()
+            .get_matches_from_safe(vec![
+                "",
+                "run",
+                "-p",
+                "maze.prof",
+                "-c",
+                "maze.info",
+                "roms/MAZE",
+            ])
+            .unwrap();
+        let s = parse(&m).unwrap();
+
+        assert_eq!(s.profile_path, Some("maze.prof".into()));
+        assert_eq!(s.coverage_path, Some("maze.info".into()));
+    }
+
+    #[test]
+    fn test_run_headless() {
+        let m = matcher()
+            .get_matches_from_safe(vec![
+                "",
+                "run",
+                "--headless",
+                "--frames",
+                "60",
+                "-k",
+                "keys.txt",
+                "roms/MAZE",
+            ])
+            .unwrap();
+        let s = parse(&m).unwrap();
+
+        assert!(s.headless);
+        assert_eq!(s.frames, 60);
+        assert_eq!(s.keys_path, Some("keys.txt".into()));
+        assert_eq!(s.screen_path, None);
+    }
+
+    #[test]
+    fn test_run_headless_needs_frames() {
+        assert!(matcher()
+            .get_matches_from_safe(vec!["", "run", "--headless", "roms/MAZE"])
+            .is_err());
+        let m = matcher()
+            .get_matches_from_safe(vec!["", "run", "--headless", "-f", "lots", "roms/MAZE"])
+            .unwrap();
+        assert!(parse(&m).is_err());
+    }
+
+    #[test]
+    fn test_run_movie() {
+        let m = matcher()
+            .get_matches_from_safe(vec![
+                "",
+                "run",
+                "--record",
+                "maze.movie",
+                "--seed",
+                "7",
+                "--quirks",
+                "vip",
+                "--load-state",
+                "maze.state",
+                "roms/MAZE",
+            ])
+            .unwrap();
+        let s = parse(&m).unwrap();
+
+        assert_eq!(s.record_path, Some("maze.movie".into()));
+        assert_eq!(s.seed, Some(7));
+        assert_eq!(s.quirks, Some(Quirks::vip()));
+        assert_eq!(s.options_path, None);
+        assert_eq!(s.load_state_path, Some("maze.state".into()));
+        assert!(!s.semihosting);
+
+        //a movie played headless runs to its end without --frames
+        let m = matcher()
+            .get_matches_from_safe(vec![
+                "",
+                "run",
+                "--headless",
+                "--play",
+                "maze.movie",
+                "roms/MAZE",
+            ])
+            .unwrap();
+        let s = parse(&m).unwrap();
+        assert_eq!(s.play_path, Some("maze.movie".into()));
+        assert_eq!(s.frames, 0);
+
+        assert!(matcher()
+            .get_matches_from_safe(vec!["", "run", "--play", "a", "--record", "b", "roms/MAZE"])
+            .is_err());
+        let m = matcher()
+            .get_matches_from_safe(vec!["", "run", "--quirks", "bogus", "roms/MAZE"])
+            .unwrap();
+        assert!(parse(&m).is_err());
+    }
+
+    #[test]
+    fn test_run_options() {
+        let m = matcher()
+            .get_matches_from_safe(vec!["", "run", "--options", "maze.json", "roms/MAZE"])
+            .unwrap();
+        assert_eq!(parse(&m).unwrap().options_path, Some("maze.json".into()));
+    }
+
+    #[test]
+    fn test_run_config() {
+        let m = matcher()
+            .get_matches_from_safe(vec![
+                "",
+                "run",
+                "--tickrate",
+                "15",
+                "--scale",
+                "4",
+                "--mute",
+                "--print-config",
+                "roms/MAZE",
+            ])
+            .unwrap();
+        let s = parse(&m).unwrap();
+        assert_eq!(s.tickrate, Some(15));
+        assert_eq!(s.scale, Some(4));
+        assert!(s.mute);
+        assert!(s.print_config);
+        assert_eq!(s.frontend, None);
+
+        let m = matcher()
+            .get_matches_from_safe(vec!["", "run", "--tickrate", "0x10", "roms/MAZE"])
+            .unwrap();
+        assert!(parse(&m).is_err());
+
+        //the same range as config.toml takes, so printed configs load again
+        for args in &[
+            ["--tickrate", "0"],
+            ["--tickrate", "1000001"],
+            ["--scale", "0"],
+            ["--scale", "4294967295"],
+        ] {
+            let m = matcher()
+                .get_matches_from_safe(vec!["", "run", args[0], args[1], "roms/MAZE"])
+                .unwrap();
+            let e = parse(&m).unwrap_err();
+            assert_eq!(e.kind, clap::ErrorKind::InvalidValue);
+        }
+        let m = matcher()
+            .get_matches_from_safe(vec!["", "run", "--scale", "0", "roms/MAZE"])
+            .unwrap();
+        assert_eq!(
+            parse(&m).unwrap_err().message,
+            "scale should be from 1 to 64, not 0"
+        );
+    }
+
+    #[test]
+    fn test_run_frontend() {
+        let m = matcher()
+            .get_matches_from_safe(vec!["", "run", "--frontend", "braille", "roms/MAZE"])
+            .unwrap();
+        let s = parse(&m).unwrap();
+        assert_eq!(s.frontend, Some(Frontend::Tui(Glyphs::Braille)));
+
+        assert!(matcher()
+            .get_matches_from_safe(vec!["", "run", "--frontend", "x11", "roms/MAZE"])
+            .is_err());
+        assert!(matcher()
+            .get_matches_from_safe(vec![
+                "",
+                "run",
+                "--headless",
+                "--frames",
+                "1",
+                "--frontend",
+                "tui",
+                "roms/MAZE"
+            ])
+            .is_err());
+    }
+
+    #[test]
+    fn test_run_semihosting() {
+        let m = matcher()
+            .get_matches_from_safe(vec!["", "run", "--semihosting", "test.8o"])
+            .unwrap();
+        assert!(parse(&m).unwrap().semihosting);
+    }
+
+    #[test]
+    fn test_run_faults() {
+        let m = matcher()
+            .get_matches_from_safe(vec!["", "run", "roms/MAZE"])
+            .unwrap();
+        assert_eq!(parse(&m).unwrap().fault_policy, FaultPolicy::Halt);
+        let m = matcher()
+            .get_matches_from_safe(vec!["", "run", "--faults", "skip", "roms/MAZE"])
+            .unwrap();
+        assert_eq!(parse(&m).unwrap().fault_policy, FaultPolicy::Skip);
+    }
+
+    #[test]
+    fn test_debug() {
+        let m = matcher()
+            .get_matches_from_safe(vec![
+                "",
+                "debug",
+                "-m",
+                "game.sym",
+                "--quirks",
+                "vip",
+                "--frontend",
+                "tui",
+                "game",
+            ])
+            .unwrap();
+        let s = parse(&m).unwrap();
+
+        assert_eq!(s.command, types::Command::Debug);
+        assert_eq!(s.rom_path, Some("game".into()));
+        assert_eq!(s.symbols_path, Some("game.sym".into()));
+        assert_eq!(s.quirks, Some(Quirks::vip()));
+        assert_eq!(s.frontend, Some(Frontend::Tui(Glyphs::HalfBlocks)));
+        assert!(!s.headless);
+    }
+
+    #[test]
+    fn test_disasm() {
+        let m = matcher()
+            .get_matches_from_safe(vec!["", "disasm", "-s", "octo", "roms/MAZE"])
+            .unwrap();
+        let s = parse(&m).unwrap();
+
+        assert_eq!(s.command, types::Command::Disasm(disasm::Syntax::Octo));
+        assert_eq!(s.rom_path, Some("roms/MAZE".into()));
+    }
+
+    #[test]
+    fn test_info() {
+        let m = matcher()
+            .get_matches_from_safe(vec!["", "info", "roms/MAZE"])
+            .unwrap();
+        let s = parse(&m).unwrap();
+
+        assert_eq!(s.command, types::Command::Info);
+        assert_eq!(s.rom_path, Some("roms/MAZE".into()));
+    }
+
+    #[test]
+    fn test_detect() {
+        let m = matcher()
+            .get_matches_from_safe(vec!["", "detect", "-f", "60", "--no-save", "roms/MAZE"])
+            .unwrap();
+        let s = parse(&m).unwrap();
+        assert_eq!(
+            s.command,
+            types::Command::Detect {
+                frames: 60,
+                save: false
+            }
+        );
+        assert_eq!(s.rom_path, Some("roms/MAZE".into()));
+
+        let m = matcher()
+            .get_matches_from_safe(vec!["", "run", "--quirks", "auto", "roms/MAZE"])
+            .unwrap();
+        let s = parse(&m).unwrap();
+        assert!(s.detect_quirks);
+        assert_eq!(s.quirks, None);
+    }
+
+    #[test]
+    fn test_asm() {
+        let m = matcher()
+            .get_matches_from_safe(vec!["", "asm", "-m", "game.sym", "game.s"])
+            .unwrap();
+        let s = parse(&m).unwrap();
+
+        assert_eq!(
+            s.command,
+            types::Command::Asm {
+                source: "game.s".into(),
+                output: "game.ch8".into(),
+                symbols: Some("game.sym".into()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_test() {
+        let m = matcher()
+            .get_matches_from_safe(vec!["", "test", "a.scenario", "b.scenario"])
+            .unwrap();
+        let s = parse(&m).unwrap();
+
+        assert_eq!(
+            s.command,
+            types::Command::Test(vec!["a.scenario".into(), "b.scenario".into()])
+        );
+    }
+
+    #[test]
+    fn test_bench() {
+        let m = matcher()
+            .get_matches_from_safe(vec!["", "bench", "roms/MAZE"])
+            .unwrap();
+        let s = parse(&m).unwrap();
+        assert_eq!(
+            s.command,
+            types::Command::Bench {
+                frames: bench::DEFAULT_FRAMES
+            }
+        );
+
+        let m = matcher()
+            .get_matches_from_safe(vec!["", "bench", "-f", "60", "roms/MAZE"])
+            .unwrap();
+        let s = parse(&m).unwrap();
+        assert_eq!(s.command, types::Command::Bench { frames: 60 });
+        assert_eq!(s.rom_path, Some("roms/MAZE".into()));
+    }
+
     #[test]
     fn test_bogus_timestamps() {
         assert!(
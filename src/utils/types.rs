@@ -1,6 +1,39 @@
+use disasm;
+use fault::FaultPolicy;
+use quirks::Quirks;
+use tui::Frontend;
 //reexport Timestamp, so other modules don't need to use stderrlog
 pub use stderrlog::Timestamp;
 
+///What the binary has been asked to do
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    ///run the ROM, or compile and run an Octo `.8o` source or cartridge,
+    ///in a window
+    Run,
+    ///run the ROM in a window, paused before its first instruction
+    Debug,
+    ///print a listing of the ROM
+    Disasm(disasm::Syntax),
+    ///print the size, hashes and database entry of the ROM, and the
+    ///instructions it uses
+    Info,
+    ///run the ROM under each quirks profile for `frames` frames and
+    ///recommend one, adding it to the user's ROM database if `save`
+    Detect { frames: u32, save: bool },
+    ///assemble `source` into the ROM `output`
+    Asm {
+        source: String,
+        output: String,
+        symbols: Option<String>,
+    },
+    ///check each of the scenario files
+    Test(Vec<String>),
+    ///run the ROM headless for `frames` frames as fast as possible, and
+    ///report the speed
+    Bench { frames: u32 },
+}
+
 #[derive(Debug)]
 pub struct Settings {
     pub verbosity: usize,
@@ -8,6 +41,51 @@ pub struct Settings {
     pub timestamp: Timestamp,
     pub module_path: Option<String>,
     pub rom_path: Option<String>,
+    ///symbol map with breakpoints for the debugger
+    pub symbols_path: Option<String>,
+    ///where to write the profiler's report, the folded stacks go next to it
+    pub profile_path: Option<String>,
+    ///where to write lcov coverage, an annotated listing goes next to it
+    ///when there are no source lines
+    pub coverage_path: Option<String>,
+    ///run without a window for `frames` frames, then print the state
+    pub headless: bool,
+    pub frames: u32,
+    ///script of key presses for headless runs
+    pub keys_path: Option<String>,
+    ///where to write the final framebuffer as ASCII art
+    pub screen_path: Option<String>,
+    ///movie to record the keypad to, appended to when starting from a state
+    pub record_path: Option<String>,
+    ///movie to play the keypad back from
+    pub play_path: Option<String>,
+    ///seed for the random number generator, random when not given
+    pub seed: Option<u64>,
+    ///quirks profile to use rather than the default or the ROM's options
+    pub quirks: Option<Quirks>,
+    ///pick the quirks by trying each profile, see `detect`
+    pub detect_quirks: bool,
+    ///Octo options file, rather than the one next to the ROM
+    pub options_path: Option<String>,
+    ///instructions per frame, over the config file and the ROM's settings
+    pub tickrate: Option<u32>,
+    ///window pixels to a CHIP-8 pixel, over the config file
+    pub scale: Option<u32>,
+    ///don't beep, whatever the config file says
+    pub mute: bool,
+    ///where to show the screen, over the config file
+    pub frontend: Option<Frontend>,
+    ///print the settings a run would use, rather than running
+    pub print_config: bool,
+    ///treat some `0NNN` calls as host calls, see `semihost`
+    pub semihosting: bool,
+    ///what to do when the ROM faults
+    pub fault_policy: FaultPolicy,
+    ///save state to start from
+    pub load_state_path: Option<String>,
+    ///where to save state, at the end of a headless run or on F2
+    pub save_state_path: Option<String>,
+    pub command: Command,
 }
 
 impl Default for Settings {
@@ -18,6 +96,29 @@ impl Default for Settings {
             timestamp: Timestamp::Off,
             module_path: None,
             rom_path: None,
+            symbols_path: None,
+            profile_path: None,
+            coverage_path: None,
+            headless: false,
+            frames: 0,
+            keys_path: None,
+            screen_path: None,
+            record_path: None,
+            play_path: None,
+            seed: None,
+            quirks: None,
+            detect_quirks: false,
+            options_path: None,
+            tickrate: None,
+            scale: None,
+            mute: false,
+            frontend: None,
+            print_config: false,
+            semihosting: false,
+            fault_policy: FaultPolicy::default(),
+            load_state_path: None,
+            save_state_path: None,
+            command: Command::Run,
         }
     }
 }
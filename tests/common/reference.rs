@@ -0,0 +1,350 @@
+//! A deliberately simple second implementation of the instruction set
+//!
+//! `step` is a pure function from a machine state and an opcode to the next
+//! state, written straight from the instruction descriptions and sharing
+//! nothing with `Cpu` but the random number generator, so the two can be
+//! checked against each other. Faults are `Cpu`'s business: for `0NNN`
+//! calls, opcodes that aren't instructions, `rts` with nothing on the stack
+//! and `jsr` with it full, `step` gives `None`.
+//!
+//! PC and I are 12 bits, and addresses are taken modulo the 4K of memory.
+
+use chip8_tismith::cpu::{Cpu, SCREEN_HEIGHT, SCREEN_WIDTH};
+use chip8_tismith::quirks::Quirks;
+use std::fmt;
+
+const MEMORY_SIZE: usize = 4096;
+const ADDRESS_MASK: u16 = 0x0FFF;
+
+///Everything an instruction can read or change
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct State {
+    pub v: [u8; 16],
+    pub i: u16,
+    pub pc: u16,
+    ///innermost last
+    pub stack: Vec<u16>,
+    pub delay: u8,
+    pub sound: u8,
+    ///bit N set while key N is down
+    pub keys: u16,
+    ///row by row, `SCREEN_WIDTH` pixels to a row
+    pub screen: Vec<bool>,
+    pub memory: Vec<u8>,
+    ///the xorshift64* state behind CXNN
+    pub rng: u64,
+}
+
+impl State {
+    ///a state with everything zero but the PC, at the start of a ROM
+    pub fn new() -> Self {
+        State {
+            v: [0; 16],
+            i: 0,
+            pc: 0x200,
+            stack: Vec::new(),
+            delay: 0,
+            sound: 0,
+            keys: 0,
+            screen: vec![false; SCREEN_WIDTH * SCREEN_HEIGHT],
+            memory: vec![0; MEMORY_SIZE],
+            rng: 1,
+        }
+    }
+
+    pub fn from_cpu(cpu: &Cpu) -> Self {
+        State {
+            v: *cpu.registers(),
+            i: cpu.i(),
+            pc: cpu.pc(),
+            stack: cpu.stack().to_vec(),
+            delay: cpu.delay(),
+            sound: cpu.sound(),
+            keys: cpu.keys(),
+            screen: cpu.screen().to_vec(),
+            memory: cpu.memory().to_vec(),
+            rng: cpu.rng_state(),
+        }
+    }
+
+    ///a cpu in this state, which must have a non-zero `rng` and at most
+    ///`MAX_STACK_DEPTH` addresses on the stack
+    pub fn to_cpu(&self, quirks: Quirks) -> Cpu {
+        let mut cpu = Cpu::with_quirks(quirks);
+        *cpu.registers_mut() = self.v;
+        cpu.set_i(self.i);
+        cpu.set_pc(self.pc);
+        cpu.set_stack(&self.stack);
+        cpu.set_delay(self.delay);
+        cpu.set_sound(self.sound);
+        cpu.set_keys(self.keys);
+        cpu.screen_mut().copy_from_slice(&self.screen);
+        cpu.memory_mut().copy_from_slice(&self.memory);
+        cpu.seed(self.rng);
+        cpu
+    }
+
+    ///the byte at `address`, wrapping around memory
+    fn mem(&self, address: u16) -> u8 {
+        self.memory[usize::from(address) % MEMORY_SIZE]
+    }
+
+    fn set_mem(&mut self, address: u16, value: u8) {
+        self.memory[usize::from(address) % MEMORY_SIZE] = value;
+    }
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State::new()
+    }
+}
+
+///Only what differs from `State::new`, as a state is mostly memory and
+///screen
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "pc=0x{:03X} i=0x{:03X}", self.pc, self.i)?;
+        for (x, value) in self.v.iter().enumerate().filter(|&(_, &v)| v != 0) {
+            write!(f, " v{:x}=0x{:02X}", x, value)?;
+        }
+        if !self.stack.is_empty() {
+            let stack: Vec<String> = self.stack.iter().map(|a| format!("0x{:03X}", a)).collect();
+            write!(f, " stack=[{}]", stack.join(","))?;
+        }
+        if self.delay != 0 || self.sound != 0 {
+            write!(f, " delay={} sound={}", self.delay, self.sound)?;
+        }
+        if self.keys != 0 {
+            write!(f, " keys=0x{:04X}", self.keys)?;
+        }
+        if self.rng != 1 {
+            write!(f, " rng=0x{:016X}", self.rng)?;
+        }
+        let lit: Vec<String> = self
+            .screen
+            .iter()
+            .enumerate()
+            .filter(|&(_, &pixel)| pixel)
+            .map(|(n, _)| format!("({},{})", n % SCREEN_WIDTH, n / SCREEN_WIDTH))
+            .collect();
+        if !lit.is_empty() {
+            write!(f, " lit={}", lit.join(""))?;
+        }
+        let memory: Vec<String> = self
+            .memory
+            .iter()
+            .enumerate()
+            .filter(|&(_, &byte)| byte != 0)
+            .map(|(address, byte)| format!("{:03X}:{:02X}", address, byte))
+            .collect();
+        if !memory.is_empty() {
+            write!(f, " mem=[{}]", memory.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+///the state after running `opcode` in `state`, or `None` for a fault
+pub fn step(state: &State, opcode: u16, quirks: Quirks) -> Option<State> {
+    let x = usize::from(opcode >> 8 & 0xF);
+    let y = usize::from(opcode >> 4 & 0xF);
+    let n = opcode & 0xF;
+    let nn = (opcode & 0xFF) as u8;
+    let nnn = opcode & 0xFFF;
+
+    let mut s = state.clone();
+    let (vx, vy) = (s.v[x], s.v[y]);
+    let next = (s.pc + 2) & ADDRESS_MASK;
+    let skip = (s.pc + 4) & ADDRESS_MASK;
+    s.pc = next;
+
+    match opcode >> 12 {
+        0x0 if opcode == 0x00E0 => s.screen.iter_mut().for_each(|p| *p = false),
+        0x0 if opcode == 0x00EE => s.pc = s.stack.pop()?,
+        0x0 => return None,
+        0x1 => s.pc = nnn,
+        0x2 => {
+            if s.stack.len() >= usize::from(quirks.stack_depth) {
+                return None;
+            }
+            s.stack.push(next);
+            s.pc = nnn;
+        }
+        0x3 if vx == nn => s.pc = skip,
+        0x4 if vx != nn => s.pc = skip,
+        0x5 if n == 0 && vx == vy => s.pc = skip,
+        0x3 | 0x4 => {}
+        0x5 if n == 0 => {}
+        0x6 => s.v[x] = nn,
+        0x7 => s.v[x] = vx.wrapping_add(nn),
+        0x8 => {
+            let source = if quirks.shift { vx } else { vy };
+            let (result, flag) = match n {
+                0x0 => {
+                    s.v[x] = vy;
+                    return Some(s);
+                }
+                0x1 => {
+                    s.v[x] = vx | vy;
+                    return Some(s);
+                }
+                0x2 => {
+                    s.v[x] = vx & vy;
+                    return Some(s);
+                }
+                0x3 => {
+                    s.v[x] = vx ^ vy;
+                    return Some(s);
+                }
+                0x4 => (vx.wrapping_add(vy), u16::from(vx) + u16::from(vy) > 0xFF),
+                0x5 => (vx.wrapping_sub(vy), vx >= vy),
+                0x6 => (source >> 1, source & 1 == 1),
+                0x7 => (vy.wrapping_sub(vx), vy >= vx),
+                0xE => (source << 1, source >= 0x80),
+                _ => return None,
+            };
+            //whichever is written last wins when X is F
+            if quirks.vf_order {
+                s.v[0xF] = flag as u8;
+                s.v[x] = result;
+            } else {
+                s.v[x] = result;
+                s.v[0xF] = flag as u8;
+            }
+        }
+        0x9 if n == 0 => {
+            if vx != vy {
+                s.pc = skip;
+            }
+        }
+        0xA => s.i = nnn,
+        0xB => {
+            let offset = if quirks.jump { vx } else { s.v[0] };
+            s.pc = (nnn + u16::from(offset)) & ADDRESS_MASK;
+        }
+        0xC => {
+            s.rng ^= s.rng >> 12;
+            s.rng ^= s.rng << 25;
+            s.rng ^= s.rng >> 27;
+            let random = (s.rng.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8;
+            s.v[x] = random & nn;
+        }
+        0xD => draw(&mut s, vx, vy, n, quirks),
+        0xE if nn == 0x9E => {
+            if s.keys & 1 << (vx & 0xF) != 0 {
+                s.pc = skip;
+            }
+        }
+        0xE if nn == 0xA1 => {
+            if s.keys & 1 << (vx & 0xF) == 0 {
+                s.pc = skip;
+            }
+        }
+        0xF => match nn {
+            0x07 => s.v[x] = s.delay,
+            0x0A => match (0..16).find(|key| s.keys & 1 << key != 0) {
+                Some(key) => s.v[x] = key as u8,
+                None => s.pc = state.pc,
+            },
+            0x15 => s.delay = vx,
+            0x18 => s.sound = vx,
+            0x1E => {
+                let sum = s.i + u16::from(vx);
+                s.i = sum & ADDRESS_MASK;
+                s.v[0xF] = (sum > ADDRESS_MASK) as u8;
+            }
+            0x29 => s.i = 0x50 + 5 * u16::from(vx & 0xF),
+            0x33 => {
+                let i = s.i;
+                s.set_mem(i, vx / 100);
+                s.set_mem(i.wrapping_add(1), vx / 10 % 10);
+                s.set_mem(i.wrapping_add(2), vx % 10);
+            }
+            0x55 => {
+                for r in 0..=x {
+                    let address = s.i.wrapping_add(r as u16);
+                    let value = s.v[r];
+                    s.set_mem(address, value);
+                }
+                if !quirks.load_store {
+                    s.i = (s.i + x as u16 + 1) & ADDRESS_MASK;
+                }
+            }
+            0x65 => {
+                for r in 0..=x {
+                    s.v[r] = s.mem(s.i.wrapping_add(r as u16));
+                }
+                if !quirks.load_store {
+                    s.i = (s.i + x as u16 + 1) & ADDRESS_MASK;
+                }
+            }
+            _ => return None,
+        },
+        _ => return None,
+    }
+    Some(s)
+}
+
+///DXYN, XORing N rows of 8 pixels from I onto the screen at (VX, VY)
+fn draw(s: &mut State, vx: u8, vy: u8, rows: u16, quirks: Quirks) {
+    let left = usize::from(vx) % SCREEN_WIDTH;
+    let top = usize::from(vy) % SCREEN_HEIGHT;
+    let mut collision = false;
+    for row in 0..usize::from(rows) {
+        let bits = s.mem(s.i.wrapping_add(row as u16));
+        for column in 0..8 {
+            if bits & 0x80 >> column == 0 {
+                continue;
+            }
+            let (x, y) = (left + column, top + row);
+            if quirks.clip && (x >= SCREEN_WIDTH || y >= SCREEN_HEIGHT) {
+                continue;
+            }
+            let pixel = &mut s.screen[(y % SCREEN_HEIGHT) * SCREEN_WIDTH + x % SCREEN_WIDTH];
+            collision |= *pixel;
+            *pixel = !*pixel;
+        }
+    }
+    s.v[0xF] = collision as u8;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_step() {
+        let mut state = State::new();
+        state.v[1] = 3;
+        state.i = 0x300;
+        //str v0-v1, stores two registers whatever v1 holds
+        let after = step(&state, 0xF155, Quirks::default()).unwrap();
+        assert_eq!(&after.memory[0x300..0x303], &[0, 3, 0]);
+        assert_eq!((after.i, after.pc), (0x302, 0x202));
+        assert_eq!(step(&state, 0xF155, Quirks::schip()).unwrap().i, 0x300);
+
+        //sub v1,v1 doesn't borrow
+        let after = step(&state, 0x8115, Quirks::default()).unwrap();
+        assert_eq!((after.v[1], after.v[0xF]), (0, 1));
+
+        assert_eq!(step(&state, 0x00EE, Quirks::default()), None);
+        assert_eq!(step(&state, 0x0123, Quirks::default()), None);
+        assert_eq!(step(&state, 0x5121, Quirks::default()), None);
+    }
+
+    #[test]
+    fn test_cpu_round_trip() {
+        let mut state = State::new();
+        state.v[4] = 9;
+        state.stack.push(0x222);
+        state.screen[7] = true;
+        state.memory[0x200] = 0x12;
+        state.rng = 77;
+        assert_eq!(State::from_cpu(&state.to_cpu(Quirks::default())), state);
+        assert_eq!(
+            state.to_string(),
+            "pc=0x200 i=0x000 v4=0x09 stack=[0x222] rng=0x000000000000004D lit=(7,0) mem=[200:12]"
+        );
+    }
+}
@@ -0,0 +1,172 @@
+//! Checks `Cpu::tick` against the reference interpreter
+//!
+//! Random states and opcodes are run through both for each quirks profile.
+//! A divergence is shrunk, by zeroing as much of the state and opcode as
+//! still diverges, before being reported. Set `CHIP8_DIFF_SEED` to vary the
+//! cases from the fixed default.
+
+extern crate chip8_tismith;
+extern crate rand;
+
+#[path = "common/reference.rs"]
+mod reference;
+
+use chip8_tismith::cpu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use chip8_tismith::quirks::Quirks;
+use rand::prng::XorShiftRng;
+use rand::{Rng, SeedableRng};
+use reference::State;
+use std::env;
+
+const CASES: usize = 5_000;
+///right up to the end of memory, where addresses wrap
+const MAX_I: u16 = 0xFFF;
+const MAX_PC: u16 = 0xFFE;
+
+const PROFILES: &[&str] = &["default", "vip", "schip", "octo"];
+
+fn random_state(rng: &mut XorShiftRng) -> State {
+    let mut state = State::new();
+    rng.fill(&mut state.v[..]);
+    state.i = rng.gen_range(0, MAX_I + 1);
+    state.pc = rng.gen_range(0x100, MAX_PC / 2 + 1) * 2;
+    //sometimes full, for whichever profile has the smaller stack
+    state.stack = (0..rng.gen_range(0, 17))
+        .map(|_| rng.gen_range(0x100, 0x800) * 2)
+        .collect();
+    state.delay = rng.gen();
+    state.sound = rng.gen();
+    //few keys down, so FX0A sometimes waits
+    state.keys = rng.gen::<u16>() & rng.gen::<u16>() & rng.gen::<u16>();
+    for pixel in state.screen.iter_mut() {
+        *pixel = rng.gen_range(0, 8) == 0;
+    }
+    rng.fill(&mut state.memory[..]);
+    state.rng = rng.gen::<u64>() | 1;
+    state
+}
+
+///put `opcode` at the PC, where the cpu fetches it from
+fn with_opcode(state: &State, opcode: u16) -> State {
+    let mut state = state.clone();
+    let pc = usize::from(state.pc);
+    state.memory[pc] = (opcode >> 8) as u8;
+    state.memory[pc + 1] = opcode as u8;
+    state
+}
+
+///the reference and cpu states after `opcode`, if the reference covers it
+///and they differ
+fn diverges(state: &State, opcode: u16, quirks: Quirks) -> Option<(State, State)> {
+    let state = with_opcode(state, opcode);
+    let expected = reference::step(&state, opcode, quirks)?;
+    let mut cpu = state.to_cpu(quirks);
+    cpu.tick();
+    let actual = State::from_cpu(&cpu);
+    if actual == expected {
+        None
+    } else {
+        Some((expected, actual))
+    }
+}
+
+///simpler versions of a failing case to try
+fn simpler(state: &State, opcode: u16) -> Vec<(State, u16)> {
+    let mut candidates = Vec::new();
+    for shift in &[0, 4, 8] {
+        let nibble = opcode >> shift & 0xF;
+        for smaller in &[0, nibble / 2, nibble.saturating_sub(1)] {
+            if *smaller < nibble {
+                let opcode = opcode & !(0xF << shift) | smaller << shift;
+                candidates.push((state.clone(), opcode));
+            }
+        }
+    }
+    let mut push = |change: &dyn Fn(&mut State)| {
+        let mut simpler = state.clone();
+        change(&mut simpler);
+        if simpler != *state {
+            candidates.push((simpler, opcode));
+        }
+    };
+    push(&|s| s.memory.iter_mut().for_each(|b| *b = 0));
+    push(&|s| s.screen.iter_mut().for_each(|p| *p = false));
+    push(&|s| s.stack.clear());
+    push(&|s| s.keys = 0);
+    push(&|s| s.rng = 1);
+    push(&|s| s.pc = 0x200);
+    push(&|s| s.i = 0);
+    push(&|s| s.i /= 2);
+    push(&|s| {
+        s.delay = 0;
+        s.sound = 0
+    });
+    for x in 0..16 {
+        push(&|s| s.v[x] = 0);
+        push(&|s| s.v[x] /= 2);
+    }
+    for chunk in 0..16 {
+        push(&|s| {
+            for byte in &mut s.memory[chunk * 256..(chunk + 1) * 256] {
+                *byte = 0;
+            }
+        });
+    }
+    for row in 0..SCREEN_HEIGHT {
+        push(&|s| {
+            for pixel in &mut s.screen[row * SCREEN_WIDTH..(row + 1) * SCREEN_WIDTH] {
+                *pixel = false;
+            }
+        });
+    }
+    candidates
+}
+
+///the smallest case still diverging that zeroing and halving can find
+fn shrink(mut state: State, mut opcode: u16, quirks: Quirks) -> (State, u16) {
+    'shrinking: loop {
+        for (simpler, simpler_opcode) in simpler(&state, opcode) {
+            if diverges(&simpler, simpler_opcode, quirks).is_some() {
+                state = simpler;
+                opcode = simpler_opcode;
+                continue 'shrinking;
+            }
+        }
+        return (state, opcode);
+    }
+}
+
+#[test]
+fn test_cpu_matches_reference() {
+    let seed: u64 = env::var("CHIP8_DIFF_SEED")
+        .ok()
+        .and_then(|seed| seed.parse().ok())
+        .unwrap_or(0xC8);
+    let mut failures = Vec::new();
+    for profile in PROFILES {
+        let quirks: Quirks = profile.parse().unwrap();
+        let mut bytes = [0; 16];
+        bytes[..8].copy_from_slice(&seed.to_le_bytes());
+        bytes[8] = 1;
+        let mut rng = XorShiftRng::from_seed(bytes);
+        for _ in 0..CASES {
+            let state = random_state(&mut rng);
+            let opcode = rng.gen();
+            if diverges(&state, opcode, quirks).is_none() {
+                continue;
+            }
+            let (state, opcode) = shrink(state, opcode, quirks);
+            let (expected, actual) = diverges(&state, opcode, quirks).unwrap();
+            failures.push(format!(
+                "{} quirks, opcode 0x{:04X} from\n  {}\nreference gives\n  {}\ncpu gives\n  {}",
+                profile,
+                opcode,
+                with_opcode(&state, opcode),
+                expected,
+                actual
+            ));
+            break;
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n\n"));
+}
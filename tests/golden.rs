@@ -0,0 +1,80 @@
+//! Golden-frame regression tests for the bundled ROMs
+//!
+//! Each ROM in `roms/` is run headless with a fixed seed and scripted input,
+//! and the final framebuffer compared with `tests/golden/<ROM>.txt`. After a
+//! deliberate change to the output, rewrite the snapshots with
+//!
+//! ```text
+//! CHIP8_BLESS=1 cargo test --test golden
+//! ```
+//!
+//! and review the diff before committing them.
+
+extern crate chip8_tismith;
+
+use chip8_tismith::cpu::Cpu;
+use chip8_tismith::headless;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+const SEED: u64 = 0xC8;
+const FRAMES: u32 = 300;
+///moves about and presses the keys most of the games start or fire with
+const DEFAULT_KEYS: &str = "30:5:5 60:4:20 90:6:20 120:5:5 150:8:10 180:2:10 210:f:5 240:1:5";
+///games that need something else to get going
+const KEYS: &[(&str, &str)] = &[
+    ("TETRIS", "30:4:10 60:6:10 90:5:5 120:7:40"),
+    ("TICTAC", "30:1:2 60:5:2 90:9:2 120:3:2 150:7:2"),
+];
+
+fn frame(rom: &[u8], keys: &str) -> String {
+    let mut cpu = Cpu::new();
+    cpu.seed(SEED);
+    cpu.load_rom(rom).unwrap();
+    let keys = headless::parse_keys(keys).unwrap();
+    headless::run(&mut cpu, FRAMES, &keys, |cpu| cpu.tick());
+    headless::screen_ascii(&cpu)
+}
+
+#[test]
+fn test_golden_frames() {
+    let bless = env::var_os("CHIP8_BLESS").is_some();
+    let golden = Path::new("tests/golden");
+    let mut roms: Vec<_> = fs::read_dir("roms")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    roms.sort();
+
+    let mut failures = Vec::new();
+    for path in roms {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let keys = KEYS
+            .iter()
+            .find(|&&(rom, _)| rom == name)
+            .map_or(DEFAULT_KEYS, |&(_, keys)| keys);
+        let actual = frame(&fs::read(&path).unwrap(), keys);
+        let snapshot = golden.join(format!("{}.txt", name));
+        if bless {
+            fs::write(&snapshot, &actual).unwrap();
+            continue;
+        }
+        match fs::read_to_string(&snapshot) {
+            Ok(ref expected) if *expected == actual => {}
+            Ok(_) => failures.push(format!("{} differs from {}", name, snapshot.display())),
+            Err(_) => failures.push(format!("{} has no snapshot", name)),
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{}\nrerun with CHIP8_BLESS=1 to accept the new frames",
+        failures.join("\n")
+    );
+}
+
+#[test]
+fn test_frames_are_reproducible() {
+    let rom = fs::read("roms/MAZE").unwrap();
+    assert_eq!(frame(&rom, ""), frame(&rom, ""));
+}
@@ -17,3 +17,311 @@ fn test_help() {
         .contains("USAGE")
         .unwrap();
 }
+
+#[test]
+fn test_no_rom() {
+    //test that with no ROM and no subcommand there's nothing to run
+    let bin: &str = &format!("{}/target/debug/chip8", get_cwd());
+    assert_cli::Assert::command(&[bin])
+        .with_args(&["-v"])
+        .fails()
+        .stderr()
+        .contains("no ROM given")
+        .unwrap();
+}
+
+#[test]
+fn test_bench() {
+    //test that bench runs the frames asked for and reports the speed
+    let bin: &str = &format!("{}/target/debug/chip8", get_cwd());
+    assert_cli::Assert::command(&[bin])
+        .with_args(&["bench", "-f", "60", "roms/MAZE"])
+        .stdout()
+        .contains("60 frames, 6000 instructions in")
+        .stdout()
+        .contains("x real time")
+        .unwrap();
+}
+
+#[test]
+fn test_tui_needs_terminal() {
+    //test that the terminal frontend won't start on a pipe
+    let bin: &str = &format!("{}/target/debug/chip8", get_cwd());
+    assert_cli::Assert::command(&[bin])
+        .with_args(&["run", "--frontend", "tui", "roms/MAZE"])
+        .stdin("")
+        .fails()
+        .stderr()
+        .contains("needs stdin to be a terminal")
+        .unwrap();
+}
+
+#[test]
+fn test_disasm() {
+    //test that disasm labels the MAZE jump targets and sprites
+    let bin: &str = &format!("{}/target/debug/chip8", get_cwd());
+    assert_cli::Assert::command(&[bin])
+        .with_args(&["disasm", "roms/MAZE"])
+        .stdout()
+        .contains("jmp label_200")
+        .stdout()
+        .contains("sprite_21A:")
+        .unwrap();
+}
+
+#[test]
+fn test_headless() {
+    //test that a headless run prints the final state as JSON
+    let bin: &str = &format!("{}/target/debug/chip8", get_cwd());
+    assert_cli::Assert::command(&[bin])
+        .with_args(&["run", "--headless", "--frames", "10", "roms/MAZE"])
+        .stdout()
+        .contains("{\"frames\":10,\"pc\":")
+        .stdout()
+        .contains("\"stack\":[],")
+        .stdout()
+        .contains("\"screen_hash\":\"")
+        .unwrap();
+}
+
+#[test]
+fn test_scenario() {
+    //test that the test subcommand checks a scenario file
+    let bin: &str = &format!("{}/target/debug/chip8", get_cwd());
+    assert_cli::Assert::command(&[bin])
+        .with_args(&["test", "tests/scenarios/maze.scenario"])
+        .stdout()
+        .contains("ok tests/scenarios/maze.scenario")
+        .unwrap();
+}
+
+#[test]
+fn test_movie() {
+    //test that a recorded movie plays back headless to its end
+    let bin: &str = &format!("{}/target/debug/chip8", get_cwd());
+    let movie = env::temp_dir().join("chip8-test.movie");
+    let movie: &str = movie.to_str().unwrap();
+    assert_cli::Assert::command(&[bin])
+        .with_args(&[
+            "run",
+            "--headless",
+            "--frames",
+            "30",
+            "--seed",
+            "7",
+            "--record",
+            movie,
+            "roms/MAZE",
+        ])
+        .unwrap();
+    assert_cli::Assert::command(&[bin])
+        .with_args(&["run", "--headless", "--play", movie, "roms/MAZE"])
+        .stdout()
+        .contains("{\"frames\":30,")
+        .unwrap();
+    assert_cli::Assert::command(&[bin])
+        .with_args(&["run", "--headless", "--play", movie, "roms/PONG"])
+        .fails()
+        .unwrap();
+}
+
+#[test]
+fn test_semihosting_exit_code() {
+    //test that a ROM's semihosted exit code is the emulator's, and that the
+    //state is reported for the frame it stopped in, not the frames asked for
+    let bin: &str = &format!("{}/target/debug/chip8", get_cwd());
+    let source = env::temp_dir().join("chip8-exit.8o");
+    std::fs::write(&source, "v0 := 0x21 0x01 0x10 0x03 0x07").unwrap();
+    assert_cli::Assert::command(&[bin])
+        .with_args(&[
+            "run",
+            "--semihosting",
+            "--headless",
+            "--frames",
+            "600",
+            source.to_str().unwrap(),
+        ])
+        .fails_with(7)
+        .stdout()
+        .contains("!")
+        .stdout()
+        .contains("{\"frames\":0,")
+        .unwrap();
+}
+
+#[test]
+fn test_octo_comparisons() {
+    //test that Octo sources run with the quirks their comparisons rely on
+    let bin: &str = &format!("{}/target/debug/chip8", get_cwd());
+    assert_cli::Assert::command(&[bin])
+        .with_args(&[
+            "run",
+            "--semihosting",
+            "--headless",
+            "--frames",
+            "10",
+            "tests/roms/comparisons.8o",
+        ])
+        .succeeds()
+        .unwrap();
+}
+
+#[test]
+fn test_unknown_rom_format() {
+    //test that a file that is no kind of ROM is named in the error
+    let bin: &str = &format!("{}/target/debug/chip8", get_cwd());
+    assert_cli::Assert::command(&[bin])
+        .with_args(&["run", "--headless", "--frames", "1", "Cargo.toml"])
+        .fails()
+        .stderr()
+        .contains("Cargo.toml: can't tell what format the ROM is in")
+        .unwrap();
+}
+
+#[test]
+fn test_cartridge() {
+    //test that an Octo cartridge is compiled and run with its own options
+    let bin: &str = &format!("{}/target/debug/chip8", get_cwd());
+    assert_cli::Assert::command(&[bin])
+        .with_args(&[
+            "-vvv",
+            "run",
+            "--headless",
+            "--frames",
+            "5",
+            "tests/carts/smile.gif",
+        ])
+        .stdout()
+        .contains("{\"frames\":5,\"pc\":520,")
+        .stderr()
+        .contains("tickrate 20, quirks shift=0 load_store=0 clip=1")
+        .unwrap();
+}
+
+#[test]
+fn test_options() {
+    //test that the options next to a ROM are used, and --options beats them
+    let bin: &str = &format!("{}/target/debug/chip8", get_cwd());
+    let source = env::temp_dir().join("chip8-options.8o");
+    std::fs::write(&source, ": main loop again").unwrap();
+    let options = source.with_extension("json");
+    std::fs::write(&options, r#"{"tickrate": 3, "jumpQuirks": true}"#).unwrap();
+    let args = ["-vvv", "run", "--headless", "--frames", "1"];
+    assert_cli::Assert::command(&[bin])
+        .with_args(&args)
+        .with_args(&[source.to_str().unwrap()])
+        .stderr()
+        .contains("tickrate 3, quirks shift=0 load_store=0 clip=0 jump=1")
+        .unwrap();
+
+    let fast = env::temp_dir().join("chip8-fast.json");
+    std::fs::write(&fast, r#"{"tickrate": 500, "clipQuirks": false}"#).unwrap();
+    assert_cli::Assert::command(&[bin])
+        .with_args(&args)
+        .with_args(&["--options", fast.to_str().unwrap(), "tests/carts/smile.gif"])
+        .stderr()
+        .contains("tickrate 500, quirks shift=0 load_store=0 clip=0")
+        .stderr()
+        .contains("colours #FFCC00 on #996600")
+        .unwrap();
+}
+
+#[test]
+fn test_rom_database() {
+    //test that known ROMs get their settings, and users can add their own
+    let bin: &str = &format!("{}/target/debug/chip8", get_cwd());
+    let config = env::temp_dir().join("chip8-config");
+    let _ = std::fs::remove_dir_all(&config);
+    let args = ["-vvv", "run", "--headless", "--frames", "1"];
+    assert_cli::Assert::command(&[bin])
+        .with_env(assert_cli::Environment::inherit().insert("XDG_CONFIG_HOME", &config))
+        .with_args(&args)
+        .with_args(&["roms/BLITZ"])
+        .stderr()
+        .contains("recognised Blitz by David Winter (CHIP-8)")
+        .stderr()
+        .contains("tickrate 20, quirks shift=1 load_store=0 clip=1")
+        .unwrap();
+
+    std::fs::create_dir_all(config.join("chip8")).unwrap();
+    std::fs::write(
+        config.join("chip8/roms.json"),
+        r#"{"b9272ae1acdaaa79ab649f6b48b72088ca2b1d74":
+            {"title": "Amazing", "quirks": "vip", "tickrate": 9}}"#,
+    )
+    .unwrap();
+    assert_cli::Assert::command(&[bin])
+        .with_env(assert_cli::Environment::inherit().insert("XDG_CONFIG_HOME", &config))
+        .with_args(&args)
+        .with_args(&["roms/MAZE"])
+        .stderr()
+        .contains("settings for Amazing: tickrate 9, quirks shift=0")
+        .unwrap();
+}
+
+#[test]
+fn test_config_file() {
+    //test that config.toml layers under the command line, and --print-config
+    //says where each setting came from
+    let bin: &str = &format!("{}/target/debug/chip8", get_cwd());
+    let config = env::temp_dir().join("chip8-config-toml");
+    let _ = std::fs::remove_dir_all(&config);
+    std::fs::create_dir_all(config.join("chip8")).unwrap();
+    std::fs::write(
+        config.join("chip8/config.toml"),
+        "[defaults]\nscale = 3\n\n[roms.MAZE]\ntickrate = 7\nfill = \"#FFCC00\"\n",
+    )
+    .unwrap();
+    assert_cli::Assert::command(&[bin])
+        .with_env(assert_cli::Environment::inherit().insert("XDG_CONFIG_HOME", &config))
+        .with_args(&["run", "--tickrate", "9", "--print-config", "roms/MAZE"])
+        .stdout()
+        .contains("tickrate = 9 # the command line")
+        .stdout()
+        .contains("scale = 3 # config [defaults]")
+        .stdout()
+        .contains("fill = \"#FFCC00\" # config [roms.MAZE]")
+        .unwrap();
+}
+
+#[test]
+fn test_info() {
+    //test that info identifies a ROM and lists what it uses
+    let bin: &str = &format!("{}/target/debug/chip8", get_cwd());
+    assert_cli::Assert::command(&[bin])
+        .with_args(&["info", "roms/BLINKY"])
+        .stdout()
+        .contains("sha1: d40abc54374e4343639f993e897e00904ddf85d9")
+        .stdout()
+        .contains("database: Blinky by Hans Christian Egeberg (CHIP-8)")
+        .stdout()
+        .contains("extensions: none")
+        .stdout()
+        .contains("FX55 (load_store) at 0x220, 0x226,")
+        .unwrap();
+}
+
+#[test]
+fn test_detect() {
+    //test that detected quirks are recommended and remembered
+    let bin: &str = &format!("{}/target/debug/chip8", get_cwd());
+    let config = env::temp_dir().join("chip8-detect");
+    let _ = std::fs::remove_dir_all(&config);
+    assert_cli::Assert::command(&[bin])
+        .with_env(assert_cli::Environment::inherit().insert("XDG_CONFIG_HOME", &config))
+        .with_args(&["detect", "roms/BLITZ"])
+        .stdout()
+        .contains("recommended: vip (shift=0 load_store=0 clip=1")
+        .unwrap();
+    let saved = std::fs::read_to_string(config.join("chip8/roms.json")).unwrap();
+    assert!(saved.contains("\"title\": \"Blitz\""));
+    assert!(saved.contains("\"quirks\": \"shift=0 load_store=0 clip=1"));
+
+    assert_cli::Assert::command(&[bin])
+        .with_env(assert_cli::Environment::inherit().insert("XDG_CONFIG_HOME", &config))
+        .with_args(&["-vvv", "run", "--quirks", "auto", "--headless", "-f", "1"])
+        .with_args(&["roms/BLITZ"])
+        .stderr()
+        .contains("detected quirks: vip")
+        .unwrap();
+}
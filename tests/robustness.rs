@@ -0,0 +1,122 @@
+//! Random bytes run through everything that takes a ROM or a saved file
+//!
+//! A cut-down, deterministic version of the targets under `fuzz/`, so that
+//! `cargo test` catches panics and broken invariants without a fuzzer. Set
+//! `CHIP8_ROBUSTNESS_SEED` to vary the cases from the fixed default.
+
+extern crate chip8_tismith;
+extern crate rand;
+
+use chip8_tismith::cfg::ControlFlowGraph;
+use chip8_tismith::cpu::{Cpu, MAX_ROM_SIZE};
+use chip8_tismith::disasm::{self, Syntax};
+use chip8_tismith::fault::FaultPolicy;
+use chip8_tismith::instruction::Instruction;
+use chip8_tismith::movie::{self, Movie};
+use chip8_tismith::quirks::Quirks;
+use chip8_tismith::rom;
+use rand::prng::XorShiftRng;
+use rand::{Rng, SeedableRng};
+use std::env;
+
+const ROMS: usize = 300;
+const TICKS: usize = 2_000;
+
+const PROFILES: &[&str] = &["default", "vip", "schip", "octo"];
+
+fn rng() -> XorShiftRng {
+    let seed: u64 = env::var("CHIP8_ROBUSTNESS_SEED")
+        .ok()
+        .and_then(|seed| seed.parse().ok())
+        .unwrap_or(0xC8);
+    let mut bytes = [0; 16];
+    bytes[..8].copy_from_slice(&seed.to_le_bytes());
+    bytes[8] = 1;
+    XorShiftRng::from_seed(bytes)
+}
+
+///random bytes, sometimes more than fits in memory
+fn random_rom(rng: &mut XorShiftRng) -> Vec<u8> {
+    let mut rom = vec![0; rng.gen_range(0, 4200)];
+    rng.fill(&mut rom[..]);
+    rom
+}
+
+#[test]
+fn test_random_roms_keep_invariants() {
+    let mut rng = rng();
+    for n in 0..ROMS {
+        let rom = random_rom(&mut rng);
+        let quirks: Quirks = PROFILES[n % PROFILES.len()].parse().unwrap();
+        let mut cpu = Cpu::with_quirks(quirks);
+        //never halt, so the whole of the ROM gets a go
+        cpu.set_fault_policy(FaultPolicy::Ignore);
+        cpu.seed(rng.gen::<u64>() | 1);
+        if cpu.load_rom(&rom).is_err() {
+            assert!(rom.len() > MAX_ROM_SIZE);
+            continue;
+        }
+        for tick in 0..TICKS {
+            if tick % 100 == 0 {
+                cpu.set_keys(rng.gen());
+                cpu.tick_timers();
+            }
+            cpu.tick();
+            let context = format!("rom {} ({} bytes) tick {}", n, rom.len(), tick);
+            assert!(cpu.pc() < 0x1000, "pc 0x{:X} after {}", cpu.pc(), context);
+            assert!(cpu.i() < 0x1000, "i 0x{:X} after {}", cpu.i(), context);
+            assert!(
+                cpu.stack().len() <= usize::from(cpu.quirks().stack_depth),
+                "stack too deep after {}",
+                context
+            );
+            assert!(cpu.stack().iter().all(|&address| address < 0x1000));
+        }
+    }
+}
+
+#[test]
+fn test_random_roms_decode() {
+    let mut rng = rng();
+    for _ in 0..ROMS / 10 {
+        let rom = random_rom(&mut rng);
+        for opcode in rom.chunks(2).filter(|pair| pair.len() == 2) {
+            let opcode = u16::from(opcode[0]) << 8 | u16::from(opcode[1]);
+            let instruction = Instruction::decode(opcode);
+            assert_eq!(Instruction::decode(instruction.encode()), instruction);
+        }
+        disasm::disassemble(&rom, Syntax::Plain);
+        disasm::disassemble(&rom, Syntax::Octo);
+        ControlFlowGraph::new(&rom).to_dot();
+    }
+}
+
+#[test]
+fn test_random_files_load() {
+    let mut rng = rng();
+    let state = movie::save_state(&Cpu::new(), 3);
+    for _ in 0..ROMS {
+        //mangled save states, cut short or with bytes changed
+        let mut bytes = state[..rng.gen_range(0, state.len() + 1)].to_vec();
+        for _ in 0..rng.gen_range(0, 4) {
+            if !bytes.is_empty() {
+                let at = rng.gen_range(0, bytes.len());
+                bytes[at] = rng.gen();
+            }
+        }
+        if let Ok((cpu, _)) = movie::load_state(&bytes) {
+            assert!(cpu.pc() < 0x1000 && cpu.i() < 0x1000);
+        }
+
+        let text: String = (0..rng.gen_range(0, 200))
+            .map(|_| {
+                *rng.choose(b"chip8-movie 1\nromseedquirksframes 0123456789abcdefABCDEF=\n ")
+                    .unwrap() as char
+            })
+            .collect();
+        let _ = Movie::parse(&text);
+        if let Ok(rom) = rom::decode(text.as_bytes(), "random") {
+            assert!(rom.len() <= MAX_ROM_SIZE);
+        }
+    }
+}
@@ -0,0 +1,35 @@
+//! Runs the self-checking ROMs in `tests/roms`
+//!
+//! Each is an Octo source that asserts its own results through semihosting
+//! and exits with code 0 when they all hold. They run with Octo's quirks, as
+//! `chip8 run` runs Octo sources.
+
+extern crate chip8_tismith;
+
+use chip8_tismith::cpu::Cpu;
+use chip8_tismith::headless;
+use chip8_tismith::octo;
+use chip8_tismith::quirks::Quirks;
+use std::fs;
+
+///long enough for any of them, too short to hang the suite
+const FRAMES: u32 = 600;
+
+#[test]
+fn test_roms() {
+    let mut failures = Vec::new();
+    for entry in fs::read_dir("tests/roms").unwrap() {
+        let path = entry.unwrap().path();
+        let assembly = octo::compile_file(&path).unwrap();
+        let mut cpu = Cpu::with_quirks(Quirks::octo());
+        cpu.enable_semihosting();
+        cpu.load_rom(&assembly.rom).unwrap();
+        headless::run(&mut cpu, FRAMES, &[], |cpu| cpu.tick());
+        match cpu.exit_code() {
+            Some(0) => {}
+            Some(code) => failures.push(format!("{} exited with {}", path.display(), code)),
+            None => failures.push(format!("{} didn't exit", path.display())),
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
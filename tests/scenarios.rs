@@ -0,0 +1,19 @@
+//! Runs the scenario files in `tests/scenarios`
+
+extern crate chip8_tismith;
+
+use chip8_tismith::scenario::Scenario;
+use std::fs;
+
+#[test]
+fn test_scenarios() {
+    let mut failures = Vec::new();
+    for entry in fs::read_dir("tests/scenarios").unwrap() {
+        let path = entry.unwrap().path();
+        let scenario = Scenario::from_file(&path).unwrap();
+        if let Err(failure) = scenario.check() {
+            failures.push(failure.to_string());
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n\n"));
+}